@@ -1,15 +1,24 @@
 use anyhow::{anyhow, Context as _};
+use geekytray_shell::dbus;
 use geekytray_shell::event::{
-    ControlFlow, Event, EventLoop, EventLoopContext, KeyState, Modifiers,
+    ControlFlow, Event, EventLoop, EventLoopContext, KeyState, Keysym, Modifiers, MouseButton,
+    ScrollDirection, TimerId,
 };
 use geekytray_shell::geometrics::Size;
-use geekytray_shell::window::Window;
+use geekytray_shell::window::{Effect, Window};
 use geekytray_shell::xkb;
+use geekytray_shell::xkbcommon_sys as ffi;
+use nix::sys::signal::Signal;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
 use std::mem::ManuallyDrop;
+use std::path::PathBuf;
 use std::process;
 use std::rc::Rc;
+use std::time::Duration;
 use x11rb::connection::Connection;
-use x11rb::errors::ReplyError;
+use x11rb::errors::{ReplyError, ReplyOrIdError};
 use x11rb::protocol;
 use x11rb::protocol::damage::ConnectionExt as _;
 use x11rb::protocol::xkb::ConnectionExt as _;
@@ -19,24 +28,48 @@ use x11rb::wrapper::ConnectionExt as _;
 use x11rb::xcb_ffi::XCBConnection;
 
 use crate::command::Command;
-use crate::config::{Config, WindowConfig};
-use crate::hotkey::HotkeyInterpreter;
+use crate::config::{
+    Config, FilterAction, FilterConfig, MessageConfig, MessageSink, Orientation, SelectionConfig,
+    StatusNotifierConfig, TrayColorsConfig, WindowConfig,
+};
+use crate::hotkey::{Hotkey, HotkeyInterpreter};
+use crate::notification_client::NotificationClient;
+use crate::rule::Rule;
+use crate::state::{get_state_path, State};
+use crate::status_notifier_host::{StatusNotifierEvent, StatusNotifierHost};
+use crate::status_notifier_watcher::StatusNotifierWatcher;
 use crate::tray_container::TrayContainer;
+use crate::tray_item::{TrayItemIcon, TrayItemId};
 use crate::tray_manager::{SystemTrayColors, SystemTrayOrientation, TrayEvent, TrayManager};
+use crate::xembed::XEmbedFocus;
 
 pub struct App {
     connection: Rc<XCBConnection>,
     screen_num: usize,
     atoms: Atoms,
-    window: ManuallyDrop<Window<TrayContainer>>,
+    windows: ManuallyDrop<Vec<Window<TrayContainer>>>,
     window_config: WindowConfig,
-    tray_manager: ManuallyDrop<TrayManager<XCBConnection>>,
+    config_path: Option<PathBuf>,
+    state_path: Option<PathBuf>,
+    selection_config: SelectionConfig,
+    status_notifier_config: StatusNotifierConfig,
+    message_config: MessageConfig,
+    selection_timer: Option<TimerId>,
+    tray_managers: ManuallyDrop<Vec<TrayManager<XCBConnection>>>,
     keyboard_state: xkb::State,
     hotkey_interpreter: HotkeyInterpreter,
+    filter_config: FilterConfig,
+    captured_keys: HashSet<xproto::Keycode>,
+    active_window: Option<usize>,
+    dbus_connection: Option<Rc<dbus::Connection>>,
+    status_notifier_watcher: Option<StatusNotifierWatcher>,
+    status_notifier_host: Option<StatusNotifierHost>,
+    notification_client: Option<NotificationClient>,
+    message_timers: HashMap<TimerId, (usize, xproto::Window, u32)>,
 }
 
 impl App {
-    pub fn new(config: Config) -> anyhow::Result<Self> {
+    pub fn new(config: Config, config_path: Option<PathBuf>) -> anyhow::Result<Self> {
         let (connection, screen_num) =
             XCBConnection::connect(None).context("connect to X server")?;
         let connection = Rc::new(connection);
@@ -45,55 +78,108 @@ impl App {
 
         setup_damage_extension(&connection)?;
 
-        let screen = &connection.setup().roots[screen_num];
-        let (visual_id, depth) =
-            match find_visual_from_screen(screen, 32, xproto::VisualClass::TRUE_COLOR) {
-                Some(visual) => (visual.visual_id, 32),
-                None => (screen.root_visual, screen.root_depth),
+        let atoms = Atoms::new(connection.as_ref())?
+            .reply()
+            .context("intern atoms")?;
+
+        let dbus_connection =
+            if config.status_notifier.enabled || config.message.sink == MessageSink::Notification {
+                match dbus::Connection::session() {
+                    Ok(dbus_connection) => Some(Rc::new(dbus_connection)),
+                    Err(error) => {
+                        log::warn!("Failed to connect to the session bus: {}", error);
+                        None
+                    }
+                }
+            } else {
+                None
             };
-        let colormap = connection.generate_id()?;
 
-        connection
-            .create_colormap(
-                xproto::ColormapAlloc::NONE,
-                colormap,
-                screen.root,
+        let (status_notifier_watcher, status_notifier_host) = match &dbus_connection {
+            Some(dbus_connection) if config.status_notifier.enabled => {
+                match setup_status_notifier(
+                    dbus_connection.clone(),
+                    &config.status_notifier,
+                    config.ui.icon_size as u32,
+                ) {
+                    Ok((status_notifier_watcher, status_notifier_host)) => {
+                        (status_notifier_watcher, Some(status_notifier_host))
+                    }
+                    Err(error) => {
+                        log::warn!("StatusNotifierItem support is disabled: {}", error);
+                        (None, None)
+                    }
+                }
+            }
+            _ => (None, None),
+        };
+
+        let notification_client = match &dbus_connection {
+            Some(dbus_connection) if config.message.sink == MessageSink::Notification => {
+                match NotificationClient::new(dbus_connection.clone()) {
+                    Ok(notification_client) => Some(notification_client),
+                    Err(error) => {
+                        log::warn!("Failed to forward messages to notifications: {}", error);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let rule_hotkeys = rule_hotkeys(&config.rules);
+
+        let state_path = get_state_path();
+        let state = match &state_path {
+            Some(state_path) => State::load(state_path).unwrap_or_else(|error| {
+                log::warn!("Failed to load state: {:?}", error);
+                State::default()
+            }),
+            None => State::default(),
+        };
+
+        // Each screen has its own tray manager and window, since icons can
+        // not be reparented and composited across screens.
+        let orientation = system_tray_orientation(config.ui.orientation);
+        let ui_config = Rc::new(config.ui);
+        let mut tray_managers = Vec::new();
+        let mut windows = Vec::new();
+        for tray_screen_num in 0..connection.setup().roots.len() {
+            let (visual_id, depth, colormap) = create_colormap(&connection, tray_screen_num)?;
+
+            tray_managers.push(TrayManager::new(
+                connection.clone(),
+                tray_screen_num,
                 visual_id,
-            )?
-            .check()?;
+                orientation,
+                system_tray_colors(&ui_config.tray_colors),
+            )?);
 
-        let atoms = Atoms::new(connection.as_ref())?
-            .reply()
-            .context("intern atoms")?;
+            let mut tray_container = TrayContainer::new(
+                ui_config.clone(),
+                config.rules.clone(),
+                config.window.max_height,
+            );
+            tray_container.restore_state(state.clone());
 
-        let tray_manager = TrayManager::new(
-            connection.clone(),
-            screen_num,
-            visual_id,
-            SystemTrayOrientation::VERTICAL,
-            SystemTrayColors::new(
-                config.ui.item_foreground,
-                config.ui.item_foreground,
-                config.ui.item_foreground,
-                config.ui.item_foreground,
-            ),
-        )?;
+            let window = Window::new(
+                tray_container,
+                connection.clone(),
+                tray_screen_num,
+                depth,
+                visual_id,
+                colormap,
+                Size {
+                    width: config.window.width,
+                    height: 0.0,
+                },
+            )
+            .context("create window")?;
 
-        let window = Window::new(
-            TrayContainer::new(Rc::new(config.ui)),
-            connection.clone(),
-            screen_num,
-            depth,
-            visual_id,
-            colormap,
-            Size {
-                width: config.window.width,
-                height: 0.0,
-            },
-        )
-        .context("create window")?;
+            configure_window(&connection, window.id(), &config.window, &atoms)?;
 
-        configure_window(&connection, window.id(), &config.window, &atoms)?;
+            windows.push(window);
+        }
 
         let keyboard_state = {
             let context = xkb::Context::new();
@@ -104,68 +190,337 @@ impl App {
             xkb::State::from_keymap(keymap)
         };
 
-        for key in &config.global_hotkeys {
-            let keycode = keyboard_state
-                .lookup_keycode(key.keysym())
-                .context("lookup keycode")?;
-            grab_key(&connection, screen_num, keycode, key.modifiers()).context("grab_key")?;
-        }
+        grab_global_hotkeys(&connection, &keyboard_state, &config.global_hotkeys)?;
 
         let all_hotkeys = config
             .hotkeys
             .into_iter()
-            .chain(config.global_hotkeys.into_iter());
+            .chain(config.global_hotkeys.into_iter())
+            .chain(rule_hotkeys);
         let hotkey_interpreter = HotkeyInterpreter::new(all_hotkeys);
 
         Ok(Self {
             connection,
             screen_num,
             atoms,
-            window: ManuallyDrop::new(window),
+            windows: ManuallyDrop::new(windows),
             window_config: config.window,
-            tray_manager: ManuallyDrop::new(tray_manager),
+            config_path,
+            state_path,
+            selection_config: config.selection,
+            status_notifier_config: config.status_notifier,
+            message_config: config.message,
+            selection_timer: None,
+            tray_managers: ManuallyDrop::new(tray_managers),
             keyboard_state,
             hotkey_interpreter,
+            filter_config: config.filter,
+            captured_keys: HashSet::new(),
+            active_window: None,
+            dbus_connection,
+            status_notifier_watcher,
+            status_notifier_host,
+            notification_client,
+            message_timers: HashMap::new(),
         })
     }
 
+    pub fn reload_config(
+        &mut self,
+        config: Config,
+        context: &mut EventLoopContext,
+    ) -> anyhow::Result<()> {
+        // These sections are only read on startup.
+        let restart_sections = [
+            ("selection", config.selection != self.selection_config),
+            (
+                "status_notifier",
+                config.status_notifier != self.status_notifier_config,
+            ),
+            ("message", config.message != self.message_config),
+        ];
+        for (section, _) in restart_sections.iter().filter(|(_, changed)| *changed) {
+            log::warn!(
+                "The changes in `{}` take effect after geekytray is restarted",
+                section
+            );
+        }
+
+        log::set_max_level(config.log_level.into());
+
+        let colors = system_tray_colors(&config.ui.tray_colors);
+        let orientation = system_tray_orientation(config.ui.orientation);
+        for tray_manager in self.tray_managers.iter() {
+            tray_manager
+                .set_colors(&colors)
+                .context("update tray colors")?;
+            tray_manager
+                .set_orientation(orientation)
+                .context("update tray orientation")?;
+        }
+
+        if let Some(status_notifier_host) = &mut self.status_notifier_host {
+            status_notifier_host
+                .set_icon_size(config.ui.icon_size as u32)
+                .context("update icon size")?;
+        }
+
+        for window in self.windows.iter_mut() {
+            configure_window(&self.connection, window.id(), &config.window, &self.atoms)?;
+            let effect = window.widget_mut().set_max_height(config.window.max_height);
+            window.apply_effect(effect, context)?;
+        }
+        self.window_config = config.window;
+
+        grab_global_hotkeys(
+            &self.connection,
+            &self.keyboard_state,
+            &config.global_hotkeys,
+        )?;
+
+        let all_hotkeys = config
+            .hotkeys
+            .into_iter()
+            .chain(config.global_hotkeys)
+            .chain(rule_hotkeys(&config.rules));
+        self.hotkey_interpreter = HotkeyInterpreter::new(all_hotkeys);
+        self.filter_config = config.filter;
+
+        let ui_config = Rc::new(config.ui);
+        for window in self.windows.iter_mut() {
+            let effect = window.widget_mut().set_config(ui_config.clone());
+            window.apply_effect(effect, context)?;
+
+            let effect = window.widget_mut().set_rules(config.rules.clone());
+            window.apply_effect(effect, context)?;
+        }
+
+        Ok(())
+    }
+
     pub fn run(&mut self) -> anyhow::Result<()> {
         let mut event_loop =
             EventLoop::new(self.connection.clone()).context("create event loop")?;
 
-        self.tray_manager
-            .acquire_tray_selection()
-            .context("acquire tray selection")?;
+        event_loop
+            .watch_signals(&[Signal::SIGTERM, Signal::SIGQUIT, Signal::SIGHUP])
+            .context("watch signals")?;
+
+        for tray_manager in self.tray_managers.iter_mut() {
+            tray_manager
+                .acquire_tray_selection(self.selection_config.policy)
+                .context("acquire tray selection")?;
+            if tray_manager.is_unmanaged() {
+                return Err(SelectionError::AlreadyOwned(tray_manager.screen_num()).into());
+            }
+        }
+
+        if self.selection_config.timeout > 0.0
+            && self
+                .tray_managers
+                .iter()
+                .any(|tray_manager| tray_manager.is_pending())
+        {
+            let timer_id = event_loop
+                .context_mut()
+                .request_timeout(Duration::from_secs_f64(self.selection_config.timeout))
+                .context("request timeout for tray selection")?;
+            self.selection_timer = Some(timer_id);
+        }
+
+        if let Some(dbus_connection) = &self.dbus_connection {
+            event_loop
+                .watch_dbus_connection(dbus_connection.clone())
+                .context("watch D-Bus connection")?;
+        }
+
+        if let Some(status_notifier_host) = &mut self.status_notifier_host {
+            if let Err(error) = status_notifier_host.register_host() {
+                log::warn!("Failed to register StatusNotifierHost: {}", error);
+            }
+        }
 
         event_loop.run(|event, context, control_flow| {
-            self.window.process_event(&event, context, control_flow)?;
+            for window in self.windows.iter_mut() {
+                window.process_event(&event, context, control_flow)?;
+            }
 
             match event {
                 Event::X11Event(event) => {
-                    match self.tray_manager.process_event(&event, self.window.id()) {
-                        Ok(Some(event)) => {
-                            self.on_tray_event(event, context, control_flow)?;
-                        }
-                        Ok(None) => {}
-                        Err(error) => {
-                            log::warn!("Error while processing event by TrayManager: {}", error);
+                    let mut tray_events = Vec::new();
+                    for (index, (tray_manager, window)) in self
+                        .tray_managers
+                        .iter_mut()
+                        .zip(self.windows.iter())
+                        .enumerate()
+                    {
+                        match tray_manager.process_event(&event, window.id()) {
+                            Ok(Some(event)) => tray_events.push((index, event)),
+                            Ok(None) => {}
+                            Err(error) => {
+                                log::warn!(
+                                    "Error while processing event by TrayManager: {}",
+                                    error
+                                );
+                            }
                         }
                     }
+                    for (index, tray_event) in tray_events {
+                        self.on_tray_event(index, tray_event, context, control_flow)?;
+                    }
                     self.on_x11_event(&event, context, control_flow)?;
+                    self.update_icon_focus(XEmbedFocus::Current)?;
+                    self.send_menu_requests();
+                    self.save_state_if_changed();
                     Ok(())
                 }
-                Event::Timer(_timer) => Ok(()),
-                Event::Signal(_signal) => {
-                    *control_flow = ControlFlow::Break;
+                Event::DBusMessage(message) => {
+                    let window = self.default_window_mut();
+                    let effect = window.widget_mut().update_menu(&message);
+                    window.apply_effect(effect, context)?;
+                    if let Some(status_notifier_watcher) = &mut self.status_notifier_watcher {
+                        if let Err(error) = status_notifier_watcher.process_message(&message) {
+                            log::warn!(
+                                "Error while processing message by StatusNotifierWatcher: {}",
+                                error
+                            );
+                        }
+                    }
+                    let events = match &mut self.status_notifier_host {
+                        Some(status_notifier_host) => {
+                            match status_notifier_host.process_message(&message) {
+                                Ok(events) => events,
+                                Err(error) => {
+                                    log::warn!(
+                                        "Error while processing message by StatusNotifierHost: {}",
+                                        error
+                                    );
+                                    Vec::new()
+                                }
+                            }
+                        }
+                        None => Vec::new(),
+                    };
+                    for event in events {
+                        self.on_status_notifier_event(event, context)?;
+                    }
+                    self.send_menu_requests();
+                    self.save_state_if_changed();
+                    if let Some(notification_client) = &mut self.notification_client {
+                        if let Err(error) = notification_client.process_message(&message) {
+                            log::warn!(
+                                "Error while processing message for notification: {}",
+                                error
+                            );
+                        }
+                    }
+                    Ok(())
+                }
+                Event::Timer(timer) if self.selection_timer == Some(timer.id) => {
+                    self.selection_timer = None;
+                    if let Some(tray_manager) = self
+                        .tray_managers
+                        .iter()
+                        .find(|tray_manager| tray_manager.is_pending())
+                    {
+                        return Err(SelectionError::Timeout(tray_manager.screen_num()).into());
+                    }
+                    Ok(())
+                }
+                Event::Timer(timer) => {
+                    if let Some((index, icon_window, message_id)) =
+                        self.message_timers.remove(&timer.id)
+                    {
+                        let window = &mut self.windows[index];
+                        let effect = window
+                            .widget_mut()
+                            .remove_message(&TrayItemId::XEmbed(icon_window), message_id);
+                        window.apply_effect(effect, context)?;
+                    }
+                    Ok(())
+                }
+                Event::Signal(signal) => {
+                    match Signal::try_from(signal.ssi_signo as i32) {
+                        Ok(Signal::SIGHUP) => match &self.config_path {
+                            Some(config_path) => {
+                                log::info!("Reload config from {:?}", config_path);
+                                match Config::load(config_path) {
+                                    Ok(config) => self.reload_config(config, context)?,
+                                    Err(error) => {
+                                        log::error!("Failed to reload config: {:?}", error)
+                                    }
+                                }
+                            }
+                            None => log::warn!("No config file to reload"),
+                        },
+                        _ => {
+                            *control_flow = ControlFlow::Break;
+                        }
+                    }
                     Ok(())
                 }
                 Event::NextTick => Ok(()),
             }
         })?;
 
+        if let Some(state_path) = &self.state_path {
+            if let Err(error) = self.state().save(state_path) {
+                log::warn!("Failed to save state: {:?}", error);
+            }
+        }
+
         Ok(())
     }
 
+    fn save_state_if_changed(&mut self) {
+        let mut is_changed = false;
+        for window in self.windows.iter_mut() {
+            is_changed |= window.widget_mut().take_item_order_changed();
+        }
+        if !is_changed {
+            return;
+        }
+        if let Some(state_path) = &self.state_path {
+            if let Err(error) = self.state().save(state_path) {
+                log::warn!("Failed to save state: {:?}", error);
+            }
+        }
+    }
+
+    // Merges the states of all windows. The selection is taken from the
+    // default window, which also has StatusNotifierItems.
+    fn state(&self) -> State {
+        let mut state = self.default_window().widget().state();
+        for window in self.windows.iter() {
+            for key in window.widget().state().items {
+                if !state.items.contains(&key) {
+                    state.items.push(key);
+                }
+            }
+        }
+        state
+    }
+
+    fn default_window(&self) -> &Window<TrayContainer> {
+        &self.windows[self.screen_num]
+    }
+
+    fn default_window_mut(&mut self) -> &mut Window<TrayContainer> {
+        &mut self.windows[self.screen_num]
+    }
+
+    fn window_index(&self, window: xproto::Window) -> Option<usize> {
+        self.windows.iter().position(|w| w.id() == window)
+    }
+
+    fn screen_index(&self, root: xproto::Window) -> Option<usize> {
+        self.connection
+            .setup()
+            .roots
+            .iter()
+            .position(|screen| screen.root == root)
+    }
+
     fn on_x11_event(
         &mut self,
         event: &protocol::Event,
@@ -175,44 +530,108 @@ impl App {
         use protocol::Event::*;
 
         match event {
+            FocusIn(event) if event.detail != xproto::NotifyDetail::POINTER => {
+                if let Some(index) = self.window_index(event.event) {
+                    if self.active_window != Some(index) {
+                        if let Some(active_index) = self.active_window {
+                            self.activate_window(active_index, false)?;
+                        }
+                        self.active_window = Some(index);
+                        self.activate_window(index, true)?;
+                    }
+                }
+            }
             FocusOut(event) => {
+                let index = match self.window_index(event.event) {
+                    Some(index) => index,
+                    None => return Ok(()),
+                };
+                if event.detail != xproto::NotifyDetail::INFERIOR
+                    && event.detail != xproto::NotifyDetail::POINTER
+                    && self.active_window == Some(index)
+                {
+                    self.active_window = None;
+                    self.activate_window(index, false)?;
+                }
                 if self.window_config.auto_close
                     && event.mode == xproto::NotifyMode::NORMAL
                     && event.detail == xproto::NotifyDetail::NONLINEAR
-                    && event.event == self.window.id()
                 {
-                    self.window.hide().context("hide window")?;
+                    self.windows[index].hide().context("hide window")?;
                 }
             }
             KeyPress(event) => {
                 self.keyboard_state
                     .update_key(event.detail as u32, KeyState::Down);
+                let index = match self.screen_index(event.root) {
+                    Some(index) => index,
+                    None => return Ok(()),
+                };
+                let is_captured = if self.windows[index].widget().is_hinting() {
+                    self.process_hint_key(index, event, context)?
+                } else if self.windows[index].widget().is_filtering() {
+                    self.process_filter_key(index, event, context)?
+                } else {
+                    false
+                };
+                if is_captured {
+                    self.captured_keys.insert(event.detail);
+                    return Ok(());
+                }
+                let keysym = self.keyboard_state.get_keysym(event.detail as u32);
+                let modifiers = self.keyboard_state.get_modifiers();
+                if self.find_accelerator(keysym, modifiers).is_none()
+                    && self.hotkey_interpreter.eval(keysym, modifiers).is_empty()
+                {
+                    self.forward_key_event(event)?;
+                }
             }
             KeyRelease(event) => {
                 self.keyboard_state
                     .update_key(event.detail as u32, KeyState::Up);
+                if self.captured_keys.remove(&event.detail) {
+                    return Ok(());
+                }
                 let keysym = self.keyboard_state.get_keysym(event.detail as u32);
                 let modifiers = self.keyboard_state.get_modifiers();
                 let commands = self.hotkey_interpreter.eval(keysym, modifiers);
-                for command in commands {
-                    run_command(&mut self.window, command, context)?;
+                if let Some((tray_manager, icon_window, id)) =
+                    self.find_accelerator(keysym, modifiers)
+                {
+                    tray_manager
+                        .activate_accelerator(icon_window, id, !commands.is_empty())
+                        .context("activate accelerator")?;
+                    return Ok(());
+                }
+                if commands.is_empty() {
+                    self.forward_key_event(event)?;
+                }
+                if let Some(index) = self.screen_index(event.root) {
+                    for command in commands {
+                        run_command(&mut self.windows[index], command, event.time, context)?;
+                    }
                 }
             }
             LeaveNotify(event) => {
                 if self.window_config.auto_close
                     && event.mode == xproto::NotifyMode::NORMAL
                     && event.detail == xproto::NotifyDetail::ANCESTOR
-                    && event.event == self.window.id()
                 {
-                    self.window.hide().context("hide window")?;
+                    if let Some(index) = self.window_index(event.event) {
+                        self.windows[index].hide().context("hide window")?;
+                    }
                 }
             }
             ClientMessage(event)
                 if event.type_ == self.atoms.WM_PROTOCOLS && event.format == 32 =>
             {
+                let index = match self.window_index(event.window) {
+                    Some(index) => index,
+                    None => return Ok(()),
+                };
                 let [protocol, ..] = event.data.as_data32();
                 if protocol == self.atoms._NET_WM_PING {
-                    let screen = &self.connection.setup().roots[self.screen_num];
+                    let screen = &self.connection.setup().roots[index];
                     let mut reply_event = event.clone();
                     reply_event.window = screen.root;
                     self.connection
@@ -225,9 +644,9 @@ impl App {
                         )
                         .context("reply _NET_WM_PING")?;
                 } else if protocol == self.atoms._NET_WM_SYNC_REQUEST {
-                    self.window.request_redraw();
+                    self.windows[index].request_redraw();
                 } else if protocol == self.atoms.WM_DELETE_WINDOW {
-                    self.window.hide().context("hide window")?;
+                    self.windows[index].hide().context("hide window")?;
                 }
             }
             XkbStateNotify(event) => self.keyboard_state.update_mask(event),
@@ -239,6 +658,7 @@ impl App {
 
     fn on_tray_event(
         &mut self,
+        index: usize,
         event: TrayEvent,
         context: &mut EventLoopContext,
         control_flow: &mut ControlFlow,
@@ -249,34 +669,335 @@ impl App {
                     "Tray message from window {}: {}",
                     icon_window,
                     message.as_str()
-                )
+                );
+                if let Some(notification_client) = &mut self.notification_client {
+                    let title = self
+                        .tray_managers
+                        .iter()
+                        .find_map(|tray_manager| tray_manager.find_icon(icon_window))
+                        .map_or("", |icon| icon.title());
+                    if let Err(error) = notification_client.notify(
+                        icon_window,
+                        message.id(),
+                        title,
+                        message.as_str(),
+                        message.timeout(),
+                    ) {
+                        log::warn!("Failed to send notification: {}", error);
+                    }
+                } else {
+                    let window = &mut self.windows[index];
+                    let effect = window.widget_mut().show_message(
+                        &TrayItemId::XEmbed(icon_window),
+                        message.id(),
+                        message.as_str().to_owned(),
+                    );
+                    window.apply_effect(effect, context)?;
+                    if message.timeout() > Duration::ZERO {
+                        let timer_id = context
+                            .request_timeout(message.timeout())
+                            .context("request timeout for tray message")?;
+                        self.message_timers
+                            .insert(timer_id, (index, icon_window, message.id()));
+                    }
+                }
+            }
+            TrayEvent::MessageCanceled(icon_window, message_id) => {
+                if let Some(notification_client) = &mut self.notification_client {
+                    if let Err(error) = notification_client.close(icon_window, message_id) {
+                        log::warn!("Failed to close notification: {}", error);
+                    }
+                } else {
+                    let window = &mut self.windows[index];
+                    let effect = window
+                        .widget_mut()
+                        .remove_message(&TrayItemId::XEmbed(icon_window), message_id);
+                    window.apply_effect(effect, context)?;
+                }
             }
             TrayEvent::TrayIconAdded(icon) => {
-                let effect = self.window.widget_mut().add_tray_item(icon);
-                self.window.apply_effect(effect, context)?;
+                let window = &mut self.windows[index];
+                let effect = window.widget_mut().add_tray_item(icon);
+                window.apply_effect(effect, context)?;
             }
             TrayEvent::TrayIconUpdated(icon) => {
-                let effect = self.window.widget_mut().update_tray_item(icon);
-                self.window.apply_effect(effect, context)?;
+                let window = &mut self.windows[index];
+                let effect = window.widget_mut().update_tray_item(icon);
+                window.apply_effect(effect, context)?;
             }
             TrayEvent::TrayIconRemoved(icon) => {
-                let effect = self.window.widget_mut().remove_tray_item(icon.window());
-                self.window.apply_effect(effect, context)?;
+                let window = &mut self.windows[index];
+                let effect = window
+                    .widget_mut()
+                    .remove_tray_item(&TrayItemId::XEmbed(icon.window()));
+                window.apply_effect(effect, context)?;
+            }
+            // The icon is given the focus by `update_icon_focus()` once it is
+            // selected and the window is active.
+            TrayEvent::FocusRequested(icon_window) => {
+                let window = &mut self.windows[index];
+                let effect = window
+                    .widget_mut()
+                    .select_item_by_id(&TrayItemId::XEmbed(icon_window));
+                window.apply_effect(effect, context)?;
+                if window.is_mapped() {
+                    self.connection
+                        .set_input_focus(
+                            xproto::InputFocus::PARENT,
+                            window.id(),
+                            x11rb::CURRENT_TIME,
+                        )?
+                        .check()?;
+                }
+            }
+            TrayEvent::FocusNext => {
+                let window = &mut self.windows[index];
+                let effect = window.widget_mut().select_next_item();
+                window.apply_effect(effect, context)?;
+                self.update_icon_focus(XEmbedFocus::First)?;
+            }
+            TrayEvent::FocusPrev => {
+                let window = &mut self.windows[index];
+                let effect = window.widget_mut().select_previous_item();
+                window.apply_effect(effect, context)?;
+                self.update_icon_focus(XEmbedFocus::Last)?;
+            }
+            TrayEvent::AcceleratorRegistered(icon_window, accelerator) => {
+                if !self
+                    .hotkey_interpreter
+                    .eval(accelerator.keysym, accelerator.modifiers)
+                    .is_empty()
+                {
+                    log::warn!(
+                        "Accelerator ({:?} + {}) of tray icon window `{}` overrides the hotkey",
+                        accelerator.modifiers,
+                        accelerator.keysym,
+                        icon_window
+                    );
+                }
             }
             TrayEvent::SelectionCleared => {
-                *control_flow = ControlFlow::Break;
+                if self
+                    .tray_managers
+                    .iter()
+                    .all(|tray_manager| tray_manager.is_unmanaged())
+                {
+                    *control_flow = ControlFlow::Break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_hint_key(
+        &mut self,
+        index: usize,
+        event: &xproto::KeyPressEvent,
+        context: &mut EventLoopContext,
+    ) -> anyhow::Result<bool> {
+        let keysym = self.keyboard_state.get_keysym(event.detail as u32);
+        let modifiers = self.keyboard_state.get_modifiers().without_locks();
+        let effect =
+            if keysym == Keysym::from(ffi::XKB_KEY_BackSpace) && modifiers == Modifiers::NONE {
+                match self.windows[index].widget_mut().pop_hint_char() {
+                    Effect::None => self.windows[index].widget_mut().exit_hint(),
+                    effect => effect,
+                }
+            } else if keysym == Keysym::from(ffi::XKB_KEY_Escape) && modifiers == Modifiers::NONE {
+                self.windows[index].widget_mut().exit_hint()
+            } else if !modifiers.control && !modifiers.alt && !modifiers.super_ {
+                let text = self.keyboard_state.get_utf8(event.detail as u32);
+                if text.is_empty() || text.chars().any(char::is_control) {
+                    return Ok(false);
+                }
+                self.windows[index]
+                    .widget_mut()
+                    .push_hint_text(&text, event.time)
+            } else {
+                return Ok(false);
+            };
+        self.windows[index].apply_effect(effect, context)?;
+        Ok(true)
+    }
+
+    fn process_filter_key(
+        &mut self,
+        index: usize,
+        event: &xproto::KeyPressEvent,
+        context: &mut EventLoopContext,
+    ) -> anyhow::Result<bool> {
+        let keysym = self.keyboard_state.get_keysym(event.detail as u32);
+        let modifiers = self.keyboard_state.get_modifiers().without_locks();
+        let effect = if keysym == Keysym::from(ffi::XKB_KEY_Return) && modifiers == Modifiers::NONE
+        {
+            self.windows[index]
+                .widget_mut()
+                .click_first_filtered_item(MouseButton::Left, event.time)
+        } else if keysym == Keysym::from(ffi::XKB_KEY_BackSpace) && modifiers == Modifiers::NONE {
+            if self.windows[index].widget().filter_query() == Some("") {
+                return self
+                    .run_filter_action(index, self.filter_config.backspace_on_empty, context)
+                    .map(|_| true);
+            }
+            self.windows[index].widget_mut().pop_filter_char()
+        } else if keysym == Keysym::from(ffi::XKB_KEY_Escape) && modifiers == Modifiers::NONE {
+            return self
+                .run_filter_action(index, self.filter_config.escape, context)
+                .map(|_| true);
+        } else if !modifiers.control && !modifiers.alt && !modifiers.super_ {
+            let text = self.keyboard_state.get_utf8(event.detail as u32);
+            if text.is_empty() || text.chars().any(char::is_control) {
+                return Ok(false);
+            }
+            self.windows[index].widget_mut().push_filter_text(&text)
+        } else {
+            return Ok(false);
+        };
+        self.windows[index].apply_effect(effect, context)?;
+        Ok(true)
+    }
+
+    fn run_filter_action(
+        &mut self,
+        index: usize,
+        action: FilterAction,
+        context: &mut EventLoopContext,
+    ) -> anyhow::Result<()> {
+        let effect = self.windows[index].widget_mut().run_filter_action(action);
+        self.windows[index].apply_effect(effect, context)?;
+        if action == FilterAction::HideWindow {
+            self.windows[index].hide().context("hide window")?;
+        }
+        Ok(())
+    }
+
+    fn activate_window(&self, index: usize, is_active: bool) -> anyhow::Result<()> {
+        self.tray_managers[index]
+            .activate_window(is_active)
+            .context("send XEmbed window activation")?;
+        Ok(())
+    }
+
+    fn update_icon_focus(&mut self, focus: XEmbedFocus) -> anyhow::Result<()> {
+        for (index, (tray_manager, window)) in self
+            .tray_managers
+            .iter_mut()
+            .zip(self.windows.iter())
+            .enumerate()
+        {
+            let icon_window = match window.widget().selected_item_id() {
+                Some(TrayItemId::XEmbed(icon_window)) if self.active_window == Some(index) => {
+                    Some(icon_window)
+                }
+                _ => None,
+            };
+            tray_manager
+                .focus_icon(icon_window, focus)
+                .context("send XEmbed focus")?;
+        }
+        Ok(())
+    }
+
+    fn find_accelerator(
+        &self,
+        keysym: Keysym,
+        modifiers: Modifiers,
+    ) -> Option<(&TrayManager<XCBConnection>, xproto::Window, u32)> {
+        self.tray_managers.iter().find_map(|tray_manager| {
+            let (icon_window, id) = tray_manager.find_accelerator(keysym, modifiers)?;
+            Some((tray_manager, icon_window, id))
+        })
+    }
+
+    fn forward_key_event(&self, event: &xproto::KeyPressEvent) -> anyhow::Result<()> {
+        for tray_manager in self.tray_managers.iter() {
+            if tray_manager
+                .forward_key_event(event)
+                .context("forward key event")?
+            {
+                break;
             }
         }
+        Ok(())
+    }
 
+    fn on_status_notifier_event(
+        &mut self,
+        event: StatusNotifierEvent,
+        context: &mut EventLoopContext,
+    ) -> anyhow::Result<()> {
+        // StatusNotifierItems are only shown in the default window.
+        let window = self.default_window_mut();
+        let effect = match event {
+            StatusNotifierEvent::Added(item) => window.widget_mut().add_tray_item(item),
+            StatusNotifierEvent::Updated(item) => window.widget_mut().update_tray_item(item),
+            StatusNotifierEvent::Removed(item) => {
+                let id = TrayItemIcon::from(item).id();
+                window.widget_mut().remove_tray_item(&id)
+            }
+            StatusNotifierEvent::MenuLayoutReceived(menu, root) => {
+                window.widget_mut().receive_menu_layout(&menu, root)
+            }
+            StatusNotifierEvent::MenuLayoutFailed(menu) => {
+                window.widget_mut().fail_menu_layout(&menu)
+            }
+        };
+        window.apply_effect(effect, context)?;
         Ok(())
     }
+
+    fn send_menu_requests(&mut self) {
+        let requests = self.default_window_mut().widget_mut().take_menu_requests();
+        if let Some(status_notifier_host) = &mut self.status_notifier_host {
+            for request in requests {
+                if let Err(error) = status_notifier_host.request_menu(request) {
+                    log::warn!("Failed to request menu: {}", error);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SelectionError {
+    AlreadyOwned(usize),
+    Timeout(usize),
+}
+
+impl SelectionError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::AlreadyOwned(_) => 2,
+            Self::Timeout(_) => 3,
+        }
+    }
 }
 
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AlreadyOwned(screen_num) => write!(
+                f,
+                "System tray selection for screen {} is already owned by another tray",
+                screen_num
+            ),
+            Self::Timeout(screen_num) => write!(
+                f,
+                "Timed out waiting for system tray selection for screen {}",
+                screen_num
+            ),
+        }
+    }
+}
+
+impl error::Error for SelectionError {}
+
 impl Drop for App {
     fn drop(&mut self) {
         unsafe {
-            ManuallyDrop::drop(&mut self.tray_manager);
-            ManuallyDrop::drop(&mut self.window);
+            ManuallyDrop::drop(&mut self.tray_managers);
+            ManuallyDrop::drop(&mut self.windows);
         }
     }
 }
@@ -284,6 +1005,7 @@ impl Drop for App {
 fn run_command(
     window: &mut Window<TrayContainer>,
     command: &Command,
+    time: xproto::Timestamp,
     context: &mut EventLoopContext,
 ) -> anyhow::Result<()> {
     match command {
@@ -320,14 +1042,135 @@ fn run_command(
             let effect = window.widget_mut().select_previous_item();
             window.apply_effect(effect, context)?;
         }
-        Command::ClickMouseButton { button } => {
-            let effect = window.widget_mut().click_selected_item(*button);
+        Command::SelectLeftItem => {
+            let effect = window.widget_mut().select_left_item();
+            window.apply_effect(effect, context)?;
+        }
+        Command::SelectRightItem => {
+            let effect = window.widget_mut().select_right_item();
+            window.apply_effect(effect, context)?;
+        }
+        Command::SelectUpItem => {
+            let effect = window.widget_mut().select_up_item();
+            window.apply_effect(effect, context)?;
+        }
+        Command::SelectDownItem => {
+            let effect = window.widget_mut().select_down_item();
+            window.apply_effect(effect, context)?;
+        }
+        Command::ClickMouseButton {
+            button,
+            modifiers,
+            position,
+        } => {
+            let effect = window
+                .widget_mut()
+                .click_selected_item(*button, *modifiers, *position, time);
+            window.apply_effect(effect, context)?;
+        }
+        Command::DoubleClickMouseButton {
+            button,
+            modifiers,
+            position,
+        } => {
+            let effect = window
+                .widget_mut()
+                .double_click_selected_item(*button, *modifiers, *position, time);
+            window.apply_effect(effect, context)?;
+        }
+        Command::HoldMouseButton {
+            button,
+            modifiers,
+            position,
+            duration,
+        } => {
+            let effect = window.widget_mut().hold_selected_item(
+                *button,
+                *modifiers,
+                *position,
+                Duration::from_millis(*duration),
+                time,
+            );
+            window.apply_effect(effect, context)?;
+        }
+        Command::HoverSelectedItem => {
+            let effect = window.widget_mut().hover_selected_item();
+            window.apply_effect(effect, context)?;
+        }
+        Command::ScrollUp { steps } => {
+            let effect = window
+                .widget_mut()
+                .scroll_selected_item(ScrollDirection::Up, *steps);
+            window.apply_effect(effect, context)?;
+        }
+        Command::ScrollDown { steps } => {
+            let effect = window
+                .widget_mut()
+                .scroll_selected_item(ScrollDirection::Down, *steps);
+            window.apply_effect(effect, context)?;
+        }
+        Command::ScrollLeft { steps } => {
+            let effect = window
+                .widget_mut()
+                .scroll_selected_item(ScrollDirection::Left, *steps);
+            window.apply_effect(effect, context)?;
+        }
+        Command::ScrollRight { steps } => {
+            let effect = window
+                .widget_mut()
+                .scroll_selected_item(ScrollDirection::Right, *steps);
+            window.apply_effect(effect, context)?;
+        }
+        Command::CloseMenu => {
+            let effect = window.widget_mut().close_menu();
+            window.apply_effect(effect, context)?;
+        }
+        Command::DismissMessage => {
+            let effect = window.widget_mut().dismiss_message();
+            window.apply_effect(effect, context)?;
+        }
+        Command::SelectMessageItem => {
+            let effect = window.widget_mut().select_message_item();
+            window.apply_effect(effect, context)?;
+        }
+        Command::SelectRuleItem { index } => {
+            let effect = window.widget_mut().select_rule_item(*index);
+            window.apply_effect(effect, context)?;
+        }
+        Command::StartFilter => {
+            let effect = window.widget_mut().start_filter();
+            window.apply_effect(effect, context)?;
+        }
+        Command::StartHint { button } => {
+            let effect = window.widget_mut().start_hint(*button);
             window.apply_effect(effect, context)?;
         }
     }
     Ok(())
 }
 
+fn rule_hotkeys(rules: &[Rule]) -> Vec<Hotkey> {
+    rules
+        .iter()
+        .enumerate()
+        .filter_map(|(index, rule)| rule.hotkey(index))
+        .collect()
+}
+
+fn setup_status_notifier(
+    connection: Rc<dbus::Connection>,
+    config: &StatusNotifierConfig,
+    icon_size: u32,
+) -> Result<(Option<StatusNotifierWatcher>, StatusNotifierHost), dbus::Error> {
+    let watcher = if config.builtin_watcher {
+        Some(StatusNotifierWatcher::new(connection.clone())?)
+    } else {
+        None
+    };
+    let host = StatusNotifierHost::new(connection, icon_size)?;
+    Ok((watcher, host))
+}
+
 fn setup_xkb_extension(connection: &XCBConnection) -> anyhow::Result<()> {
     let reply = connection
         .xkb_use_extension(1, 0)?
@@ -482,6 +1325,41 @@ fn configure_window(
     Ok(())
 }
 
+fn system_tray_colors(config: &TrayColorsConfig) -> SystemTrayColors {
+    SystemTrayColors::new(config.normal, config.success, config.warning, config.error)
+}
+
+fn system_tray_orientation(orientation: Orientation) -> SystemTrayOrientation {
+    match orientation {
+        Orientation::Vertical => SystemTrayOrientation::VERTICAL,
+        Orientation::Horizontal => SystemTrayOrientation::HORZONTAL,
+    }
+}
+
+fn create_colormap(
+    connection: &XCBConnection,
+    screen_num: usize,
+) -> Result<(xproto::Visualid, u8, xproto::Colormap), ReplyOrIdError> {
+    let screen = &connection.setup().roots[screen_num];
+    let (visual_id, depth) =
+        match find_visual_from_screen(screen, 32, xproto::VisualClass::TRUE_COLOR) {
+            Some(visual) => (visual.visual_id, 32),
+            None => (screen.root_visual, screen.root_depth),
+        };
+    let colormap = connection.generate_id()?;
+
+    connection
+        .create_colormap(
+            xproto::ColormapAlloc::NONE,
+            colormap,
+            screen.root,
+            visual_id,
+        )?
+        .check()?;
+
+    Ok((visual_id, depth, colormap))
+}
+
 fn find_visual_from_screen(
     screen: &xproto::Screen,
     depth: u8,
@@ -495,6 +1373,31 @@ fn find_visual_from_screen(
         .find(|visualtype| visualtype.class == visual_class)
 }
 
+fn grab_global_hotkeys(
+    connection: &XCBConnection,
+    keyboard_state: &xkb::State,
+    hotkeys: &[Hotkey],
+) -> anyhow::Result<()> {
+    for screen_num in 0..connection.setup().roots.len() {
+        ungrab_keys(connection, screen_num).context("ungrab_keys")?;
+        for key in hotkeys {
+            let keycode = keyboard_state
+                .lookup_keycode(key.keysym())
+                .context("lookup keycode")?;
+            grab_key(connection, screen_num, keycode, key.modifiers()).context("grab_key")?;
+        }
+    }
+    Ok(())
+}
+
+fn ungrab_keys(connection: &XCBConnection, screen_num: usize) -> Result<(), ReplyError> {
+    let screen = &connection.setup().roots[screen_num];
+    connection
+        .ungrab_key(xproto::Grab::ANY, screen.root, xproto::ModMask::ANY)?
+        .check()?;
+    Ok(())
+}
+
 fn grab_key(
     connection: &XCBConnection,
     screen_num: usize,
@@ -542,3 +1445,28 @@ x11rb::atom_manager! {
         _NET_WM_WINDOW_TYPE_UTILITY,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection_error() {
+        let error: anyhow::Error = SelectionError::AlreadyOwned(1).into();
+        let selection_error = error.downcast_ref::<SelectionError>().unwrap();
+        assert_eq!(selection_error.exit_code(), 2);
+        assert_eq!(
+            selection_error.to_string(),
+            "System tray selection for screen 1 is already owned by another tray"
+        );
+
+        let error: anyhow::Error =
+            anyhow::Error::from(SelectionError::Timeout(0)).context("acquire tray selection");
+        let selection_error = error.downcast_ref::<SelectionError>().unwrap();
+        assert_eq!(selection_error.exit_code(), 3);
+        assert_eq!(
+            selection_error.to_string(),
+            "Timed out waiting for system tray selection for screen 0"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use geekytray_shell::event::MouseButton;
+use geekytray_shell::event::{Modifiers, MouseButton};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
@@ -8,8 +8,126 @@ pub enum Command {
     ShowWindow,
     ToggleWindow,
     DeselectItem,
-    SelectItem { index: usize },
+    SelectItem {
+        index: usize,
+    },
     SelectNextItem,
     SelectPreviousItem,
-    ClickMouseButton { button: MouseButton },
+    SelectLeftItem,
+    SelectRightItem,
+    SelectUpItem,
+    SelectDownItem,
+    ClickMouseButton {
+        button: MouseButton,
+        #[serde(default)]
+        modifiers: Modifiers,
+        #[serde(default)]
+        position: Option<ClickPosition>,
+    },
+    DoubleClickMouseButton {
+        button: MouseButton,
+        #[serde(default)]
+        modifiers: Modifiers,
+        #[serde(default)]
+        position: Option<ClickPosition>,
+    },
+    HoldMouseButton {
+        button: MouseButton,
+        #[serde(default)]
+        modifiers: Modifiers,
+        #[serde(default)]
+        position: Option<ClickPosition>,
+        // In milliseconds.
+        duration: u64,
+    },
+    HoverSelectedItem,
+    ScrollUp {
+        steps: usize,
+    },
+    ScrollDown {
+        steps: usize,
+    },
+    ScrollLeft {
+        steps: usize,
+    },
+    ScrollRight {
+        steps: usize,
+    },
+    CloseMenu,
+    DismissMessage,
+    SelectMessageItem,
+    SelectRuleItem {
+        index: usize,
+    },
+    StartFilter,
+    StartHint {
+        button: Option<MouseButton>,
+    },
+}
+
+// The position in pixels relative to the top-left corner of the icon.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ClickPosition {
+    pub x: i16,
+    pub y: i16,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Hotkey {
+        commands: Vec<Command>,
+    }
+
+    #[test]
+    fn test_deserialize_click_commands() {
+        let hotkey: Hotkey = toml::from_str(
+            r#"
+            commands = [
+                { type = "ClickMouseButton", params = { button = "Left" } },
+                { type = "ClickMouseButton", params = { button = "Right", modifiers = { control = true, super = true }, position = { x = 1, y = 2 } } },
+                { type = "DoubleClickMouseButton", params = { button = "Left", modifiers = { shift = true } } },
+                { type = "HoldMouseButton", params = { button = "Left", duration = 1000 } },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            hotkey.commands,
+            vec![
+                Command::ClickMouseButton {
+                    button: MouseButton::Left,
+                    modifiers: Modifiers::NONE,
+                    position: None,
+                },
+                Command::ClickMouseButton {
+                    button: MouseButton::Right,
+                    modifiers: Modifiers {
+                        control: true,
+                        super_: true,
+                        ..Modifiers::NONE
+                    },
+                    position: Some(ClickPosition { x: 1, y: 2 }),
+                },
+                Command::DoubleClickMouseButton {
+                    button: MouseButton::Left,
+                    modifiers: Modifiers {
+                        shift: true,
+                        ..Modifiers::NONE
+                    },
+                    position: None,
+                },
+                Command::HoldMouseButton {
+                    button: MouseButton::Left,
+                    modifiers: Modifiers::NONE,
+                    position: None,
+                    duration: 1000,
+                },
+            ]
+        );
+    }
 }
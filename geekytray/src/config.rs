@@ -1,30 +1,52 @@
+use anyhow::Context as _;
 use geekytray_shell::event::{Modifiers, MouseButton};
 use geekytray_shell::graphics::{Color, FontFamily, FontStretch, FontStyle, FontWeight};
 use geekytray_shell::xkbcommon_sys as xkb;
 use serde::de;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
 use std::str::FromStr as _;
 
 use crate::command::Command;
 use crate::hotkey::Hotkey;
+use crate::rule::Rule;
 
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Config {
     pub window: WindowConfig,
+    pub selection: SelectionConfig,
     pub ui: UiConfig,
+    pub status_notifier: StatusNotifierConfig,
+    pub message: MessageConfig,
+    pub filter: FilterConfig,
+    pub rules: Vec<Rule>,
     pub hotkeys: Vec<Hotkey>,
     pub global_hotkeys: Vec<Hotkey>,
     pub log_level: LogLevel,
 }
 
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let toml_string = fs::read_to_string(path).context("read config file")?;
+        let config: Config = toml::from_str(&toml_string).context("parse config file")?;
+        Ok(config)
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             log_level: LogLevel(log::LevelFilter::Error),
             window: WindowConfig::default(),
+            selection: SelectionConfig::default(),
             ui: UiConfig::default(),
+            status_notifier: StatusNotifierConfig::default(),
+            message: MessageConfig::default(),
+            filter: FilterConfig::default(),
+            rules: Vec::new(),
             hotkeys: vec![
                 Hotkey::new(
                     xkb::XKB_KEY_1,
@@ -82,6 +104,11 @@ impl Default for Config {
                     Modifiers::NONE,
                     vec![Command::SelectNextItem],
                 ),
+                Hotkey::new(
+                    xkb::XKB_KEY_Right,
+                    Modifiers::NONE,
+                    vec![Command::SelectNextItem],
+                ),
                 Hotkey::new(
                     xkb::XKB_KEY_n,
                     Modifiers::CONTROL,
@@ -97,6 +124,11 @@ impl Default for Config {
                     Modifiers::NONE,
                     vec![Command::SelectPreviousItem],
                 ),
+                Hotkey::new(
+                    xkb::XKB_KEY_Left,
+                    Modifiers::NONE,
+                    vec![Command::SelectPreviousItem],
+                ),
                 Hotkey::new(
                     xkb::XKB_KEY_p,
                     Modifiers::CONTROL,
@@ -107,6 +139,8 @@ impl Default for Config {
                     Modifiers::NONE,
                     vec![Command::ClickMouseButton {
                         button: MouseButton::Left,
+                        modifiers: Modifiers::NONE,
+                        position: None,
                     }],
                 ),
                 Hotkey::new(
@@ -114,6 +148,8 @@ impl Default for Config {
                     Modifiers::NONE,
                     vec![Command::ClickMouseButton {
                         button: MouseButton::Left,
+                        modifiers: Modifiers::NONE,
+                        position: None,
                     }],
                 ),
                 Hotkey::new(
@@ -121,6 +157,8 @@ impl Default for Config {
                     Modifiers::NONE,
                     vec![Command::ClickMouseButton {
                         button: MouseButton::Right,
+                        modifiers: Modifiers::NONE,
+                        position: None,
                     }],
                 ),
                 Hotkey::new(
@@ -128,8 +166,71 @@ impl Default for Config {
                     Modifiers::SHIFT,
                     vec![Command::ClickMouseButton {
                         button: MouseButton::Right,
+                        modifiers: Modifiers::NONE,
+                        position: None,
+                    }],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_Return,
+                    Modifiers::CONTROL,
+                    vec![Command::DoubleClickMouseButton {
+                        button: MouseButton::Left,
+                        modifiers: Modifiers::NONE,
+                        position: None,
+                    }],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_Up,
+                    Modifiers::SHIFT,
+                    vec![Command::ScrollUp { steps: 1 }],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_Down,
+                    Modifiers::SHIFT,
+                    vec![Command::ScrollDown { steps: 1 }],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_Left,
+                    Modifiers::SHIFT,
+                    vec![Command::ScrollLeft { steps: 1 }],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_Right,
+                    Modifiers::SHIFT,
+                    vec![Command::ScrollRight { steps: 1 }],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_BackSpace,
+                    Modifiers::NONE,
+                    vec![Command::CloseMenu],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_d,
+                    Modifiers::NONE,
+                    vec![Command::DismissMessage],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_m,
+                    Modifiers::NONE,
+                    vec![Command::SelectMessageItem],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_slash,
+                    Modifiers::NONE,
+                    vec![Command::StartFilter],
+                ),
+                Hotkey::new(
+                    xkb::XKB_KEY_f,
+                    Modifiers::NONE,
+                    vec![Command::StartHint {
+                        button: Some(MouseButton::Left),
                     }],
                 ),
+                Hotkey::new(
+                    xkb::XKB_KEY_F,
+                    Modifiers::SHIFT,
+                    vec![Command::StartHint { button: None }],
+                ),
                 Hotkey::new(xkb::XKB_KEY_q, Modifiers::NONE, vec![Command::HideWindow]),
                 Hotkey::new(
                     xkb::XKB_KEY_Escape,
@@ -153,6 +254,7 @@ pub struct WindowConfig {
     pub instance_name: Cow<'static, str>,
     pub class_name: Cow<'static, str>,
     pub width: f64,
+    pub max_height: f64,
     pub auto_close: bool,
 }
 
@@ -163,15 +265,51 @@ impl Default for WindowConfig {
             instance_name: Cow::Borrowed("GeekyTray"),
             class_name: Cow::Borrowed("GeekyTray"),
             width: 480.0,
+            max_height: 0.0,
             auto_close: true,
         }
     }
 }
 
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SelectionConfig {
+    pub policy: SelectionPolicy,
+    pub timeout: f64,
+}
+
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        Self {
+            policy: SelectionPolicy::default(),
+            timeout: 5.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum SelectionPolicy {
+    Fail,
+    Wait,
+    Replace,
+}
+
+impl Default for SelectionPolicy {
+    fn default() -> Self {
+        Self::Fail
+    }
+}
+
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
 #[serde(default)]
 pub struct UiConfig {
+    pub orientation: Orientation,
+    pub layout: ItemLayout,
+    pub grid_columns: usize,
     pub show_number: bool,
+    pub show_label: bool,
+    pub hint_alphabet: String,
+    pub click_backend: ClickBackend,
     pub icon_size: f64,
     pub text_size: f64,
     pub container_padding: f64,
@@ -186,6 +324,7 @@ pub struct UiConfig {
     pub selected_item_font: FontConfig,
     pub selected_item_background: Color,
     pub selected_item_foreground: Color,
+    pub tray_colors: TrayColorsConfig,
 }
 
 impl UiConfig {
@@ -197,7 +336,13 @@ impl UiConfig {
 impl Default for UiConfig {
     fn default() -> Self {
         Self {
+            orientation: Orientation::default(),
+            layout: ItemLayout::default(),
+            grid_columns: 4,
             show_number: true,
+            show_label: true,
+            hint_alphabet: "asdfghjkl".to_owned(),
+            click_backend: ClickBackend::default(),
             icon_size: 24.0,
             text_size: 12.0,
             container_padding: 8.0,
@@ -212,10 +357,125 @@ impl Default for UiConfig {
             selected_item_font: FontConfig::default(),
             selected_item_background: Color::from_rgb(0x1c95e6),
             selected_item_foreground: Color::from_rgb(0xe8eaeb),
+            tray_colors: TrayColorsConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TrayColorsConfig {
+    pub normal: Color,
+    pub success: Color,
+    pub warning: Color,
+    pub error: Color,
+}
+
+impl Default for TrayColorsConfig {
+    fn default() -> Self {
+        Self {
+            normal: Color::from_rgb(0xe8eaeb),
+            success: Color::from_rgb(0x7bc275),
+            warning: Color::from_rgb(0xf0b44c),
+            error: Color::from_rgb(0xe5534b),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum Orientation {
+    Vertical,
+    Horizontal,
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::Vertical
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum ClickBackend {
+    Synthetic,
+    XTest,
+}
+
+impl Default for ClickBackend {
+    fn default() -> Self {
+        Self::Synthetic
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum ItemLayout {
+    List,
+    Grid,
+}
+
+impl Default for ItemLayout {
+    fn default() -> Self {
+        Self::List
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StatusNotifierConfig {
+    pub enabled: bool,
+    pub builtin_watcher: bool,
+}
+
+impl Default for StatusNotifierConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            builtin_watcher: true,
         }
     }
 }
 
+#[derive(Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MessageConfig {
+    pub sink: MessageSink,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum MessageSink {
+    Window,
+    Notification,
+}
+
+impl Default for MessageSink {
+    fn default() -> Self {
+        Self::Window
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    pub escape: FilterAction,
+    pub backspace_on_empty: FilterAction,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            escape: FilterAction::ExitFilter,
+            backspace_on_empty: FilterAction::ExitFilter,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum FilterAction {
+    Nothing,
+    ClearQuery,
+    ExitFilter,
+    HideWindow,
+}
+
 #[derive(Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(default)]
 pub struct FontConfig {
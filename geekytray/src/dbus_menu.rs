@@ -0,0 +1,231 @@
+use geekytray_shell::dbus;
+use std::rc::Rc;
+
+pub const DBUSMENU_INTERFACE: &str = "com.canonical.dbusmenu";
+
+#[derive(Clone, Debug)]
+pub struct DBusMenu {
+    connection: Rc<dbus::Connection>,
+    bus_name: String,
+    object_path: String,
+}
+
+impl DBusMenu {
+    pub fn new(connection: Rc<dbus::Connection>, bus_name: String, object_path: String) -> Self {
+        Self {
+            connection,
+            bus_name,
+            object_path,
+        }
+    }
+
+    pub fn send_event(&self, id: i32, event_id: &str) -> Result<(), dbus::Error> {
+        log::info!(
+            "Send `{}` event to menu item {} of `{}{}`",
+            event_id,
+            id,
+            self.bus_name,
+            self.object_path
+        );
+        self.connection.send(
+            dbus::Message::method_call(
+                &self.bus_name,
+                &self.object_path,
+                DBUSMENU_INTERFACE,
+                "Event",
+            )
+            .with_body(vec![
+                id.into(),
+                event_id.into(),
+                dbus::Value::variant(0),
+                0u32.into(),
+            ])
+            .without_reply(),
+        )?;
+        Ok(())
+    }
+
+    pub fn is_update_signal(&self, message: &dbus::Message) -> bool {
+        (message.is_signal(DBUSMENU_INTERFACE, "LayoutUpdated")
+            || message.is_signal(DBUSMENU_INTERFACE, "ItemsPropertiesUpdated"))
+            && message.sender() == Some(self.bus_name.as_str())
+            && message.path() == Some(self.object_path.as_str())
+    }
+}
+
+impl PartialEq for DBusMenu {
+    fn eq(&self, other: &Self) -> bool {
+        self.bus_name == other.bus_name && self.object_path == other.object_path
+    }
+}
+
+// The layout of menus is fetched asynchronously through the pending calls of
+// StatusNotifierHost, so that a slow application does not block the UI.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MenuRequest {
+    GetLayout(DBusMenu),
+    AboutToShow(DBusMenu, i32),
+}
+
+impl MenuRequest {
+    pub fn to_message(&self) -> dbus::Message {
+        match self {
+            Self::GetLayout(menu) => dbus::Message::method_call(
+                &menu.bus_name,
+                &menu.object_path,
+                DBUSMENU_INTERFACE,
+                "GetLayout",
+            )
+            .with_body(vec![
+                0.into(),
+                (-1).into(),
+                dbus::Value::Array("s".to_owned(), Vec::new()),
+            ]),
+            Self::AboutToShow(menu, id) => dbus::Message::method_call(
+                &menu.bus_name,
+                &menu.object_path,
+                DBUSMENU_INTERFACE,
+                "AboutToShow",
+            )
+            .with_body(vec![(*id).into()]),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DBusMenuItem {
+    pub id: i32,
+    pub label: String,
+    pub is_enabled: bool,
+    pub is_visible: bool,
+    pub is_separator: bool,
+    pub toggle: MenuToggle,
+    pub has_submenu: bool,
+    pub children: Vec<DBusMenuItem>,
+}
+
+impl DBusMenuItem {
+    pub fn from_layout_reply(reply: &dbus::Message) -> Option<Self> {
+        reply.body().get(1).and_then(Self::parse)
+    }
+
+    fn parse(value: &dbus::Value) -> Option<Self> {
+        let (id, properties, children) = match value.as_struct()? {
+            [id, properties, children] => (id.as_i32()?, properties, children.as_array()?),
+            _ => return None,
+        };
+
+        let get_str = |key: &str| properties.dict_get(key).and_then(dbus::Value::as_str);
+        let get_bool = |key: &str| properties.dict_get(key).and_then(dbus::Value::as_bool);
+
+        let children: Vec<DBusMenuItem> = children.iter().filter_map(Self::parse).collect();
+        let is_checked = properties
+            .dict_get("toggle-state")
+            .and_then(dbus::Value::as_i32)
+            == Some(1);
+        let toggle = match get_str("toggle-type") {
+            Some("checkmark") => MenuToggle::Checkmark(is_checked),
+            Some("radio") => MenuToggle::Radio(is_checked),
+            _ => MenuToggle::None,
+        };
+
+        Some(Self {
+            id,
+            label: strip_mnemonic(get_str("label").unwrap_or_default()),
+            is_enabled: get_bool("enabled").unwrap_or(true),
+            is_visible: get_bool("visible").unwrap_or(true),
+            is_separator: get_str("type") == Some("separator"),
+            toggle,
+            has_submenu: get_str("children-display") == Some("submenu") || !children.is_empty(),
+            children,
+        })
+    }
+
+    pub fn find(&self, id: i32) -> Option<&DBusMenuItem> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(id))
+    }
+
+    pub fn is_selectable(&self) -> bool {
+        self.is_visible && self.is_enabled && !self.is_separator
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MenuToggle {
+    None,
+    Checkmark(bool),
+    Radio(bool),
+}
+
+fn strip_mnemonic(label: &str) -> String {
+    let mut result = String::with_capacity(label.len());
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        if c == '_' {
+            if let Some(next) = chars.next() {
+                result.push(next);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_layout() {
+        let entry = |id: i32, properties: Vec<(&str, dbus::Value)>, children: Vec<dbus::Value>| {
+            dbus::Value::Struct(vec![
+                id.into(),
+                dbus::Value::dict(
+                    "s",
+                    "v",
+                    properties
+                        .into_iter()
+                        .map(|(key, value)| (key.into(), dbus::Value::variant(value)))
+                        .collect(),
+                ),
+                dbus::Value::Array(
+                    "v".to_owned(),
+                    children.into_iter().map(dbus::Value::variant).collect(),
+                ),
+            ])
+        };
+        let layout = entry(
+            0,
+            vec![("children-display", "submenu".into())],
+            vec![
+                entry(1, vec![("label", "_Open__File".into())], Vec::new()),
+                entry(2, vec![("type", "separator".into())], Vec::new()),
+                entry(
+                    3,
+                    vec![
+                        ("label", "Mute".into()),
+                        ("toggle-type", "checkmark".into()),
+                        ("toggle-state", 1.into()),
+                        ("enabled", false.into()),
+                    ],
+                    Vec::new(),
+                ),
+            ],
+        );
+
+        let root = DBusMenuItem::parse(&layout).unwrap();
+        assert!(root.has_submenu);
+        assert_eq!(root.children.len(), 3);
+        assert_eq!(root.children[0].label, "Open_File");
+        assert!(root.children[0].is_selectable());
+        assert!(root.children[1].is_separator);
+        assert!(!root.children[1].is_selectable());
+        assert_eq!(root.children[2].toggle, MenuToggle::Checkmark(true));
+        assert!(!root.children[2].is_selectable());
+        assert_eq!(root.find(3).map(|item| item.id), Some(3));
+    }
+}
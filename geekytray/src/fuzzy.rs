@@ -0,0 +1,94 @@
+use std::ops::Range;
+
+const SCORE_MATCH: i32 = 16;
+const BONUS_CONSECUTIVE: i32 = 16;
+const BONUS_WORD_START: i32 = 8;
+const PENALTY_GAP: i32 = 1;
+
+#[derive(Debug, PartialEq)]
+pub struct FuzzyMatch {
+    pub score: i32,
+    pub ranges: Vec<Range<usize>>,
+}
+
+// Matches the pattern as a case-insensitive subsequence of the subject. The
+// ranges of the result are byte ranges of the matched characters.
+pub fn fuzzy_match(pattern: &str, subject: &str) -> Option<FuzzyMatch> {
+    let mut pattern_chars = pattern.chars().filter(|c| !c.is_whitespace()).peekable();
+    let mut score = 0;
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut previous_char = None;
+    let mut gap = 0;
+
+    for (offset, c) in subject.char_indices() {
+        let pattern_char = match pattern_chars.peek() {
+            Some(pattern_char) => *pattern_char,
+            None => break,
+        };
+
+        if eq_ignore_case(pattern_char, c) {
+            pattern_chars.next();
+            score += SCORE_MATCH - gap * PENALTY_GAP;
+            if is_word_start(previous_char, c) {
+                score += BONUS_WORD_START;
+            }
+            match ranges.last_mut() {
+                Some(range) if range.end == offset => {
+                    score += BONUS_CONSECUTIVE;
+                    range.end = offset + c.len_utf8();
+                }
+                _ => ranges.push(offset..offset + c.len_utf8()),
+            }
+            gap = 0;
+        } else {
+            gap += 1;
+        }
+
+        previous_char = Some(c);
+    }
+
+    if pattern_chars.peek().is_some() {
+        return None;
+    }
+
+    Some(FuzzyMatch { score, ranges })
+}
+
+fn eq_ignore_case(x: char, y: char) -> bool {
+    x == y || x.to_lowercase().eq(y.to_lowercase())
+}
+
+fn is_word_start(previous_char: Option<char>, c: char) -> bool {
+    match previous_char {
+        Some(previous_char) => {
+            !previous_char.is_alphanumeric() || (previous_char.is_lowercase() && c.is_uppercase())
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert_eq!(
+            fuzzy_match("nm", "NetworkManager").map(|result| result.ranges),
+            Some(vec![0..1, 7..8])
+        );
+        assert_eq!(
+            fuzzy_match("stm", "Steam").map(|result| result.ranges),
+            Some(vec![0..2, 4..5])
+        );
+        assert_eq!(
+            fuzzy_match("cé", "Café").map(|result| result.ranges),
+            Some(vec![0..1, 3..5])
+        );
+        assert_eq!(fuzzy_match("xyz", "Steam"), None);
+        assert!(
+            fuzzy_match("ste", "Steam").unwrap().score
+                > fuzzy_match("ste", "System Tray Editor").unwrap().score
+        );
+    }
+}
@@ -0,0 +1,48 @@
+// Generates labels of the same length so that no label is a prefix of
+// another. An alphabet with less than two characters can only label as many
+// items as it has characters.
+pub fn hint_labels(alphabet: &str, count: usize) -> Vec<String> {
+    let mut chars: Vec<char> = Vec::new();
+    for c in alphabet.chars() {
+        if !c.is_whitespace() && !chars.contains(&c) {
+            chars.push(c);
+        }
+    }
+
+    if chars.len() < 2 {
+        return chars.iter().take(count).map(char::to_string).collect();
+    }
+
+    let mut length = 1;
+    let mut capacity = chars.len();
+    while capacity < count {
+        length += 1;
+        capacity *= chars.len();
+    }
+
+    (0..count)
+        .map(|index| {
+            let mut label = vec![chars[0]; length];
+            let mut n = index;
+            for slot in label.iter_mut().rev() {
+                *slot = chars[n % chars.len()];
+                n /= chars.len();
+            }
+            label.into_iter().collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hint_labels() {
+        assert_eq!(hint_labels("asd", 3), vec!["a", "s", "d"]);
+        assert_eq!(hint_labels("asd", 5), vec!["aa", "as", "ad", "sa", "ss"]);
+        assert_eq!(hint_labels("a a", 3), vec!["a"]);
+        assert_eq!(hint_labels("", 3), Vec::<String>::new());
+        assert_eq!(hint_labels("asdfghjkl", 82).last().unwrap(), "saa");
+    }
+}
@@ -1,11 +1,26 @@
 mod app;
 mod command;
 mod config;
+mod dbus_menu;
+mod fuzzy;
+mod hint;
 mod hotkey;
+mod menu_list;
+mod notification_client;
+mod rule;
+mod state;
+mod status_notifier_host;
+mod status_notifier_watcher;
+#[cfg(test)]
+mod test_bus;
 mod tray_container;
 mod tray_item;
 mod tray_manager;
 mod xembed;
 
-pub use app::App;
-pub use config::{Config, UiConfig, WindowConfig};
+pub use app::{App, SelectionError};
+pub use config::{
+    ClickBackend, Config, FilterAction, FilterConfig, ItemLayout, MessageConfig, MessageSink,
+    Orientation, SelectionConfig, SelectionPolicy, StatusNotifierConfig, TrayColorsConfig,
+    UiConfig, WindowConfig,
+};
@@ -6,31 +6,55 @@ use simple_logger::SimpleLogger;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process;
 
-use geekytray::{App, Config};
+use geekytray::{App, Config, SelectionError, SelectionPolicy};
 
 const HELP: &'static str = "\
 USAGE:
   geekytray [OPTIONS]
 
 OPTIONS:
-  -c, --config <CONFIG>  a path to the alternative config file [Default: $XDG_CONFIG_HOME/geekytray/config.yml]
-  -h, --help             Print help information
-  -V, --version          Print version information
+  -c, --config <CONFIG>    a path to the alternative config file [Default: $XDG_CONFIG_HOME/geekytray/config.yml]
+  -r, --replace            Take over the tray selection from the running system tray
+  -w, --wait               Wait until the running system tray gives up the tray selection
+  -t, --timeout <SECONDS>  Seconds to wait for the tray selection, 0 means forever [Default: 5]
+  -h, --help               Print help information
+  -V, --version            Print version information
+
+EXIT STATUS:
+  0  Exited normally
+  1  An error occurred
+  2  The tray selection is already owned by another system tray
+  3  Timed out waiting for the tray selection
 ";
 
 #[derive(Debug)]
 struct Args {
     config: Option<String>,
+    selection_policy: Option<SelectionPolicy>,
+    selection_timeout: Option<f64>,
 }
 
 fn main() -> anyhow::Result<()> {
+    if let Err(error) = run() {
+        if let Some(selection_error) = error.downcast_ref::<SelectionError>() {
+            eprintln!("Error: {}", selection_error);
+            process::exit(selection_error.exit_code());
+        }
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn run() -> anyhow::Result<()> {
     let args = parse_args().context("parse args")?;
 
-    let config = match args.config.map(PathBuf::from).or_else(get_config_path) {
+    let config_path = args.config.map(PathBuf::from).or_else(get_config_path);
+    let mut config = match &config_path {
         Some(path) => {
             if path.exists() {
-                load_config(path)?
+                Config::load(path)?
             } else {
                 save_default_config(path)?;
                 Config::default()
@@ -38,11 +62,19 @@ fn main() -> anyhow::Result<()> {
         }
         _ => Config::default(),
     };
+    if let Some(selection_policy) = args.selection_policy {
+        config.selection.policy = selection_policy;
+    }
+    if let Some(selection_timeout) = args.selection_timeout {
+        config.selection.timeout = selection_timeout;
+    }
+    // The level is limited by the max level, which can be changed on reload.
     SimpleLogger::new()
-        .with_level(config.log_level.into())
+        .with_level(log::LevelFilter::Trace)
         .init()
         .context("init logger")?;
-    let mut app = App::new(config)?;
+    log::set_max_level(config.log_level.into());
+    let mut app = App::new(config, config_path)?;
     app.run()?;
     Ok(())
 }
@@ -52,16 +84,26 @@ fn parse_args() -> Result<Args, pico_args::Error> {
 
     if pargs.contains(["-h", "--help"]) {
         print!("{}", HELP);
-        std::process::exit(0);
+        process::exit(0);
     }
 
     if pargs.contains(["-V", "--version"]) {
         println!("geekytray v{}", env!("CARGO_PKG_VERSION"));
-        std::process::exit(0);
+        process::exit(0);
     }
 
+    let selection_policy = if pargs.contains(["-r", "--replace"]) {
+        Some(SelectionPolicy::Replace)
+    } else if pargs.contains(["-w", "--wait"]) {
+        Some(SelectionPolicy::Wait)
+    } else {
+        None
+    };
+
     Ok(Args {
         config: pargs.opt_value_from_str(["-c", "--config"])?,
+        selection_policy,
+        selection_timeout: pargs.opt_value_from_str(["-t", "--timeout"])?,
     })
 }
 
@@ -73,12 +115,6 @@ fn get_config_path() -> Option<PathBuf> {
         .ok()
 }
 
-fn load_config(path: impl AsRef<Path>) -> anyhow::Result<Config> {
-    let toml_string = fs::read_to_string(path).context("read config file")?;
-    let config: Config = toml::from_str(&toml_string).context("parse config file")?;
-    Ok(config)
-}
-
 fn save_default_config(path: impl AsRef<Path>) -> anyhow::Result<()> {
     let default_string = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml"));
     fs::write(path, default_string).context("write config file")?;
@@ -0,0 +1,496 @@
+use geekytray_shell::dbus;
+use geekytray_shell::geometrics::{PhysicalPoint, Point, Rect, Size};
+use geekytray_shell::graphics::{
+    FontDescription, HorizontalAlign, RenderContext, RenderOp, Text, VerticalAlign,
+};
+use geekytray_shell::window::{Effect, Layout, Widget};
+use std::mem;
+use std::rc::Rc;
+use x11rb::protocol;
+
+use crate::config::UiConfig;
+use crate::dbus_menu::{DBusMenu, DBusMenuItem, MenuRequest, MenuToggle};
+use crate::tray_item::TrayItemId;
+
+const DISABLED_ALPHA: u8 = 0x80;
+const SEPARATOR_HEIGHT: f64 = 1.0;
+
+#[derive(Debug)]
+pub struct MenuList {
+    owner: TrayItemId,
+    title: String,
+    menu: DBusMenu,
+    root: DBusMenuItem,
+    submenu_path: Vec<i32>,
+    selected_index: Option<usize>,
+    pressed_index: Option<usize>,
+    is_closed: bool,
+    requests: Vec<MenuRequest>,
+    item_font: FontDescription,
+    selected_item_font: FontDescription,
+    config: Rc<UiConfig>,
+}
+
+impl MenuList {
+    pub fn new(
+        owner: TrayItemId,
+        title: String,
+        menu: DBusMenu,
+        root: DBusMenuItem,
+        item_font: FontDescription,
+        selected_item_font: FontDescription,
+        config: Rc<UiConfig>,
+    ) -> Self {
+        Self {
+            owner,
+            title,
+            menu,
+            root,
+            submenu_path: Vec::new(),
+            selected_index: None,
+            pressed_index: None,
+            is_closed: false,
+            requests: Vec::new(),
+            item_font,
+            selected_item_font,
+            config,
+        }
+    }
+
+    pub fn set_config(
+        &mut self,
+        item_font: FontDescription,
+        selected_item_font: FontDescription,
+        config: Rc<UiConfig>,
+    ) {
+        self.item_font = item_font;
+        self.selected_item_font = selected_item_font;
+        self.config = config;
+    }
+
+    pub fn owner(&self) -> &TrayItemId {
+        &self.owner
+    }
+
+    pub fn menu(&self) -> &DBusMenu {
+        &self.menu
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.is_closed
+    }
+
+    pub fn take_requests(&mut self) -> Vec<MenuRequest> {
+        mem::take(&mut self.requests)
+    }
+
+    pub fn select_item(&mut self, index: Option<usize>) -> Effect {
+        self.selected_index = index.filter(|index| {
+            self.entries()
+                .get(*index)
+                .map_or(false, |entry| entry.is_selectable())
+        });
+        Effect::RequestRedraw
+    }
+
+    pub fn select_nth_item(&mut self, n: usize) -> Effect {
+        let index = self
+            .entries()
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_selectable())
+            .nth(n)
+            .map(|(index, _)| index);
+        self.select_item(index)
+    }
+
+    pub fn select_next_item(&mut self) -> Effect {
+        let start = self.selected_index.map_or(0, |index| index + 1);
+        let index = self
+            .entries()
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find(|(_, entry)| entry.is_selectable())
+            .map(|(index, _)| index);
+        self.select_item(index)
+    }
+
+    pub fn select_previous_item(&mut self) -> Effect {
+        let entries = self.entries();
+        let end = self.selected_index.unwrap_or(entries.len());
+        let index = entries[..end]
+            .iter()
+            .rposition(|entry| entry.is_selectable());
+        self.select_item(index)
+    }
+
+    pub fn click_selected_item(&mut self) -> Effect {
+        let entry = match self
+            .selected_index
+            .and_then(|index| self.entries().get(index).map(|entry| (*entry).clone()))
+        {
+            Some(entry) if entry.is_selectable() => entry,
+            _ => return Effect::None,
+        };
+
+        if entry.has_submenu {
+            self.requests
+                .push(MenuRequest::AboutToShow(self.menu.clone(), entry.id));
+            self.submenu_path.push(entry.id);
+            self.selected_index = None;
+            self.select_next_item() + Effect::RequestLayout
+        } else {
+            if let Err(error) = self.menu.send_event(entry.id, "clicked") {
+                log::warn!("Failed to click menu item {}: {}", entry.id, error);
+            }
+            self.is_closed = true;
+            Effect::RequestLayout
+        }
+    }
+
+    pub fn close_submenu(&mut self) -> Effect {
+        match self.submenu_path.pop() {
+            Some(id) => {
+                self.selected_index = self.entries().iter().position(|entry| entry.id == id);
+            }
+            None => {
+                self.is_closed = true;
+            }
+        }
+        Effect::RequestLayout
+    }
+
+    pub fn update_menu(&mut self, message: &dbus::Message) -> Effect {
+        if self.menu.is_update_signal(message) {
+            self.requests
+                .push(MenuRequest::GetLayout(self.menu.clone()));
+        }
+        Effect::None
+    }
+
+    pub fn set_layout(&mut self, root: DBusMenuItem) -> Effect {
+        let selected_id = self
+            .selected_index
+            .and_then(|index| self.entries().get(index).map(|entry| entry.id));
+
+        self.root = root;
+
+        while let Some(id) = self.submenu_path.last() {
+            if self.root.find(*id).is_some() {
+                break;
+            }
+            self.submenu_path.pop();
+        }
+
+        self.selected_index = selected_id.and_then(|id| {
+            self.entries()
+                .iter()
+                .position(|entry| entry.id == id && entry.is_selectable())
+        });
+
+        // The submenu may have been entered before its items are known.
+        if self.selected_index.is_none() {
+            self.select_next_item() + Effect::RequestLayout
+        } else {
+            Effect::RequestLayout
+        }
+    }
+
+    fn current_menu(&self) -> &DBusMenuItem {
+        self.submenu_path
+            .last()
+            .and_then(|id| self.root.find(*id))
+            .unwrap_or(&self.root)
+    }
+
+    fn entries(&self) -> Vec<&DBusMenuItem> {
+        self.current_menu()
+            .children
+            .iter()
+            .filter(|entry| entry.is_visible)
+            .collect()
+    }
+
+    fn header_title(&self) -> String {
+        let mut title = self.title.clone();
+        for id in &self.submenu_path {
+            if let Some(submenu) = self.root.find(*id) {
+                title.push_str(" › ");
+                title.push_str(&submenu.label);
+            }
+        }
+        title
+    }
+
+    fn render_entry(
+        &self,
+        entry: &DBusMenuItem,
+        number: Option<usize>,
+        is_selected: bool,
+        bounds: Rect,
+    ) -> RenderOp {
+        if entry.is_separator {
+            return RenderOp::Rect(self.config.item_background, bounds);
+        }
+
+        let (bg_color, fg_color) = if is_selected {
+            (
+                self.config.selected_item_background,
+                self.config.selected_item_foreground,
+            )
+        } else {
+            (self.config.item_background, self.config.item_foreground)
+        };
+        let fg_color = if entry.is_enabled {
+            fg_color
+        } else {
+            fg_color.with_alpha(DISABLED_ALPHA)
+        };
+        let font = if is_selected {
+            self.selected_item_font.clone()
+        } else {
+            self.item_font.clone()
+        };
+
+        let mut result = if self.config.item_corner_radius > 0.0 {
+            RenderOp::RoundedRect(
+                bg_color,
+                bounds,
+                Size {
+                    width: self.config.item_corner_radius,
+                    height: self.config.item_corner_radius,
+                },
+            )
+        } else {
+            RenderOp::Rect(bg_color, bounds)
+        };
+
+        let mark = match entry.toggle {
+            MenuToggle::Checkmark(true) => "✓",
+            MenuToggle::Radio(true) => "●",
+            MenuToggle::Radio(false) => "○",
+            MenuToggle::Checkmark(false) | MenuToggle::None => "",
+        };
+        if !mark.is_empty() {
+            result = result
+                + RenderOp::Text(
+                    fg_color,
+                    Rect {
+                        x: bounds.x + self.config.item_padding,
+                        y: bounds.y,
+                        width: self.config.icon_size,
+                        height: bounds.height,
+                    },
+                    Text {
+                        content: mark.into(),
+                        font: font.clone(),
+                        size: self.config.text_size,
+                        horizontal_align: HorizontalAlign::Center,
+                        vertical_align: VerticalAlign::Middle,
+                        highlights: Vec::new(),
+                    },
+                );
+        }
+
+        let label = match number {
+            Some(number) if self.config.show_number => format!("{}. {}", number, entry.label),
+            _ => entry.label.clone(),
+        };
+        let label_bounds = Rect {
+            x: bounds.x + (self.config.icon_size + self.config.item_padding * 2.0),
+            y: bounds.y,
+            width: bounds.width - (self.config.icon_size + self.config.item_padding * 3.0),
+            height: bounds.height,
+        };
+
+        result = result
+            + RenderOp::Text(
+                fg_color,
+                label_bounds,
+                Text {
+                    content: label.into(),
+                    font: font.clone(),
+                    size: self.config.text_size,
+                    horizontal_align: HorizontalAlign::Left,
+                    vertical_align: VerticalAlign::Middle,
+                    highlights: Vec::new(),
+                },
+            );
+
+        if entry.has_submenu {
+            result = result
+                + RenderOp::Text(
+                    fg_color,
+                    label_bounds,
+                    Text {
+                        content: "›".into(),
+                        font,
+                        size: self.config.text_size,
+                        horizontal_align: HorizontalAlign::Right,
+                        vertical_align: VerticalAlign::Middle,
+                        highlights: Vec::new(),
+                    },
+                );
+        }
+
+        result
+    }
+}
+
+impl Widget for MenuList {
+    fn render(
+        &self,
+        position: Point,
+        layout: &Layout,
+        _index: usize,
+        _context: &mut RenderContext,
+    ) -> RenderOp {
+        let mut result = RenderOp::None;
+        let mut children = layout.children.iter();
+
+        if let Some((header_position, header_layout)) = children.next() {
+            let title = if self.submenu_path.is_empty() {
+                self.header_title()
+            } else {
+                format!("‹ {}", self.header_title())
+            };
+            result = result
+                + RenderOp::Text(
+                    self.config.container_foreground,
+                    Rect {
+                        x: position.x + header_position.x + self.config.item_padding,
+                        y: position.y + header_position.y,
+                        width: header_layout.size.width - self.config.item_padding * 2.0,
+                        height: header_layout.size.height,
+                    },
+                    Text {
+                        content: title.into(),
+                        font: self.selected_item_font.clone(),
+                        size: self.config.text_size,
+                        horizontal_align: HorizontalAlign::Left,
+                        vertical_align: VerticalAlign::Middle,
+                        highlights: Vec::new(),
+                    },
+                );
+        }
+
+        let mut number = 0;
+        for (index, (entry, (child_position, child_layout))) in
+            self.entries().into_iter().zip(children).enumerate()
+        {
+            let number = if entry.is_selectable() {
+                number += 1;
+                Some(number)
+            } else {
+                None
+            };
+            let bounds = Rect::new(
+                Point {
+                    x: position.x + child_position.x,
+                    y: position.y + child_position.y,
+                },
+                child_layout.size,
+            );
+            result = result
+                + self.render_entry(entry, number, self.selected_index == Some(index), bounds);
+        }
+
+        result
+    }
+
+    fn layout(&self, container_size: Size) -> Layout {
+        let item_height = self.config.item_height();
+        let mut children = Vec::new();
+        let mut y = 0.0;
+
+        children.push((
+            Point { x: 0.0, y },
+            Layout {
+                size: Size {
+                    width: container_size.width,
+                    height: item_height,
+                },
+                children: Vec::new(),
+            },
+        ));
+        y += item_height;
+
+        for entry in self.entries() {
+            let height = if entry.is_separator {
+                SEPARATOR_HEIGHT
+            } else {
+                item_height
+            };
+            y += self.config.item_gap;
+            children.push((
+                Point { x: 0.0, y },
+                Layout {
+                    size: Size {
+                        width: container_size.width,
+                        height,
+                    },
+                    children: Vec::new(),
+                },
+            ));
+            y += height;
+        }
+
+        Layout {
+            size: Size {
+                width: container_size.width,
+                height: y,
+            },
+            children,
+        }
+    }
+
+    fn on_event(&mut self, event: &protocol::Event, position: Point, layout: &Layout) -> Effect {
+        use protocol::Event::*;
+
+        let hit_index = |event_x: i16, event_y: i16| {
+            let pointer_position = PhysicalPoint {
+                x: event_x as _,
+                y: event_y as _,
+            };
+            layout
+                .children
+                .iter()
+                .position(|(child_position, child_layout)| {
+                    let bounds = Rect::new(
+                        Point {
+                            x: position.x + child_position.x,
+                            y: position.y + child_position.y,
+                        },
+                        child_layout.size,
+                    );
+                    bounds.snap().contains(pointer_position)
+                })
+        };
+
+        match event {
+            ButtonPress(event) => {
+                self.pressed_index = hit_index(event.event_x, event.event_y);
+            }
+            ButtonRelease(event) => {
+                let pressed_index = self.pressed_index.take();
+                let hit_index = hit_index(event.event_x, event.event_y);
+                if pressed_index.is_some() && pressed_index == hit_index {
+                    match hit_index {
+                        Some(0) => return self.close_submenu(),
+                        Some(index) => {
+                            return self.select_item(Some(index - 1)) + self.click_selected_item();
+                        }
+                        None => {}
+                    }
+                }
+            }
+            LeaveNotify(_) => {
+                self.pressed_index = None;
+            }
+            _ => {}
+        }
+
+        Effect::None
+    }
+}
@@ -0,0 +1,159 @@
+use geekytray_shell::dbus;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+use x11rb::protocol::xproto;
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const NOTIFICATIONS_INTERFACE: &str = "org.freedesktop.Notifications";
+
+type MessageKey = (xproto::Window, u32);
+
+#[derive(Debug)]
+pub struct NotificationClient {
+    connection: Rc<dbus::Connection>,
+    pending_notifications: HashMap<u32, PendingNotification>,
+    notifications: HashMap<MessageKey, u32>,
+}
+
+impl NotificationClient {
+    pub fn new(connection: Rc<dbus::Connection>) -> Result<Self, dbus::Error> {
+        connection.add_match(&format!(
+            "type='signal',interface='{}',member='NotificationClosed'",
+            NOTIFICATIONS_INTERFACE
+        ))?;
+
+        Ok(Self {
+            connection,
+            pending_notifications: HashMap::new(),
+            notifications: HashMap::new(),
+        })
+    }
+
+    pub fn notify(
+        &mut self,
+        icon_window: xproto::Window,
+        message_id: u32,
+        app_name: &str,
+        body: &str,
+        timeout: Duration,
+    ) -> Result<(), dbus::Error> {
+        let key = (icon_window, message_id);
+        let replaces_id = self.notifications.get(&key).copied().unwrap_or(0);
+        // Zero means that the message never expires, same as the balloon message.
+        let expire_timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+
+        let serial = self.connection.send(
+            dbus::Message::method_call(
+                NOTIFICATIONS_NAME,
+                NOTIFICATIONS_PATH,
+                NOTIFICATIONS_INTERFACE,
+                "Notify",
+            )
+            .with_body(vec![
+                app_name.into(),
+                replaces_id.into(),
+                "".into(),
+                app_name.into(),
+                body.into(),
+                dbus::Value::Array("s".to_owned(), Vec::new()),
+                dbus::Value::dict("s", "v", Vec::new()),
+                expire_timeout.into(),
+            ]),
+        )?;
+
+        self.pending_notifications.insert(
+            serial,
+            PendingNotification {
+                key,
+                is_canceled: false,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn close(
+        &mut self,
+        icon_window: xproto::Window,
+        message_id: u32,
+    ) -> Result<(), dbus::Error> {
+        let key = (icon_window, message_id);
+
+        if let Some(notification_id) = self.notifications.remove(&key) {
+            self.close_notification(notification_id)?;
+        } else if let Some(pending_notification) = self
+            .pending_notifications
+            .values_mut()
+            .find(|pending_notification| pending_notification.key == key)
+        {
+            // The notification ID is not known yet, close it when the reply arrives.
+            pending_notification.is_canceled = true;
+        }
+
+        Ok(())
+    }
+
+    pub fn process_message(&mut self, message: &dbus::Message) -> Result<(), dbus::Error> {
+        match message.message_type() {
+            dbus::MessageType::MethodReturn => {
+                let pending_notification = match message
+                    .reply_serial()
+                    .and_then(|serial| self.pending_notifications.remove(&serial))
+                {
+                    Some(pending_notification) => pending_notification,
+                    None => return Ok(()),
+                };
+                let notification_id = message.body().first().and_then(dbus::Value::as_u32).ok_or(
+                    dbus::Error::InvalidMessage("Notify reply does not contain a notification ID"),
+                )?;
+                if pending_notification.is_canceled {
+                    self.close_notification(notification_id)?;
+                } else {
+                    self.notifications
+                        .insert(pending_notification.key, notification_id);
+                }
+            }
+            dbus::MessageType::Error => {
+                if let Some(serial) = message.reply_serial() {
+                    if self.pending_notifications.remove(&serial).is_some() {
+                        message.clone().into_result()?;
+                    }
+                }
+            }
+            dbus::MessageType::Signal
+                if message.is_signal(NOTIFICATIONS_INTERFACE, "NotificationClosed") =>
+            {
+                if let Some(notification_id) = message.body().first().and_then(dbus::Value::as_u32)
+                {
+                    self.notifications.retain(|_, id| *id != notification_id);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn close_notification(&self, notification_id: u32) -> Result<(), dbus::Error> {
+        log::info!("Close notification {}", notification_id);
+        self.connection.send(
+            dbus::Message::method_call(
+                NOTIFICATIONS_NAME,
+                NOTIFICATIONS_PATH,
+                NOTIFICATIONS_INTERFACE,
+                "CloseNotification",
+            )
+            .with_body(vec![notification_id.into()])
+            .without_reply(),
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct PendingNotification {
+    key: MessageKey,
+    is_canceled: bool,
+}
@@ -0,0 +1,189 @@
+use geekytray_shell::event::{Keysym, Modifiers};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::command::Command;
+use crate::config::ClickBackend;
+use crate::hotkey::Hotkey;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<Pattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    class: Option<Pattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    process: Option<Pattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rename: Option<String>,
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hotkey: Option<RuleHotkey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    click_backend: Option<ClickBackend>,
+}
+
+impl Rule {
+    pub fn matches(&self, title: &str, class: Option<&str>, process: Option<&str>) -> bool {
+        if self.title.is_none() && self.class.is_none() && self.process.is_none() {
+            return false;
+        }
+        self.title
+            .as_ref()
+            .map_or(true, |pattern| pattern.matches(title))
+            && self.class.as_ref().map_or(true, |pattern| {
+                class.map_or(false, |class| pattern.matches(class))
+            })
+            && self.process.as_ref().map_or(true, |pattern| {
+                process.map_or(false, |process| pattern.matches(process))
+            })
+    }
+
+    pub fn rename(&self) -> Option<&str> {
+        self.rename.as_deref()
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    pub fn click_backend(&self) -> Option<ClickBackend> {
+        self.click_backend
+    }
+
+    pub fn hotkey(&self, index: usize) -> Option<Hotkey> {
+        self.hotkey.as_ref().map(|hotkey| {
+            let mut commands = vec![Command::SelectRuleItem { index }];
+            commands.extend(hotkey.commands.iter().copied());
+            Hotkey::new(hotkey.keysym, hotkey.modifiers, commands)
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+struct RuleHotkey {
+    keysym: Keysym,
+    #[serde(default)]
+    modifiers: Modifiers,
+    #[serde(default)]
+    commands: Vec<Command>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "PatternDef", into = "PatternDef")]
+pub enum Pattern {
+    Exact(String),
+    Glob(String, Regex),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn matches(&self, subject: &str) -> bool {
+        match self {
+            Self::Exact(string) => string == subject,
+            Self::Glob(_, regex) | Self::Regex(regex) => regex.is_match(subject),
+        }
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Exact(x), Self::Exact(y)) => x == y,
+            (Self::Glob(x, _), Self::Glob(y, _)) => x == y,
+            (Self::Regex(x), Self::Regex(y)) => x.as_str() == y.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<PatternDef> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: PatternDef) -> Result<Self, Self::Error> {
+        match pattern {
+            PatternDef::Exact(string) | PatternDef::Tagged(TaggedPattern::Exact(string)) => {
+                Ok(Self::Exact(string))
+            }
+            PatternDef::Tagged(TaggedPattern::Glob(glob)) => {
+                let regex = Regex::new(&glob_to_regex(&glob))?;
+                Ok(Self::Glob(glob, regex))
+            }
+            PatternDef::Tagged(TaggedPattern::Regex(regex)) => Ok(Self::Regex(Regex::new(&regex)?)),
+        }
+    }
+}
+
+impl From<Pattern> for PatternDef {
+    fn from(pattern: Pattern) -> Self {
+        match pattern {
+            Pattern::Exact(string) => Self::Exact(string),
+            Pattern::Glob(glob, _) => Self::Tagged(TaggedPattern::Glob(glob)),
+            Pattern::Regex(regex) => Self::Tagged(TaggedPattern::Regex(regex.as_str().to_owned())),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum PatternDef {
+    Exact(String),
+    Tagged(TaggedPattern),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum TaggedPattern {
+    Exact(String),
+    Glob(String),
+    Regex(String),
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::with_capacity(glob.len() + 2);
+    regex.push('^');
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let rule: Rule = toml::from_str(
+            r#"
+            title = "Steam"
+            class = { glob = "steam*" }
+            process = { regex = "^ste+am$" }
+            "#,
+        )
+        .unwrap();
+        assert!(rule.matches("Steam", Some("steam.exe"), Some("steam")));
+        assert!(!rule.matches("steam", Some("steam.exe"), Some("steam")));
+        assert!(!rule.matches("Steam", Some("Steam"), Some("steam")));
+        assert!(!rule.matches("Steam", Some("steam.exe"), None));
+
+        let rule: Rule = toml::from_str(r#"class = { glob = "a?c.*" }"#).unwrap();
+        assert!(rule.matches("", Some("abc.d"), None));
+        assert!(!rule.matches("", Some("abcxd"), None));
+
+        let rule: Rule = toml::from_str(r#"hidden = true"#).unwrap();
+        assert!(!rule.matches("", None, None));
+    }
+}
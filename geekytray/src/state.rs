@@ -0,0 +1,74 @@
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::tray_item::TrayItemKey;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct State {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected_item: Option<TrayItemKey>,
+    pub items: Vec<TrayItemKey>,
+}
+
+impl State {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let toml_string = fs::read_to_string(path).context("read state file")?;
+        let state = toml::from_str(&toml_string).context("parse state file")?;
+        Ok(state)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("create state directory")?;
+        }
+        let toml_string = toml::to_string(self).context("serialize state")?;
+        fs::write(path, toml_string).context("write state file")?;
+        Ok(())
+    }
+}
+
+pub fn get_state_path() -> Option<PathBuf> {
+    env::var("XDG_STATE_HOME")
+        .map(|state_dir| Path::new(&state_dir).to_path_buf())
+        .or_else(|_| {
+            env::var("HOME").map(|home_dir| Path::new(&home_dir).join(".local").join("state"))
+        })
+        .map(|state_dir| state_dir.join("geekytray").join("state.toml"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let state = State {
+            selected_item: Some(TrayItemKey {
+                class: Some("Steam".to_owned()),
+                process: None,
+            }),
+            items: vec![
+                TrayItemKey {
+                    class: Some("Steam".to_owned()),
+                    process: None,
+                },
+                TrayItemKey {
+                    class: Some("Nm-applet".to_owned()),
+                    process: Some("nm-applet".to_owned()),
+                },
+            ],
+        };
+        let toml_string = toml::to_string(&state).unwrap();
+        assert_eq!(toml::from_str::<State>(&toml_string).unwrap(), state);
+    }
+}
@@ -0,0 +1,718 @@
+use geekytray_shell::dbus;
+use geekytray_shell::event::{MouseButton, ScrollDirection};
+use geekytray_shell::graphics::Image;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+
+use crate::dbus_menu::{DBusMenu, DBusMenuItem, MenuRequest, DBUSMENU_INTERFACE};
+
+pub const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+pub const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+pub const WATCHER_INTERFACE: &str = "org.kde.StatusNotifierWatcher";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const DEFAULT_ITEM_PATH: &str = "/StatusNotifierItem";
+
+const DBUS_NAME_FLAG_DO_NOT_QUEUE: u32 = 0x4;
+
+const ICON_SIZES: [u32; 10] = [16, 22, 24, 32, 36, 48, 64, 96, 128, 256];
+const ICON_CONTEXTS: [&str; 5] = ["apps", "status", "devices", "categories", "panel"];
+
+#[derive(Debug)]
+pub struct StatusNotifierHost {
+    connection: Rc<dbus::Connection>,
+    host_name: String,
+    icon_size: u32,
+    items: HashMap<String, StatusNotifierItem>,
+    pending_calls: HashMap<u32, PendingCall>,
+}
+
+impl StatusNotifierHost {
+    pub fn new(connection: Rc<dbus::Connection>, icon_size: u32) -> Result<Self, dbus::Error> {
+        let host_name = format!("org.kde.StatusNotifierHost-{}-1", process::id());
+        connection.request_name(&host_name, DBUS_NAME_FLAG_DO_NOT_QUEUE)?;
+
+        connection.add_match(&format!("type='signal',interface='{}'", WATCHER_INTERFACE))?;
+        connection.add_match(&format!("type='signal',interface='{}'", ITEM_INTERFACE))?;
+        connection.add_match(&format!("type='signal',interface='{}'", DBUSMENU_INTERFACE))?;
+        connection.add_match(&format!(
+            "type='signal',sender='{}',interface='{}',member='NameOwnerChanged'",
+            dbus::BUS_NAME,
+            dbus::BUS_INTERFACE
+        ))?;
+
+        Ok(Self {
+            connection,
+            host_name,
+            icon_size,
+            items: HashMap::new(),
+            pending_calls: HashMap::new(),
+        })
+    }
+
+    pub fn register_host(&mut self) -> Result<(), dbus::Error> {
+        if !self.connection.name_has_owner(WATCHER_NAME)? {
+            log::warn!("StatusNotifierWatcher is not running, wait for it to appear");
+            return Ok(());
+        }
+
+        log::info!("Register StatusNotifierHost `{}`", self.host_name);
+
+        let serial = self.connection.send(
+            dbus::Message::method_call(
+                WATCHER_NAME,
+                WATCHER_PATH,
+                WATCHER_INTERFACE,
+                "RegisterStatusNotifierHost",
+            )
+            .with_body(vec![self.host_name.as_str().into()]),
+        )?;
+        self.pending_calls.insert(serial, PendingCall::RegisterHost);
+
+        let serial = self.connection.send(
+            dbus::Message::method_call(
+                WATCHER_NAME,
+                WATCHER_PATH,
+                dbus::PROPERTIES_INTERFACE,
+                "Get",
+            )
+            .with_body(vec![
+                WATCHER_INTERFACE.into(),
+                "RegisteredStatusNotifierItems".into(),
+            ]),
+        )?;
+        self.pending_calls
+            .insert(serial, PendingCall::RegisteredItems);
+
+        Ok(())
+    }
+
+    pub fn process_message(
+        &mut self,
+        message: &dbus::Message,
+    ) -> Result<Vec<StatusNotifierEvent>, dbus::Error> {
+        let mut events = Vec::new();
+
+        if let Some(pending_call) = message
+            .reply_serial()
+            .and_then(|serial| self.pending_calls.remove(&serial))
+        {
+            match (pending_call, message.message_type()) {
+                (PendingCall::RegisterHost, dbus::MessageType::Error) => {
+                    log::warn!(
+                        "Failed to register StatusNotifierHost: {}",
+                        message.clone().into_result().unwrap_err()
+                    );
+                }
+                (PendingCall::RegisteredItems, dbus::MessageType::MethodReturn) => {
+                    let services = message
+                        .body()
+                        .first()
+                        .and_then(dbus::Value::as_array)
+                        .unwrap_or_default();
+                    for service in services.iter().filter_map(dbus::Value::as_str) {
+                        if !self.items.contains_key(service) {
+                            self.request_item_properties(service)?;
+                        }
+                    }
+                }
+                (PendingCall::ItemProperties(service), dbus::MessageType::MethodReturn) => {
+                    let properties = message.body().first();
+                    let sender = message.sender().unwrap_or_default();
+                    if let Some(item) = self.items.get_mut(&service) {
+                        if let Some(properties) = properties {
+                            item.update_properties(properties, self.icon_size);
+                        }
+                        events.push(StatusNotifierEvent::Updated(item.clone()));
+                    } else {
+                        let mut item = StatusNotifierItem::new(
+                            self.connection.clone(),
+                            service.clone(),
+                            sender.to_owned(),
+                        );
+                        if let Some(properties) = properties {
+                            item.update_properties(properties, self.icon_size);
+                        }
+                        log::info!("StatusNotifierItem `{}` is added", service);
+                        self.items.insert(service, item.clone());
+                        events.push(StatusNotifierEvent::Added(item));
+                    }
+                }
+                (PendingCall::ItemProperties(service), dbus::MessageType::Error) => {
+                    log::warn!(
+                        "Failed to get properties of StatusNotifierItem `{}`: {}",
+                        service,
+                        message.clone().into_result().unwrap_err()
+                    );
+                }
+                (
+                    PendingCall::Menu(MenuRequest::GetLayout(menu)),
+                    dbus::MessageType::MethodReturn,
+                ) => match DBusMenuItem::from_layout_reply(message) {
+                    Some(root) => events.push(StatusNotifierEvent::MenuLayoutReceived(menu, root)),
+                    None => {
+                        log::warn!("GetLayout reply does not contain a layout");
+                        events.push(StatusNotifierEvent::MenuLayoutFailed(menu));
+                    }
+                },
+                (PendingCall::Menu(MenuRequest::GetLayout(menu)), dbus::MessageType::Error) => {
+                    log::warn!(
+                        "Failed to get the menu layout: {}",
+                        message.clone().into_result().unwrap_err()
+                    );
+                    events.push(StatusNotifierEvent::MenuLayoutFailed(menu));
+                }
+                (
+                    PendingCall::Menu(MenuRequest::AboutToShow(menu, _)),
+                    dbus::MessageType::MethodReturn,
+                ) => {
+                    let needs_update = message
+                        .body()
+                        .first()
+                        .and_then(dbus::Value::as_bool)
+                        .unwrap_or(false);
+                    if needs_update {
+                        self.request_menu(MenuRequest::GetLayout(menu))?;
+                    }
+                }
+                (PendingCall::Menu(MenuRequest::AboutToShow(_, id)), dbus::MessageType::Error) => {
+                    log::warn!(
+                        "Failed to prepare submenu {}: {}",
+                        id,
+                        message.clone().into_result().unwrap_err()
+                    );
+                }
+                _ => {}
+            }
+            return Ok(events);
+        }
+
+        if message.is_signal(WATCHER_INTERFACE, "StatusNotifierItemRegistered") {
+            if let Some(service) = message.body().first().and_then(dbus::Value::as_str) {
+                self.request_item_properties(service)?;
+            }
+        } else if message.is_signal(WATCHER_INTERFACE, "StatusNotifierItemUnregistered") {
+            if let Some(service) = message.body().first().and_then(dbus::Value::as_str) {
+                if let Some(item) = self.items.remove(service) {
+                    log::info!("StatusNotifierItem `{}` is removed", service);
+                    events.push(StatusNotifierEvent::Removed(item));
+                }
+            }
+        } else if message.interface() == Some(ITEM_INTERFACE)
+            && message.message_type() == dbus::MessageType::Signal
+        {
+            let sender = message.sender().unwrap_or_default();
+            let path = message.path().unwrap_or_default();
+            if let Some(service) = self
+                .items
+                .values()
+                .find(|item| item.unique_name == sender && item.object_path == path)
+                .map(|item| item.service.clone())
+            {
+                self.request_item_properties(&service)?;
+            }
+        } else if message.is_signal(dbus::BUS_INTERFACE, "NameOwnerChanged") {
+            if let [name, _old_owner, new_owner] = message.body() {
+                let name = name.as_str().unwrap_or_default();
+                let new_owner = new_owner.as_str().unwrap_or_default();
+                if name == WATCHER_NAME && !new_owner.is_empty() {
+                    self.register_host()?;
+                } else if new_owner.is_empty() {
+                    let vanished_services: Vec<String> = self
+                        .items
+                        .values()
+                        .filter(|item| item.unique_name == name || item.bus_name == name)
+                        .map(|item| item.service.clone())
+                        .collect();
+                    for service in vanished_services {
+                        if let Some(item) = self.items.remove(&service) {
+                            log::info!("StatusNotifierItem `{}` is vanished", service);
+                            events.push(StatusNotifierEvent::Removed(item));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    // Fetches the icons of all items again if the size is changed.
+    pub fn set_icon_size(&mut self, icon_size: u32) -> Result<(), dbus::Error> {
+        if self.icon_size == icon_size {
+            return Ok(());
+        }
+        self.icon_size = icon_size;
+        let services: Vec<String> = self.items.keys().cloned().collect();
+        for service in services {
+            self.request_item_properties(&service)?;
+        }
+        Ok(())
+    }
+
+    pub fn request_menu(&mut self, request: MenuRequest) -> Result<(), dbus::Error> {
+        let serial = self.connection.send(request.to_message())?;
+        self.pending_calls
+            .insert(serial, PendingCall::Menu(request));
+        Ok(())
+    }
+
+    fn request_item_properties(&mut self, service: &str) -> Result<(), dbus::Error> {
+        let is_pending = self.pending_calls.values().any(|pending_call| {
+            matches!(pending_call, PendingCall::ItemProperties(pending_service) if pending_service == service)
+        });
+        if is_pending {
+            return Ok(());
+        }
+
+        let (bus_name, object_path) = parse_service(service);
+        let serial = self.connection.send(
+            dbus::Message::method_call(bus_name, object_path, dbus::PROPERTIES_INTERFACE, "GetAll")
+                .with_body(vec![ITEM_INTERFACE.into()]),
+        )?;
+        self.pending_calls
+            .insert(serial, PendingCall::ItemProperties(service.to_owned()));
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum PendingCall {
+    RegisterHost,
+    RegisteredItems,
+    ItemProperties(String),
+    Menu(MenuRequest),
+}
+
+#[derive(Debug)]
+pub enum StatusNotifierEvent {
+    Added(StatusNotifierItem),
+    Updated(StatusNotifierItem),
+    Removed(StatusNotifierItem),
+    MenuLayoutReceived(DBusMenu, DBusMenuItem),
+    MenuLayoutFailed(DBusMenu),
+}
+
+#[derive(Clone, Debug)]
+pub struct StatusNotifierItem {
+    connection: Rc<dbus::Connection>,
+    service: String,
+    bus_name: String,
+    object_path: String,
+    unique_name: String,
+    id: String,
+    title: String,
+    tooltip_title: String,
+    icon: Option<Rc<Image>>,
+    item_is_menu: bool,
+    menu_path: String,
+    version: u32,
+}
+
+impl StatusNotifierItem {
+    fn new(connection: Rc<dbus::Connection>, service: String, unique_name: String) -> Self {
+        let (bus_name, object_path) = parse_service(&service);
+        let (bus_name, object_path) = (bus_name.to_owned(), object_path.to_owned());
+        Self {
+            connection,
+            service,
+            bus_name,
+            object_path,
+            unique_name,
+            id: String::new(),
+            title: String::new(),
+            tooltip_title: String::new(),
+            icon: None,
+            item_is_menu: false,
+            menu_path: String::new(),
+            version: 0,
+        }
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn title(&self) -> &str {
+        if !self.title.is_empty() {
+            &self.title
+        } else if !self.tooltip_title.is_empty() {
+            &self.tooltip_title
+        } else {
+            &self.id
+        }
+    }
+
+    pub fn icon(&self) -> Option<&Rc<Image>> {
+        self.icon.as_ref()
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn context_menu(&self, button: MouseButton) -> Option<DBusMenu> {
+        let is_menu_button = match button {
+            MouseButton::Left => self.item_is_menu,
+            MouseButton::Right => true,
+            _ => false,
+        };
+        if !is_menu_button || matches!(self.menu_path.as_str(), "" | "/" | "/NO_DBUSMENU") {
+            return None;
+        }
+        Some(DBusMenu::new(
+            self.connection.clone(),
+            self.unique_name.clone(),
+            self.menu_path.clone(),
+        ))
+    }
+
+    pub fn click(&self, button: MouseButton, x: i32, y: i32) -> Result<(), dbus::Error> {
+        let method = match button {
+            MouseButton::Left if self.item_is_menu => "ContextMenu",
+            MouseButton::Left => "Activate",
+            MouseButton::Middle => "SecondaryActivate",
+            MouseButton::Right => "ContextMenu",
+            MouseButton::X1 | MouseButton::X2 => return Ok(()),
+        };
+
+        log::info!(
+            "Call {} on StatusNotifierItem `{}` at ({}, {})",
+            method,
+            self.service,
+            x,
+            y
+        );
+
+        self.connection.send(
+            dbus::Message::method_call(&self.bus_name, &self.object_path, ITEM_INTERFACE, method)
+                .with_body(vec![x.into(), y.into()])
+                .without_reply(),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn scroll(&self, direction: ScrollDirection, steps: usize) -> Result<(), dbus::Error> {
+        let (delta, orientation) = match direction {
+            ScrollDirection::Up => (-(steps as i32), "vertical"),
+            ScrollDirection::Down => (steps as i32, "vertical"),
+            ScrollDirection::Left => (-(steps as i32), "horizontal"),
+            ScrollDirection::Right => (steps as i32, "horizontal"),
+        };
+
+        log::info!(
+            "Call Scroll on StatusNotifierItem `{}` with {} {}",
+            self.service,
+            delta,
+            orientation
+        );
+
+        self.connection.send(
+            dbus::Message::method_call(&self.bus_name, &self.object_path, ITEM_INTERFACE, "Scroll")
+                .with_body(vec![delta.into(), orientation.into()])
+                .without_reply(),
+        )?;
+
+        Ok(())
+    }
+
+    fn update_properties(&mut self, properties: &dbus::Value, icon_size: u32) {
+        let get_str = |key: &str| {
+            properties
+                .dict_get(key)
+                .and_then(dbus::Value::as_str)
+                .unwrap_or_default()
+                .to_owned()
+        };
+
+        self.id = get_str("Id");
+        self.title = get_str("Title");
+        self.tooltip_title = properties
+            .dict_get("ToolTip")
+            .and_then(dbus::Value::as_struct)
+            .and_then(|fields| fields.get(2))
+            .and_then(dbus::Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+        self.item_is_menu = properties
+            .dict_get("ItemIsMenu")
+            .and_then(dbus::Value::as_bool)
+            .unwrap_or(false);
+
+        self.menu_path = get_str("Menu");
+
+        let is_attention = get_str("Status") == "NeedsAttention";
+        let (name_key, pixmap_key) = if is_attention {
+            ("AttentionIconName", "AttentionIconPixmap")
+        } else {
+            ("IconName", "IconPixmap")
+        };
+        let icon_theme_path = get_str("IconThemePath");
+        self.icon = lookup_icon(&get_str(name_key), &icon_theme_path, icon_size)
+            .and_then(Image::from_png)
+            .or_else(|| select_icon_pixmap(properties.dict_get(pixmap_key)?, icon_size))
+            .map(Rc::new);
+
+        self.version = self.version.wrapping_add(1);
+    }
+}
+
+fn parse_service(service: &str) -> (&str, &str) {
+    match service.find('/') {
+        Some(index) => (&service[..index], &service[index..]),
+        None => (service, DEFAULT_ITEM_PATH),
+    }
+}
+
+fn select_icon_pixmap(pixmaps: &dbus::Value, icon_size: u32) -> Option<Image> {
+    pixmaps
+        .as_array()?
+        .iter()
+        .filter_map(|pixmap| match pixmap.as_struct()? {
+            [width, height, bytes] => Image::from_argb32_be(
+                width.as_i32()?.try_into().ok()?,
+                height.as_i32()?.try_into().ok()?,
+                bytes.as_bytes()?,
+            ),
+            _ => None,
+        })
+        .min_by_key(|image| {
+            // Prefer the smallest image that is not smaller than the icon size.
+            let size = image.width().max(image.height());
+            if size >= icon_size {
+                (0, size)
+            } else {
+                (1, u32::MAX - size)
+            }
+        })
+}
+
+fn lookup_icon(name: &str, icon_theme_path: &str, icon_size: u32) -> Option<PathBuf> {
+    if name.is_empty() {
+        return None;
+    }
+
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return Some(path.to_path_buf()).filter(|path| path.is_file());
+    }
+
+    let mut base_dirs = Vec::new();
+    if !icon_theme_path.is_empty() {
+        base_dirs.push(PathBuf::from(icon_theme_path));
+    }
+    if let Some(data_home) = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+    {
+        base_dirs.push(data_home.join("icons"));
+    }
+    let data_dirs =
+        env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".to_owned());
+    for data_dir in data_dirs.split(':').filter(|dir| !dir.is_empty()) {
+        base_dirs.push(Path::new(data_dir).join("icons"));
+    }
+
+    let mut sizes = ICON_SIZES.to_vec();
+    sizes.sort_by_key(|size| {
+        if *size >= icon_size {
+            (0, *size)
+        } else {
+            (1, u32::MAX - *size)
+        }
+    });
+
+    let file_name = format!("{}.png", name);
+
+    for base_dir in &base_dirs {
+        let path = base_dir.join(&file_name);
+        if path.is_file() {
+            return Some(path);
+        }
+        for size in &sizes {
+            for context in ICON_CONTEXTS {
+                let path = base_dir
+                    .join("hicolor")
+                    .join(format!("{}x{}", size, size))
+                    .join(context)
+                    .join(&file_name);
+                if path.is_file() {
+                    return Some(path);
+                }
+            }
+        }
+    }
+
+    Some(Path::new("/usr/share/pixmaps").join(&file_name)).filter(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_bus::{pump, TestBus};
+
+    const ITEM_NAME: &str = "org.geekytray.TestItem";
+
+    #[test]
+    fn test_host_on_private_bus() {
+        let bus = TestBus::spawn();
+        let watcher_connection = bus.connect();
+        let item_connection = bus.connect();
+        let host_connection = bus.connect();
+        assert_eq!(watcher_connection.request_name(WATCHER_NAME, 0).unwrap(), 1);
+        assert_eq!(item_connection.request_name(ITEM_NAME, 0).unwrap(), 1);
+
+        let mut host = StatusNotifierHost::new(host_connection.clone(), 16).unwrap();
+        host.register_host().unwrap();
+
+        let mut registered_hosts = Vec::new();
+        let mut events = Vec::new();
+        pump(&mut [
+            (&watcher_connection, &mut |message| {
+                if message.is_method_call(WATCHER_INTERFACE, "RegisterStatusNotifierHost") {
+                    registered_hosts.push(message.body()[0].as_str().unwrap().to_owned());
+                    watcher_connection
+                        .send(dbus::Message::method_return(message))
+                        .unwrap();
+                } else if message.is_method_call(dbus::PROPERTIES_INTERFACE, "Get") {
+                    watcher_connection
+                        .send(dbus::Message::method_return(message).with_body(vec![
+                            dbus::Value::variant(dbus::Value::Array(
+                                "s".to_owned(),
+                                vec![ITEM_NAME.into()],
+                            )),
+                        ]))
+                        .unwrap();
+                }
+                false
+            }),
+            (&item_connection, &mut |message| {
+                if message.is_method_call(dbus::PROPERTIES_INTERFACE, "GetAll") {
+                    assert_eq!(message.path(), Some(DEFAULT_ITEM_PATH));
+                    assert_eq!(message.body()[0].as_str(), Some(ITEM_INTERFACE));
+                    item_connection
+                        .send(dbus::Message::method_return(message).with_body(vec![
+                            dbus::Value::dict(
+                                "s",
+                                "v",
+                                vec![
+                                    ("Id".into(), dbus::Value::variant("test-item")),
+                                    ("Title".into(), dbus::Value::variant("Test Item")),
+                                    ("ItemIsMenu".into(), dbus::Value::variant(true)),
+                                    ("Menu".into(), dbus::Value::variant("/MenuBar")),
+                                ],
+                            ),
+                        ]))
+                        .unwrap();
+                }
+                false
+            }),
+            (&host_connection, &mut |message| {
+                events.extend(host.process_message(message).unwrap());
+                !events.is_empty()
+            }),
+        ]);
+
+        assert_eq!(registered_hosts, vec![host.host_name.clone()]);
+        match events.as_slice() {
+            [StatusNotifierEvent::Added(item)] => {
+                assert_eq!(item.service(), ITEM_NAME);
+                assert_eq!(item.id(), "test-item");
+                assert_eq!(item.title(), "Test Item");
+                assert!(item.context_menu(MouseButton::Left).is_some());
+                assert!(item.context_menu(MouseButton::Middle).is_none());
+            }
+            events => panic!("unexpected events: {:?}", events),
+        }
+
+        drop(item_connection);
+
+        let mut events = Vec::new();
+        pump(&mut [(&host_connection, &mut |message| {
+            events.extend(host.process_message(message).unwrap());
+            !events.is_empty()
+        })]);
+
+        match events.as_slice() {
+            [StatusNotifierEvent::Removed(item)] => assert_eq!(item.service(), ITEM_NAME),
+            events => panic!("unexpected events: {:?}", events),
+        }
+        assert!(host.items.is_empty());
+    }
+
+    #[test]
+    fn test_menu_requests_on_private_bus() {
+        let bus = TestBus::spawn();
+        let item_connection = bus.connect();
+        let host_connection = bus.connect();
+
+        let mut host = StatusNotifierHost::new(host_connection.clone(), 16).unwrap();
+        let menu = DBusMenu::new(
+            host_connection.clone(),
+            item_connection.unique_name().to_owned(),
+            "/MenuBar".to_owned(),
+        );
+        host.request_menu(MenuRequest::AboutToShow(menu.clone(), 1))
+            .unwrap();
+
+        let mut events = Vec::new();
+        pump(&mut [
+            (&item_connection, &mut |message| {
+                let reply = dbus::Message::method_return(message);
+                if message.is_method_call(DBUSMENU_INTERFACE, "AboutToShow") {
+                    assert_eq!(message.body()[0].as_i32(), Some(1));
+                    item_connection
+                        .send(reply.with_body(vec![true.into()]))
+                        .unwrap();
+                } else if message.is_method_call(DBUSMENU_INTERFACE, "GetLayout") {
+                    let layout = dbus::Value::Struct(vec![
+                        0.into(),
+                        dbus::Value::dict("s", "v", Vec::new()),
+                        dbus::Value::Array("v".to_owned(), Vec::new()),
+                    ]);
+                    item_connection
+                        .send(reply.with_body(vec![1u32.into(), layout]))
+                        .unwrap();
+                }
+                false
+            }),
+            (&host_connection, &mut |message| {
+                events.extend(host.process_message(message).unwrap());
+                !events.is_empty()
+            }),
+        ]);
+
+        match events.as_slice() {
+            [StatusNotifierEvent::MenuLayoutReceived(received_menu, root)] => {
+                assert_eq!(received_menu, &menu);
+                assert_eq!(root.id, 0);
+            }
+            events => panic!("unexpected events: {:?}", events),
+        }
+
+        drop(item_connection);
+        host.request_menu(MenuRequest::GetLayout(menu.clone()))
+            .unwrap();
+
+        let mut events = Vec::new();
+        pump(&mut [(&host_connection, &mut |message| {
+            events.extend(host.process_message(message).unwrap());
+            !events.is_empty()
+        })]);
+
+        match events.as_slice() {
+            [StatusNotifierEvent::MenuLayoutFailed(failed_menu)] => {
+                assert_eq!(failed_menu, &menu)
+            }
+            events => panic!("unexpected events: {:?}", events),
+        }
+    }
+}
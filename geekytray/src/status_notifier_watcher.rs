@@ -0,0 +1,444 @@
+use geekytray_shell::dbus;
+use std::rc::Rc;
+
+use crate::status_notifier_host::{WATCHER_INTERFACE, WATCHER_NAME, WATCHER_PATH};
+
+const PROTOCOL_VERSION: i32 = 0;
+
+const DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER: u32 = 1;
+
+const ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const ERROR_UNKNOWN_PROPERTY: &str = "org.freedesktop.DBus.Error.UnknownProperty";
+const ERROR_INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+
+#[derive(Debug)]
+pub struct StatusNotifierWatcher {
+    connection: Rc<dbus::Connection>,
+    is_active: bool,
+    items: Vec<RegisteredName>,
+    hosts: Vec<RegisteredName>,
+}
+
+impl StatusNotifierWatcher {
+    pub fn new(connection: Rc<dbus::Connection>) -> Result<Self, dbus::Error> {
+        connection.add_match(&format!(
+            "type='signal',sender='{}',interface='{}',member='NameOwnerChanged'",
+            dbus::BUS_NAME,
+            dbus::BUS_INTERFACE
+        ))?;
+
+        // Wait in the queue if someone else owns the name, so that the watcher
+        // takes over when it goes away.
+        let is_active =
+            connection.request_name(WATCHER_NAME, 0)? == DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER;
+        if is_active {
+            log::info!("Start built-in StatusNotifierWatcher");
+        } else {
+            log::info!("StatusNotifierWatcher is already running, wait in queue");
+        }
+
+        Ok(Self {
+            connection,
+            is_active,
+            items: Vec::new(),
+            hosts: Vec::new(),
+        })
+    }
+
+    pub fn process_message(&mut self, message: &dbus::Message) -> Result<(), dbus::Error> {
+        match message.message_type() {
+            dbus::MessageType::MethodCall
+                if message.destination() == Some(WATCHER_NAME)
+                    || message.path() == Some(WATCHER_PATH) =>
+            {
+                if self.is_active && message.path() == Some(WATCHER_PATH) {
+                    self.process_method_call(message)?;
+                } else if message.expects_reply() {
+                    self.connection.send(dbus::Message::error(
+                        message,
+                        ERROR_UNKNOWN_METHOD,
+                        "StatusNotifierWatcher is not active",
+                    ))?;
+                }
+            }
+            dbus::MessageType::Signal => {
+                if message.is_signal(dbus::BUS_INTERFACE, "NameAcquired")
+                    && message.body().first().and_then(dbus::Value::as_str) == Some(WATCHER_NAME)
+                {
+                    if !self.is_active {
+                        log::info!("Start built-in StatusNotifierWatcher");
+                        self.is_active = true;
+                    }
+                } else if message.is_signal(dbus::BUS_INTERFACE, "NameLost")
+                    && message.body().first().and_then(dbus::Value::as_str) == Some(WATCHER_NAME)
+                {
+                    log::info!("Stop built-in StatusNotifierWatcher");
+                    self.is_active = false;
+                    self.items.clear();
+                    self.hosts.clear();
+                } else if message.is_signal(dbus::BUS_INTERFACE, "NameOwnerChanged") {
+                    if let [name, _old_owner, new_owner] = message.body() {
+                        let name = name.as_str().unwrap_or_default();
+                        let new_owner = new_owner.as_str().unwrap_or_default();
+                        if new_owner.is_empty() {
+                            self.remove_names(name)?;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn process_method_call(&mut self, message: &dbus::Message) -> Result<(), dbus::Error> {
+        let sender = message.sender().unwrap_or_default();
+        let first_arg = message.body().first().and_then(dbus::Value::as_str);
+
+        let reply = if message.is_method_call(WATCHER_INTERFACE, "RegisterStatusNotifierItem") {
+            match first_arg {
+                Some(service) => {
+                    // The item may pass only the object path, then the service
+                    // is identified by its unique name.
+                    let service = if service.starts_with('/') {
+                        format!("{}{}", sender, service)
+                    } else {
+                        service.to_owned()
+                    };
+                    if !self.items.iter().any(|item| item.service == service) {
+                        log::info!("Register StatusNotifierItem `{}`", service);
+                        self.items.push(RegisteredName {
+                            service: service.clone(),
+                            owner: sender.to_owned(),
+                        });
+                        self.emit_signal(
+                            "StatusNotifierItemRegistered",
+                            vec![service.as_str().into()],
+                        )?;
+                    }
+                    dbus::Message::method_return(message)
+                }
+                None => dbus::Message::error(message, ERROR_INVALID_ARGS, "Expected a string"),
+            }
+        } else if message.is_method_call(WATCHER_INTERFACE, "RegisterStatusNotifierHost") {
+            match first_arg {
+                Some(service) => {
+                    if !self.hosts.iter().any(|host| host.service == service) {
+                        log::info!("Register StatusNotifierHost `{}`", service);
+                        self.hosts.push(RegisteredName {
+                            service: service.to_owned(),
+                            owner: sender.to_owned(),
+                        });
+                        self.emit_signal("StatusNotifierHostRegistered", Vec::new())?;
+                    }
+                    dbus::Message::method_return(message)
+                }
+                None => dbus::Message::error(message, ERROR_INVALID_ARGS, "Expected a string"),
+            }
+        } else if message.is_method_call(dbus::PROPERTIES_INTERFACE, "Get") {
+            match message.body() {
+                [interface, property] if interface.as_str() == Some(WATCHER_INTERFACE) => {
+                    match property
+                        .as_str()
+                        .and_then(|property| self.get_property(property))
+                    {
+                        Some(value) => dbus::Message::method_return(message)
+                            .with_body(vec![dbus::Value::variant(value)]),
+                        None => dbus::Message::error(
+                            message,
+                            ERROR_UNKNOWN_PROPERTY,
+                            "Unknown property",
+                        ),
+                    }
+                }
+                _ => dbus::Message::error(message, ERROR_INVALID_ARGS, "Unknown interface"),
+            }
+        } else if message.is_method_call(dbus::PROPERTIES_INTERFACE, "GetAll") {
+            let properties = if first_arg == Some(WATCHER_INTERFACE) {
+                [
+                    "RegisteredStatusNotifierItems",
+                    "IsStatusNotifierHostRegistered",
+                    "ProtocolVersion",
+                ]
+                .iter()
+                .filter_map(|property| {
+                    let value = self.get_property(property)?;
+                    Some(((*property).into(), dbus::Value::variant(value)))
+                })
+                .collect()
+            } else {
+                Vec::new()
+            };
+            dbus::Message::method_return(message)
+                .with_body(vec![dbus::Value::dict("s", "v", properties)])
+        } else {
+            dbus::Message::error(message, ERROR_UNKNOWN_METHOD, "Unknown method")
+        };
+
+        if message.expects_reply() {
+            self.connection.send(reply)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_property(&self, property: &str) -> Option<dbus::Value> {
+        match property {
+            "RegisteredStatusNotifierItems" => Some(dbus::Value::Array(
+                "s".to_owned(),
+                self.items
+                    .iter()
+                    .map(|item| item.service.as_str().into())
+                    .collect(),
+            )),
+            "IsStatusNotifierHostRegistered" => Some((!self.hosts.is_empty()).into()),
+            "ProtocolVersion" => Some(PROTOCOL_VERSION.into()),
+            _ => None,
+        }
+    }
+
+    fn remove_names(&mut self, name: &str) -> Result<(), dbus::Error> {
+        let (vanished_items, items): (Vec<_>, Vec<_>) = self
+            .items
+            .drain(..)
+            .partition(|item| item.is_owned_by(name));
+        self.items = items;
+
+        for item in vanished_items {
+            log::info!("Unregister StatusNotifierItem `{}`", item.service);
+            self.emit_signal(
+                "StatusNotifierItemUnregistered",
+                vec![item.service.as_str().into()],
+            )?;
+        }
+
+        let (vanished_hosts, hosts): (Vec<_>, Vec<_>) = self
+            .hosts
+            .drain(..)
+            .partition(|host| host.is_owned_by(name));
+        self.hosts = hosts;
+
+        for host in vanished_hosts {
+            log::info!("Unregister StatusNotifierHost `{}`", host.service);
+            self.emit_signal("StatusNotifierHostUnregistered", Vec::new())?;
+        }
+
+        Ok(())
+    }
+
+    fn emit_signal(&self, member: &str, body: Vec<dbus::Value>) -> Result<(), dbus::Error> {
+        self.connection
+            .send(dbus::Message::signal(WATCHER_PATH, WATCHER_INTERFACE, member).with_body(body))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct RegisteredName {
+    service: String,
+    owner: String,
+}
+
+impl RegisteredName {
+    fn is_owned_by(&self, name: &str) -> bool {
+        self.owner == name
+            || self.service == name
+            || self
+                .service
+                .split_once('/')
+                .map_or(false, |(bus_name, _)| bus_name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::test_bus::{pump, TestBus};
+
+    const HOST_NAME: &str = "org.kde.StatusNotifierHost-test";
+
+    #[test]
+    fn test_watcher_on_private_bus() {
+        let bus = TestBus::spawn();
+        let watcher_connection = bus.connect();
+        let item_connection = bus.connect();
+        let host_connection = bus.connect();
+        let observer_connection = bus.connect();
+        observer_connection
+            .add_match(&format!("type='signal',interface='{}'", WATCHER_INTERFACE))
+            .unwrap();
+
+        let mut watcher = StatusNotifierWatcher::new(watcher_connection.clone()).unwrap();
+        assert!(watcher.is_active);
+
+        let item_serial = item_connection
+            .send(
+                dbus::Message::method_call(
+                    WATCHER_NAME,
+                    WATCHER_PATH,
+                    WATCHER_INTERFACE,
+                    "RegisterStatusNotifierItem",
+                )
+                .with_body(vec!["/StatusNotifierItem".into()]),
+            )
+            .unwrap();
+        host_connection
+            .send(
+                dbus::Message::method_call(
+                    WATCHER_NAME,
+                    WATCHER_PATH,
+                    WATCHER_INTERFACE,
+                    "RegisterStatusNotifierHost",
+                )
+                .with_body(vec![HOST_NAME.into()]),
+            )
+            .unwrap();
+        let item_service = format!("{}/StatusNotifierItem", item_connection.unique_name());
+
+        let is_item_registered = Cell::new(false);
+        let mut signals = Vec::new();
+        pump(&mut [
+            (&watcher_connection, &mut |message| {
+                watcher.process_message(message).unwrap();
+                false
+            }),
+            (&item_connection, &mut |message| {
+                if message.reply_serial() == Some(item_serial) {
+                    assert_eq!(message.message_type(), dbus::MessageType::MethodReturn);
+                    is_item_registered.set(true);
+                }
+                false
+            }),
+            (&observer_connection, &mut |message| {
+                if message.interface() == Some(WATCHER_INTERFACE) {
+                    signals.push((
+                        message.member().unwrap_or_default().to_owned(),
+                        message.body().to_vec(),
+                    ));
+                }
+                is_item_registered.get() && signals.len() == 2
+            }),
+        ]);
+
+        signals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(
+            signals,
+            vec![
+                ("StatusNotifierHostRegistered".to_owned(), vec![]),
+                (
+                    "StatusNotifierItemRegistered".to_owned(),
+                    vec![item_service.as_str().into()]
+                ),
+            ]
+        );
+        assert_eq!(
+            get_property(
+                &mut watcher,
+                &observer_connection,
+                "RegisteredStatusNotifierItems"
+            ),
+            dbus::Value::variant(dbus::Value::Array(
+                "s".to_owned(),
+                vec![item_service.as_str().into()]
+            ))
+        );
+        assert_eq!(
+            get_property(
+                &mut watcher,
+                &observer_connection,
+                "IsStatusNotifierHostRegistered"
+            ),
+            dbus::Value::variant(true)
+        );
+
+        drop(item_connection);
+        drop(host_connection);
+
+        let mut signals = Vec::new();
+        pump(&mut [
+            (&watcher_connection, &mut |message| {
+                watcher.process_message(message).unwrap();
+                false
+            }),
+            (&observer_connection, &mut |message| {
+                if message.interface() == Some(WATCHER_INTERFACE) {
+                    signals.push((
+                        message.member().unwrap_or_default().to_owned(),
+                        message.body().to_vec(),
+                    ));
+                }
+                signals.len() == 2
+            }),
+        ]);
+
+        signals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(
+            signals,
+            vec![
+                ("StatusNotifierHostUnregistered".to_owned(), vec![]),
+                (
+                    "StatusNotifierItemUnregistered".to_owned(),
+                    vec![item_service.as_str().into()]
+                ),
+            ]
+        );
+        assert_eq!(
+            get_property(
+                &mut watcher,
+                &observer_connection,
+                "RegisteredStatusNotifierItems"
+            ),
+            dbus::Value::variant(dbus::Value::Array("s".to_owned(), vec![]))
+        );
+        assert_eq!(
+            get_property(
+                &mut watcher,
+                &observer_connection,
+                "IsStatusNotifierHostRegistered"
+            ),
+            dbus::Value::variant(false)
+        );
+    }
+
+    fn get_property(
+        watcher: &mut StatusNotifierWatcher,
+        connection: &dbus::Connection,
+        property: &str,
+    ) -> dbus::Value {
+        let serial = connection
+            .send(
+                dbus::Message::method_call(
+                    WATCHER_NAME,
+                    WATCHER_PATH,
+                    dbus::PROPERTIES_INTERFACE,
+                    "Get",
+                )
+                .with_body(vec![WATCHER_INTERFACE.into(), property.into()]),
+            )
+            .unwrap();
+        let watcher_connection = watcher.connection.clone();
+        let mut value = None;
+        pump(&mut [
+            (&watcher_connection, &mut |message| {
+                watcher.process_message(message).unwrap();
+                false
+            }),
+            (connection, &mut |message| {
+                if message.reply_serial() == Some(serial) {
+                    value = message
+                        .clone()
+                        .into_result()
+                        .unwrap()
+                        .body()
+                        .first()
+                        .cloned();
+                }
+                value.is_some()
+            }),
+        ]);
+        value.unwrap()
+    }
+}
@@ -0,0 +1,68 @@
+use geekytray_shell::dbus;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PUMP_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type MessageHandler<'a> = &'a mut dyn FnMut(&dbus::Message) -> bool;
+
+// A private dbus-daemon that lives as long as the test.
+pub struct TestBus {
+    daemon: Child,
+    address: String,
+}
+
+impl TestBus {
+    pub fn spawn() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("dbus-daemon is required to run D-Bus tests");
+        let mut stdout = daemon.stdout.take().unwrap();
+        let mut address = String::new();
+        let mut byte = [0u8; 1];
+        while stdout.read(&mut byte).unwrap() > 0 && byte[0] != b'\n' {
+            address.push(byte[0] as char);
+        }
+        assert!(!address.is_empty(), "dbus-daemon did not print its address");
+        Self { daemon, address }
+    }
+
+    pub fn connect(&self) -> Rc<dbus::Connection> {
+        Rc::new(dbus::Connection::open(&self.address).unwrap())
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        self.daemon.kill().ok();
+        self.daemon.wait().ok();
+    }
+}
+
+// Dispatches the messages of every connection to its handler until the
+// handlers report that they are done.
+pub fn pump(connections: &mut [(&dbus::Connection, MessageHandler)]) {
+    let deadline = Instant::now() + PUMP_TIMEOUT;
+    loop {
+        let mut is_done = false;
+        for (connection, handler) in connections.iter_mut() {
+            while let Some(message) = connection.poll_message().unwrap() {
+                is_done |= handler(&message);
+            }
+        }
+        if is_done {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "timed out pumping D-Bus messages"
+        );
+        thread::sleep(Duration::from_millis(1));
+    }
+}
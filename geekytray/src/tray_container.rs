@@ -1,10 +1,13 @@
-use geekytray_shell::event::MouseButton;
+use geekytray_shell::dbus;
+use geekytray_shell::event::{Modifiers, MouseButton, ScrollDirection};
 use geekytray_shell::geometrics::{PhysicalPoint, PhysicalSize, Point, Rect, Size};
 use geekytray_shell::graphics::{
     FontDescription, HorizontalAlign, RenderContext, RenderOp, Text, VerticalAlign,
 };
 use geekytray_shell::window::{Effect, Layout, Widget};
+use std::mem;
 use std::rc::Rc;
+use std::time::Duration;
 use x11rb::connection::Connection as _;
 use x11rb::errors::ReplyError;
 use x11rb::properties;
@@ -13,165 +16,1133 @@ use x11rb::protocol::xproto;
 use x11rb::protocol::xproto::ConnectionExt as _;
 use x11rb::xcb_ffi::XCBConnection;
 
-use crate::config::UiConfig;
-use crate::tray_item::TrayItem;
-use crate::tray_manager::TrayIcon;
+use crate::command::ClickPosition;
+use crate::config::{FilterAction, ItemLayout, Orientation, UiConfig};
+use crate::dbus_menu::{DBusMenu, DBusMenuItem, MenuRequest};
+use crate::fuzzy::fuzzy_match;
+use crate::hint::hint_labels;
+use crate::menu_list::MenuList;
+use crate::rule::Rule;
+use crate::state::State;
+use crate::tray_item::{TrayItem, TrayItemIcon, TrayItemId, TrayItemKey};
+
+const SCROLL_INDICATOR_ALPHA: u8 = 0x60;
+const WHEEL_UP_BUTTON: u8 = 4;
+const WHEEL_DOWN_BUTTON: u8 = 5;
+// The maximum number of item keys remembered for the order. The oldest keys of
+// absent items are forgotten first.
+const MAX_ITEM_ORDER: usize = 256;
 
 #[derive(Debug)]
 pub struct TrayContainer {
     tray_items: Vec<TrayItem>,
+    hidden_items: Vec<TrayItem>,
+    rules: Vec<Rule>,
+    item_order: Vec<TrayItemKey>,
+    is_item_order_changed: bool,
+    restored_selection: Option<TrayItemKey>,
     selected_index: Option<usize>,
+    menu: Option<MenuList>,
+    pending_menu: Option<PendingMenu>,
+    menu_requests: Vec<MenuRequest>,
+    filter: Option<Filter>,
+    hint: Option<Hint>,
+    max_height: f64,
+    scroll_offset: f64,
     config: Rc<UiConfig>,
     item_font: FontDescription,
     selected_item_font: FontDescription,
 }
 
 impl TrayContainer {
-    pub fn new(config: Rc<UiConfig>) -> TrayContainer {
-        let item_font = FontDescription::new(
-            config.item_font.family.clone(),
-            config.item_font.style,
-            config.item_font.weight,
-            config.item_font.stretch,
-        );
-        let selected_item_font = if config.item_font == config.selected_item_font {
-            item_font.clone()
-        } else {
-            FontDescription::new(
-                config.selected_item_font.family.clone(),
-                config.selected_item_font.style,
-                config.selected_item_font.weight,
-                config.selected_item_font.stretch,
-            )
-        };
+    pub fn new(config: Rc<UiConfig>, rules: Vec<Rule>, max_height: f64) -> TrayContainer {
+        let (item_font, selected_item_font) = item_fonts(&config);
         Self {
             tray_items: Vec::new(),
+            hidden_items: Vec::new(),
+            rules,
+            item_order: Vec::new(),
+            is_item_order_changed: false,
+            restored_selection: None,
             selected_index: None,
+            menu: None,
+            pending_menu: None,
+            menu_requests: Vec::new(),
+            filter: None,
+            hint: None,
+            max_height,
+            scroll_offset: 0.0,
             config,
             item_font,
             selected_item_font,
         }
     }
 
-    pub fn add_tray_item(&mut self, icon: TrayIcon) -> Effect {
+    pub fn add_tray_item(&mut self, icon: impl Into<TrayItemIcon>) -> Effect {
+        let icon = icon.into();
         if self
             .tray_items
             .iter()
-            .find(|tray_item| tray_item.window() == icon.window())
-            .is_some()
+            .chain(self.hidden_items.iter())
+            .any(|tray_item| tray_item.id() == icon.id())
         {
             Effect::None
         } else {
-            let tray_item = TrayItem::new(
-                icon,
-                self.item_font.clone(),
-                self.selected_item_font.clone(),
-                self.config.clone(),
-            );
+            self.insert_tray_item(icon)
+        }
+    }
+
+    pub fn update_tray_item(&mut self, icon: impl Into<TrayItemIcon>) -> Effect {
+        let icon = icon.into();
+        if let Some(index) = self
+            .tray_items
+            .iter()
+            .position(|tray_item| tray_item.id() == icon.id())
+        {
+            let rule_index = find_rule(&self.rules, &icon);
+            let tray_item = &mut self.tray_items[index];
+            let mut effect = tray_item.update_icon(icon);
+            if tray_item.rule_index() != rule_index {
+                tray_item.set_rule(rule_index.map(|index| (index, &self.rules[index])));
+                effect = effect + Effect::RequestLayout;
+                if rule_index.map_or(false, |index| self.rules[index].is_hidden()) {
+                    let (tray_item, detach_effect) = self.detach_tray_item(index);
+                    self.hidden_items.push(tray_item);
+                    effect = effect + detach_effect;
+                } else {
+                    self.arrange_tray_items();
+                }
+            } else if self.filter.is_some() {
+                self.update_visible_items();
+                effect = effect + Effect::RequestLayout;
+            }
+            effect
+        } else if let Some(index) = self
+            .hidden_items
+            .iter()
+            .position(|tray_item| tray_item.id() == icon.id())
+        {
+            let rule_index = find_rule(&self.rules, &icon);
+            let tray_item = &mut self.hidden_items[index];
+            let mut effect = tray_item.update_icon(icon);
+            if tray_item.rule_index() != rule_index {
+                tray_item.set_rule(rule_index.map(|index| (index, &self.rules[index])));
+                if !rule_index.map_or(false, |index| self.rules[index].is_hidden()) {
+                    let tray_item = self.hidden_items.remove(index);
+                    self.tray_items.push(tray_item);
+                    self.arrange_tray_items();
+                    effect = effect + Effect::RequestLayout;
+                }
+            }
+            effect
+        } else {
+            self.insert_tray_item(icon)
+        }
+    }
+
+    pub fn remove_tray_item(&mut self, id: &TrayItemId) -> Effect {
+        if let Some(index) = self
+            .tray_items
+            .iter()
+            .position(|tray_item| &tray_item.id() == id)
+        {
+            let (_, effect) = self.detach_tray_item(index);
+            effect
+        } else {
+            self.hidden_items.retain(|tray_item| &tray_item.id() != id);
+            Effect::None
+        }
+    }
+
+    pub fn set_rules(&mut self, rules: Vec<Rule>) -> Effect {
+        self.rules = rules;
+
+        let selected_id = self
+            .selected_index
+            .take()
+            .map(|index| self.tray_items[index].id());
+        let mut tray_items = mem::take(&mut self.tray_items);
+        tray_items.append(&mut self.hidden_items);
+        let mut effect = Effect::RequestLayout;
+
+        for mut tray_item in tray_items {
+            let rule_index = find_rule(&self.rules, tray_item.icon());
+            tray_item.set_rule(rule_index.map(|index| (index, &self.rules[index])));
+            if rule_index.map_or(false, |index| self.rules[index].is_hidden()) {
+                if Some(tray_item.id()) == selected_id {
+                    effect = effect + tray_item.deselect_item();
+                }
+                self.hidden_items.push(tray_item);
+            } else {
+                self.tray_items.push(tray_item);
+            }
+        }
+
+        self.arrange_tray_items();
+        self.selected_index = selected_id.and_then(|id| {
+            self.tray_items
+                .iter()
+                .position(|tray_item| tray_item.id() == id)
+        });
+
+        effect
+    }
+
+    pub fn restore_state(&mut self, state: State) {
+        self.item_order = state.items;
+        self.restored_selection = state.selected_item;
+    }
+
+    pub fn state(&self) -> State {
+        State {
+            selected_item: self
+                .selected_index
+                .and_then(|index| self.tray_items[index].icon().key()),
+            items: self.item_order.clone(),
+        }
+    }
+
+    // Returns true if the order of items is changed since the last call.
+    pub fn take_item_order_changed(&mut self) -> bool {
+        mem::replace(&mut self.is_item_order_changed, false)
+    }
+
+    fn insert_tray_item(&mut self, icon: TrayItemIcon) -> Effect {
+        let key = icon.key();
+        if let Some(key) = &key {
+            if !self.item_order.contains(key) {
+                self.item_order.push(key.clone());
+                self.prune_item_order(key);
+                self.is_item_order_changed = true;
+            }
+        }
+        let rule_index = find_rule(&self.rules, &icon);
+        let mut tray_item = TrayItem::new(
+            icon,
+            self.item_font.clone(),
+            self.selected_item_font.clone(),
+            self.config.clone(),
+        );
+        tray_item.set_rule(rule_index.map(|index| (index, &self.rules[index])));
+        if rule_index.map_or(false, |index| self.rules[index].is_hidden()) {
+            self.hidden_items.push(tray_item);
+            Effect::None
+        } else {
+            let id = tray_item.id();
+            self.tray_items.push(tray_item);
+            self.arrange_tray_items();
+            let mut effect = Effect::RequestLayout;
+            if self.selected_index.is_none()
+                && key.is_some()
+                && self.restored_selection.as_ref() == key.as_ref()
+            {
+                self.restored_selection = None;
+                let index = self
+                    .tray_items
+                    .iter()
+                    .position(|tray_item| tray_item.id() == id);
+                effect = effect + self.select_tray_item(index);
+            }
+            effect
+        }
+    }
+
+    fn prune_item_order(&mut self, inserted_key: &TrayItemKey) {
+        let mut excess = self.item_order.len().saturating_sub(MAX_ITEM_ORDER);
+        if excess == 0 {
+            return;
+        }
+        let present_keys: Vec<TrayItemKey> = self
+            .tray_items
+            .iter()
+            .chain(self.hidden_items.iter())
+            .filter_map(|tray_item| tray_item.icon().key())
+            .collect();
+        self.item_order.retain(|key| {
+            if excess > 0 && key != inserted_key && !present_keys.contains(key) {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn detach_tray_item(&mut self, index: usize) -> (TrayItem, Effect) {
+        let id = self.tray_items[index].id();
+        if self.menu.as_ref().map(MenuList::owner) == Some(&id) {
+            self.menu = None;
+        }
+        if self
+            .pending_menu
+            .as_ref()
+            .map(|pending_menu| &pending_menu.owner)
+            == Some(&id)
+        {
+            self.pending_menu = None;
+        }
+        let mut effect = Effect::RequestLayout;
+        match self.selected_index {
+            Some(selected_index) if selected_index > index => {
+                self.selected_index = Some(selected_index - 1);
+            }
+            Some(selected_index) if selected_index == index => {
+                effect = effect + self.tray_items[index].deselect_item();
+                self.selected_index = None;
+            }
+            _ => {}
+        }
+        let tray_item = self.tray_items.remove(index);
+        self.update_visible_items();
+        (tray_item, effect)
+    }
+
+    // Pinned items are placed at their position, and others fill the rest in
+    // the order of the saved state, then in the order of arrival.
+    fn arrange_tray_items(&mut self) {
+        let selected_id = self.selected_index.map(|index| self.tray_items[index].id());
+        let rules = &self.rules;
+        let pinned_position = |tray_item: &TrayItem| {
+            tray_item
+                .rule_index()
+                .and_then(|index| rules[index].position())
+        };
+
+        let item_order = &self.item_order;
+        let saved_position = |tray_item: &TrayItem| {
+            tray_item
+                .icon()
+                .key()
+                .and_then(|key| item_order.iter().position(|other| *other == key))
+                .unwrap_or(usize::MAX)
+        };
+
+        let (mut pinned_items, mut unpinned_items): (Vec<_>, Vec<_>) =
+            mem::take(&mut self.tray_items)
+                .into_iter()
+                .partition(|tray_item| pinned_position(tray_item).is_some());
+        pinned_items.sort_by_key(|tray_item| pinned_position(tray_item));
+        unpinned_items.sort_by_cached_key(|tray_item| saved_position(tray_item));
+
+        let mut pinned_items = pinned_items.into_iter().peekable();
+        let mut unpinned_items = unpinned_items.into_iter();
+
+        loop {
+            let index = self.tray_items.len();
+            let tray_item = match pinned_items.next_if(|tray_item| {
+                pinned_position(tray_item).map_or(false, |position| position <= index)
+            }) {
+                Some(tray_item) => tray_item,
+                None => match unpinned_items.next().or_else(|| pinned_items.next()) {
+                    Some(tray_item) => tray_item,
+                    None => break,
+                },
+            };
             self.tray_items.push(tray_item);
+        }
+
+        self.selected_index = selected_id.and_then(|id| {
+            self.tray_items
+                .iter()
+                .position(|tray_item| tray_item.id() == id)
+        });
+
+        self.update_visible_items();
+    }
+
+    pub fn is_filtering(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    pub fn filter_query(&self) -> Option<&str> {
+        self.filter.as_ref().map(|filter| filter.query.as_str())
+    }
+
+    pub fn start_filter(&mut self) -> Effect {
+        if self.menu.is_some() || self.filter.is_some() {
+            return Effect::None;
+        }
+        self.filter = Some(Filter {
+            query: String::new(),
+            matched_indices: Vec::new(),
+        });
+        self.update_visible_items();
+        Effect::RequestLayout
+    }
+
+    pub fn exit_filter(&mut self) -> Effect {
+        if self.filter.take().is_some() {
+            for tray_item in &mut self.tray_items {
+                tray_item.set_title_highlights(Vec::new());
+            }
             Effect::RequestLayout
+        } else {
+            Effect::None
+        }
+    }
+
+    pub fn push_filter_text(&mut self, text: &str) -> Effect {
+        self.change_filter_query(|query| query.push_str(text))
+    }
+
+    pub fn pop_filter_char(&mut self) -> Effect {
+        self.change_filter_query(|query| {
+            query.pop();
+        })
+    }
+
+    pub fn clear_filter_query(&mut self) -> Effect {
+        self.change_filter_query(String::clear)
+    }
+
+    // Hiding the window is left to the caller.
+    pub fn run_filter_action(&mut self, action: FilterAction) -> Effect {
+        match action {
+            FilterAction::Nothing => Effect::None,
+            FilterAction::ClearQuery => self.clear_filter_query(),
+            FilterAction::ExitFilter | FilterAction::HideWindow => self.exit_filter(),
+        }
+    }
+
+    pub fn click_first_filtered_item(
+        &mut self,
+        button: MouseButton,
+        time: xproto::Timestamp,
+    ) -> Effect {
+        let index = match &self.filter {
+            Some(filter) => filter.matched_indices.first().copied(),
+            None => return Effect::None,
+        };
+        let mut effect = self.exit_filter();
+        if let Some(index) = index {
+            effect = effect
+                + self.select_tray_item(Some(index))
+                + self.click_item(index, button, Modifiers::NONE, None, time);
+        }
+        effect
+    }
+
+    fn change_filter_query(&mut self, f: impl FnOnce(&mut String)) -> Effect {
+        match &mut self.filter {
+            Some(filter) => f(&mut filter.query),
+            None => return Effect::None,
+        }
+        self.update_visible_items();
+        let first_index = self
+            .filter
+            .as_ref()
+            .and_then(|filter| filter.matched_indices.first().copied());
+        self.select_tray_item(first_index) + Effect::RequestLayout
+    }
+
+    pub fn is_hinting(&self) -> bool {
+        self.hint.is_some()
+    }
+
+    pub fn start_hint(&mut self, button: Option<MouseButton>) -> Effect {
+        if self.menu.is_some() || self.hint.is_some() {
+            return Effect::None;
+        }
+        self.hint = Some(Hint {
+            typed: String::new(),
+            button,
+        });
+        self.update_hint();
+        Effect::RequestRedraw
+    }
+
+    pub fn exit_hint(&mut self) -> Effect {
+        if self.hint.take().is_some() {
+            self.update_hint();
+            Effect::RequestRedraw
+        } else {
+            Effect::None
+        }
+    }
+
+    pub fn push_hint_text(&mut self, text: &str, time: xproto::Timestamp) -> Effect {
+        let (typed, button) = match &self.hint {
+            Some(hint) => (format!("{}{}", hint.typed, text), hint.button),
+            None => return Effect::None,
+        };
+
+        let visible_indices = self.visible_indices();
+        let labels = hint_labels(&self.config.hint_alphabet, visible_indices.len());
+        let mut candidates = visible_indices
+            .into_iter()
+            .zip(labels)
+            .filter(|(_, label)| label.starts_with(&typed));
+
+        match (candidates.next(), candidates.next()) {
+            (None, _) => Effect::None,
+            (Some((index, label)), None) if label == typed => {
+                let mut effect = self.exit_hint() + self.select_tray_item(Some(index));
+                if let Some(button) = button {
+                    effect = effect + self.click_item(index, button, Modifiers::NONE, None, time);
+                }
+                effect
+            }
+            _ => {
+                if let Some(hint) = &mut self.hint {
+                    hint.typed = typed;
+                }
+                self.update_hint();
+                Effect::RequestRedraw
+            }
+        }
+    }
+
+    pub fn pop_hint_char(&mut self) -> Effect {
+        if self
+            .hint
+            .as_mut()
+            .and_then(|hint| hint.typed.pop())
+            .is_some()
+        {
+            self.update_hint();
+            Effect::RequestRedraw
+        } else {
+            Effect::None
+        }
+    }
+
+    fn update_visible_items(&mut self) {
+        self.update_filter();
+        self.update_hint();
+    }
+
+    fn update_hint(&mut self) {
+        for tray_item in &mut self.tray_items {
+            tray_item.set_hint_label(None);
+        }
+
+        let hint = match &self.hint {
+            Some(hint) => hint,
+            None => return,
+        };
+
+        let visible_indices = self.visible_indices();
+        let labels = hint_labels(&self.config.hint_alphabet, visible_indices.len());
+        for (index, label) in visible_indices.into_iter().zip(labels) {
+            if label.starts_with(&hint.typed) {
+                self.tray_items[index].set_hint_label(Some((label, hint.typed.len())));
+            }
+        }
+    }
+
+    fn update_filter(&mut self) {
+        let filter = match &mut self.filter {
+            Some(filter) => filter,
+            None => return,
+        };
+
+        if filter.query.is_empty() {
+            filter.matched_indices = (0..self.tray_items.len()).collect();
+            for tray_item in &mut self.tray_items {
+                tray_item.set_title_highlights(Vec::new());
+            }
+            return;
+        }
+
+        let mut matches = Vec::new();
+        for (index, tray_item) in self.tray_items.iter_mut().enumerate() {
+            if let Some(result) = fuzzy_match(&filter.query, tray_item.title()) {
+                matches.push((index, result.score));
+                tray_item.set_title_highlights(result.ranges);
+            } else {
+                tray_item.set_title_highlights(Vec::new());
+                if let Some(result) = tray_item
+                    .icon()
+                    .class_name()
+                    .and_then(|class_name| fuzzy_match(&filter.query, class_name))
+                {
+                    matches.push((index, result.score));
+                }
+            }
+        }
+        matches.sort_by_key(|(_, score)| -score);
+
+        filter.matched_indices = matches.into_iter().map(|(index, _)| index).collect();
+    }
+
+    fn filter_bar_height(&self) -> f64 {
+        if self.filter.is_some() {
+            self.config.item_height() + self.config.item_gap
+        } else {
+            0.0
+        }
+    }
+
+    fn visible_indices(&self) -> Vec<usize> {
+        match &self.filter {
+            Some(filter) => filter.matched_indices.clone(),
+            None => (0..self.tray_items.len()).collect(),
         }
     }
 
-    pub fn update_tray_item(&mut self, icon: TrayIcon) -> Effect {
+    pub fn show_message(&mut self, id: &TrayItemId, message_id: u32, text: String) -> Effect {
         if let Some(tray_item) = self
             .tray_items
             .iter_mut()
-            .find(|tray_item| tray_item.window() == icon.window())
+            .find(|tray_item| &tray_item.id() == id)
+        {
+            tray_item.show_message(message_id, text)
+        } else {
+            Effect::None
+        }
+    }
+
+    pub fn remove_message(&mut self, id: &TrayItemId, message_id: u32) -> Effect {
+        if let Some(tray_item) = self
+            .tray_items
+            .iter_mut()
+            .find(|tray_item| &tray_item.id() == id)
+        {
+            tray_item.remove_message(Some(message_id))
+        } else {
+            Effect::None
+        }
+    }
+
+    pub fn dismiss_message(&mut self) -> Effect {
+        let selected_message_index = self
+            .selected_index
+            .filter(|index| self.tray_items[*index].message_received_at().is_some());
+        if let Some(index) = selected_message_index.or_else(|| self.latest_message_index()) {
+            self.tray_items[index].remove_message(None)
+        } else {
+            Effect::None
+        }
+    }
+
+    pub fn select_rule_item(&mut self, rule_index: usize) -> Effect {
+        if let Some(index) = self
+            .tray_items
+            .iter()
+            .position(|tray_item| tray_item.rule_index() == Some(rule_index))
         {
-            tray_item.update_icon(icon)
+            self.select_tray_item(Some(index))
+        } else {
+            Effect::None
+        }
+    }
+
+    pub fn select_message_item(&mut self) -> Effect {
+        if let Some(index) = self.latest_message_index() {
+            self.select_tray_item(Some(index))
         } else {
-            let tray_item = TrayItem::new(
-                icon,
-                self.item_font.clone(),
-                self.selected_item_font.clone(),
-                self.config.clone(),
+            Effect::None
+        }
+    }
+
+    fn latest_message_index(&self) -> Option<usize> {
+        self.tray_items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, tray_item)| Some((index, tray_item.message_received_at()?)))
+            .max_by_key(|(_, received_at)| *received_at)
+            .map(|(index, _)| index)
+    }
+
+    pub fn selected_item_id(&self) -> Option<TrayItemId> {
+        if self.menu.is_some() {
+            return None;
+        }
+        self.selected_index.map(|index| self.tray_items[index].id())
+    }
+
+    pub fn select_item_by_id(&mut self, id: &TrayItemId) -> Effect {
+        match self
+            .tray_items
+            .iter()
+            .position(|tray_item| &tray_item.id() == id)
+        {
+            Some(index) => self.select_tray_item(Some(index)),
+            None => Effect::None,
+        }
+    }
+
+    pub fn select_item(&mut self, new_index: Option<usize>) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            return match new_index {
+                Some(index) => menu.select_nth_item(index),
+                None => menu.select_item(None),
+            };
+        }
+
+        let new_index = new_index.and_then(|index| self.visible_indices().get(index).copied());
+        self.select_tray_item(new_index)
+    }
+
+    fn select_tray_item(&mut self, new_index: Option<usize>) -> Effect {
+        let mut result = Effect::None;
+
+        if let Some(index) = self.selected_index {
+            let tray_item = &mut self.tray_items[index];
+            result = result + tray_item.deselect_item();
+        }
+
+        if let Some(index) = new_index {
+            if let Some(tray_item) = self.tray_items.get_mut(index) {
+                result = result + tray_item.select_item();
+                self.selected_index = Some(index);
+            } else {
+                self.selected_index = None;
+            }
+        } else {
+            self.selected_index = None;
+        }
+
+        if let Some(index) = self.selected_index {
+            result = result + self.scroll_into_view(index);
+        }
+
+        result
+    }
+
+    pub fn set_config(&mut self, config: Rc<UiConfig>) -> Effect {
+        let (item_font, selected_item_font) = item_fonts(&config);
+        for tray_item in self
+            .tray_items
+            .iter_mut()
+            .chain(self.hidden_items.iter_mut())
+        {
+            tray_item.set_config(
+                item_font.clone(),
+                selected_item_font.clone(),
+                config.clone(),
             );
-            self.tray_items.push(tray_item);
+        }
+        if let Some(menu) = &mut self.menu {
+            menu.set_config(
+                item_font.clone(),
+                selected_item_font.clone(),
+                config.clone(),
+            );
+        }
+        self.item_font = item_font;
+        self.selected_item_font = selected_item_font;
+        self.config = config;
+        Effect::RequestLayout
+    }
+
+    pub fn set_max_height(&mut self, max_height: f64) -> Effect {
+        self.max_height = max_height;
+        Effect::RequestLayout
+    }
+
+    pub fn scroll_by(&mut self, delta: f64) -> Effect {
+        self.set_scroll_offset(self.current_scroll_offset() + delta)
+    }
+
+    fn scroll_into_view(&mut self, index: usize) -> Effect {
+        let viewport_height = match self.viewport_height() {
+            Some(viewport_height) => viewport_height,
+            None => return Effect::None,
+        };
+        let mut top = 0.0;
+        for row in self.rows() {
+            let height = self.row_height(&row);
+            if row.contains(&index) {
+                let scroll_offset = self.current_scroll_offset();
+                if top < scroll_offset {
+                    return self.set_scroll_offset(top);
+                } else if top + height > scroll_offset + viewport_height {
+                    return self.set_scroll_offset(top + height - viewport_height);
+                }
+                break;
+            }
+            top += height + self.config.item_gap;
+        }
+        Effect::None
+    }
+
+    fn set_scroll_offset(&mut self, scroll_offset: f64) -> Effect {
+        let old_scroll_offset = self.current_scroll_offset();
+        self.scroll_offset = scroll_offset.clamp(0.0, self.max_scroll_offset());
+        if self.scroll_offset != old_scroll_offset {
             Effect::RequestLayout
+        } else {
+            Effect::None
+        }
+    }
+
+    fn current_scroll_offset(&self) -> f64 {
+        self.scroll_offset.clamp(0.0, self.max_scroll_offset())
+    }
+
+    fn max_scroll_offset(&self) -> f64 {
+        self.viewport_height().map_or(0.0, |viewport_height| {
+            (self.content_height() - viewport_height).max(0.0)
+        })
+    }
+
+    fn viewport_height(&self) -> Option<f64> {
+        if self.max_height > 0.0 {
+            Some(
+                (self.max_height - self.config.container_padding * 2.0 - self.filter_bar_height())
+                    .max(0.0),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn content_height(&self) -> f64 {
+        self.rows()
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                if i > 0 {
+                    self.row_height(row) + self.config.item_gap
+                } else {
+                    self.row_height(row)
+                }
+            })
+            .sum()
+    }
+
+    // Groups the visible items by the rows in which they are placed.
+    fn rows(&self) -> Vec<Vec<usize>> {
+        let visible_indices = self.visible_indices();
+        match (self.config.layout, self.config.orientation) {
+            (ItemLayout::Grid, _) => visible_indices
+                .chunks(self.grid_columns())
+                .map(<[usize]>::to_vec)
+                .collect(),
+            (ItemLayout::List, Orientation::Vertical) => visible_indices
+                .into_iter()
+                .map(|index| vec![index])
+                .collect(),
+            (ItemLayout::List, Orientation::Horizontal) if !visible_indices.is_empty() => {
+                vec![visible_indices]
+            }
+            (ItemLayout::List, Orientation::Horizontal) => Vec::new(),
+        }
+    }
+
+    fn row_height(&self, row: &[usize]) -> f64 {
+        row.iter()
+            .map(|index| self.tray_items[*index].height())
+            .fold(0.0, f64::max)
+    }
+
+    fn grid_columns(&self) -> usize {
+        self.config.grid_columns.max(1)
+    }
+
+    pub fn select_next_item(&mut self) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            return menu.select_next_item();
+        }
+
+        let visible_indices = self.visible_indices();
+        if visible_indices.is_empty() {
+            return Effect::None;
+        }
+
+        let selected_position = self
+            .selected_index
+            .and_then(|index| visible_indices.iter().position(|other| *other == index));
+        let selected_index = match selected_position {
+            Some(position) if position < visible_indices.len() - 1 => {
+                Some(visible_indices[position + 1])
+            }
+            Some(_) => None,
+            None => Some(visible_indices[0]),
+        };
+
+        self.select_tray_item(selected_index)
+    }
+
+    pub fn select_previous_item(&mut self) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            return menu.select_previous_item();
+        }
+
+        let visible_indices = self.visible_indices();
+        if visible_indices.is_empty() {
+            return Effect::None;
+        }
+
+        let selected_position = self
+            .selected_index
+            .and_then(|index| visible_indices.iter().position(|other| *other == index));
+        let selected_index = match selected_position {
+            Some(position) if position > 0 => Some(visible_indices[position - 1]),
+            Some(_) => None,
+            None => Some(visible_indices[visible_indices.len() - 1]),
+        };
+
+        self.select_tray_item(selected_index)
+    }
+
+    pub fn select_left_item(&mut self) -> Effect {
+        if self.menu.is_some() {
+            return Effect::None;
+        }
+        self.select_adjacent_item(0, -1)
+    }
+
+    pub fn select_right_item(&mut self) -> Effect {
+        if self.menu.is_some() {
+            return Effect::None;
+        }
+        self.select_adjacent_item(0, 1)
+    }
+
+    pub fn select_up_item(&mut self) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            return menu.select_previous_item();
+        }
+        self.select_adjacent_item(-1, 0)
+    }
+
+    pub fn select_down_item(&mut self) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            return menu.select_next_item();
+        }
+        self.select_adjacent_item(1, 0)
+    }
+
+    fn select_adjacent_item(&mut self, row_delta: isize, column_delta: isize) -> Effect {
+        let rows = self.rows();
+
+        let selected_position = self.selected_index.and_then(|index| {
+            rows.iter().enumerate().find_map(|(row, indices)| {
+                Some((row, indices.iter().position(|other| *other == index)?))
+            })
+        });
+
+        let new_index = match selected_position {
+            Some((row, column)) => {
+                let new_row = offset_index(row, row_delta).and_then(|new_row| rows.get(new_row));
+                let new_column = offset_index(column, column_delta);
+                match (new_row, new_column) {
+                    // Moving to a shorter row lands on its last item.
+                    (Some(indices), Some(new_column)) if row_delta != 0 => {
+                        indices.get(new_column).or_else(|| indices.last()).copied()
+                    }
+                    (Some(indices), Some(new_column)) => indices.get(new_column).copied(),
+                    _ => None,
+                }
+            }
+            None if row_delta > 0 || column_delta > 0 => {
+                rows.first().and_then(|indices| indices.first()).copied()
+            }
+            None => rows.last().and_then(|indices| indices.last()).copied(),
+        };
+
+        match new_index {
+            Some(index) => self.select_tray_item(Some(index)),
+            None => Effect::None,
         }
     }
 
-    pub fn remove_tray_item(&mut self, window: xproto::Window) -> Effect {
-        if let Some(index) = self
-            .tray_items
-            .iter()
-            .position(|tray_item| tray_item.window() == window)
-        {
-            match self.selected_index {
-                Some(selected_index) if selected_index > index => {
-                    self.selected_index = Some(selected_index - 1);
-                }
-                Some(selected_index) if selected_index == index => {
-                    self.selected_index = None;
-                }
-                _ => {}
+    pub fn click_selected_item(
+        &mut self,
+        button: MouseButton,
+        modifiers: Modifiers,
+        position: Option<ClickPosition>,
+        time: xproto::Timestamp,
+    ) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            let effect = menu.click_selected_item();
+            if menu.is_closed() {
+                self.menu = None;
             }
-            self.tray_items.remove(index);
-            Effect::RequestLayout
+            return effect;
+        }
+
+        if let Some(index) = self.selected_index {
+            self.click_item(index, button, modifiers, position, time)
         } else {
             Effect::None
         }
     }
 
-    pub fn select_item(&mut self, new_index: Option<usize>) -> Effect {
-        let mut result = Effect::None;
-
-        if let Some(index) = self.selected_index {
-            let tray_item = &mut self.tray_items[index];
-            result = result + tray_item.deselect_item();
+    pub fn double_click_selected_item(
+        &mut self,
+        button: MouseButton,
+        modifiers: Modifiers,
+        position: Option<ClickPosition>,
+        time: xproto::Timestamp,
+    ) -> Effect {
+        if self.menu.is_some() {
+            return Effect::None;
         }
 
-        if let Some(index) = new_index {
-            if let Some(tray_item) = self.tray_items.get_mut(index) {
-                result = result + tray_item.select_item();
-                self.selected_index = Some(index);
-            } else {
-                self.selected_index = None;
+        match self.selected_index {
+            Some(index) if self.tray_items[index].context_menu(button).is_none() => {
+                self.tray_items[index].double_click_item(button, modifiers, position, time)
             }
-        } else {
-            self.selected_index = None;
+            Some(index) => self.click_item(index, button, modifiers, position, time),
+            None => Effect::None,
         }
-
-        result
     }
 
-    pub fn select_next_item(&mut self) -> Effect {
-        if self.tray_items.len() == 0 {
+    pub fn hold_selected_item(
+        &mut self,
+        button: MouseButton,
+        modifiers: Modifiers,
+        position: Option<ClickPosition>,
+        duration: Duration,
+        time: xproto::Timestamp,
+    ) -> Effect {
+        if self.menu.is_some() {
             return Effect::None;
         }
 
-        let selected_index = match self.selected_index {
-            Some(index) if index < self.tray_items.len() - 1 => Some(index + 1),
-            Some(index) if index == self.tray_items.len() - 1 => None,
-            _ => Some(0),
-        };
+        match self.selected_index {
+            Some(index) if self.tray_items[index].context_menu(button).is_none() => {
+                self.tray_items[index].hold_item(button, modifiers, position, duration, time)
+            }
+            Some(index) => self.click_item(index, button, modifiers, position, time),
+            None => Effect::None,
+        }
+    }
+
+    pub fn hover_selected_item(&mut self) -> Effect {
+        if self.menu.is_some() {
+            return Effect::None;
+        }
 
-        self.select_item(selected_index)
+        let mut effect = Effect::None;
+        if let Some(selected_index) = self.selected_index {
+            for (index, tray_item) in self.tray_items.iter_mut().enumerate() {
+                if index != selected_index {
+                    effect = effect + tray_item.unhover_item();
+                }
+            }
+            effect = effect + self.tray_items[selected_index].hover_item();
+        }
+        effect
     }
 
-    pub fn select_previous_item(&mut self) -> Effect {
-        if self.tray_items.len() == 0 {
+    pub fn scroll_selected_item(&mut self, direction: ScrollDirection, steps: usize) -> Effect {
+        if self.menu.is_some() {
             return Effect::None;
         }
 
-        let selected_index = match self.selected_index {
-            Some(index) if index > 0 => Some(index - 1),
-            Some(index) if index == 0 => None,
-            _ => Some(self.tray_items.len() - 1),
-        };
+        if let Some(index) = self.selected_index {
+            self.tray_items[index].scroll_item(direction, steps)
+        } else {
+            Effect::None
+        }
+    }
 
-        self.select_item(selected_index)
+    pub fn close_menu(&mut self) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            let effect = menu.close_submenu();
+            if menu.is_closed() {
+                self.menu = None;
+            }
+            effect
+        } else {
+            Effect::None
+        }
     }
 
-    pub fn click_selected_item(&mut self, button: MouseButton) -> Effect {
-        if let Some(index) = self.selected_index {
-            let tray_item = &mut self.tray_items[index];
-            tray_item.click_item(button)
+    pub fn update_menu(&mut self, message: &dbus::Message) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            menu.update_menu(message)
         } else {
             Effect::None
         }
     }
+
+    pub fn receive_menu_layout(&mut self, menu: &DBusMenu, root: DBusMenuItem) -> Effect {
+        match self.pending_menu.take() {
+            Some(pending_menu) if &pending_menu.menu == menu => {
+                let tray_item = match self
+                    .tray_items
+                    .iter()
+                    .find(|tray_item| tray_item.id() == pending_menu.owner)
+                {
+                    Some(tray_item) => tray_item,
+                    None => return Effect::None,
+                };
+                let mut menu = MenuList::new(
+                    pending_menu.owner,
+                    tray_item.title().to_owned(),
+                    pending_menu.menu,
+                    root,
+                    self.item_font.clone(),
+                    self.selected_item_font.clone(),
+                    self.config.clone(),
+                );
+                let effect = menu.select_next_item();
+                self.menu = Some(menu);
+                effect + Effect::RequestLayout
+            }
+            pending_menu => {
+                self.pending_menu = pending_menu;
+                match &mut self.menu {
+                    Some(menu_list) if menu_list.menu() == menu => menu_list.set_layout(root),
+                    _ => Effect::None,
+                }
+            }
+        }
+    }
+
+    pub fn fail_menu_layout(&mut self, menu: &DBusMenu) -> Effect {
+        match self.pending_menu.take() {
+            Some(pending_menu) if &pending_menu.menu == menu => {
+                match self
+                    .tray_items
+                    .iter_mut()
+                    .find(|tray_item| tray_item.id() == pending_menu.owner)
+                {
+                    Some(tray_item) => tray_item.click_item(
+                        pending_menu.button,
+                        pending_menu.modifiers,
+                        pending_menu.position,
+                        pending_menu.time,
+                    ),
+                    None => Effect::None,
+                }
+            }
+            pending_menu => {
+                self.pending_menu = pending_menu;
+                Effect::None
+            }
+        }
+    }
+
+    pub fn take_menu_requests(&mut self) -> Vec<MenuRequest> {
+        let mut requests = mem::take(&mut self.menu_requests);
+        if let Some(menu) = &mut self.menu {
+            requests.extend(menu.take_requests());
+        }
+        requests
+    }
+
+    fn click_item(
+        &mut self,
+        index: usize,
+        button: MouseButton,
+        modifiers: Modifiers,
+        position: Option<ClickPosition>,
+        time: xproto::Timestamp,
+    ) -> Effect {
+        let tray_item = &mut self.tray_items[index];
+        if let Some(menu) = tray_item.context_menu(button) {
+            // The menu is opened when its layout arrives, or the item is
+            // clicked instead if it fails.
+            self.menu_requests
+                .push(MenuRequest::GetLayout(menu.clone()));
+            self.pending_menu = Some(PendingMenu {
+                owner: tray_item.id(),
+                menu,
+                button,
+                modifiers,
+                position,
+                time,
+            });
+            return Effect::None;
+        }
+        tray_item.click_item(button, modifiers, position, time)
+    }
 }
 
 impl Widget for TrayContainer {
@@ -190,31 +1161,139 @@ impl Widget for TrayContainer {
                 Rect::new(position, layout.size),
             );
 
-        if self.tray_items.len() > 0 {
-            for (index, (tray_item, (child_position, child_layout))) in self
-                .tray_items
+        for tray_item in &self.hidden_items {
+            result = result + tray_item.render_hidden();
+        }
+
+        if let Some(menu) = &self.menu {
+            if let Some((child_position, child_layout)) = layout.children.first() {
+                result = result + menu.render(*child_position, child_layout, 0, context);
+            }
+            return result;
+        }
+
+        let mut content_position = position;
+        let mut content_size = layout.size;
+
+        let filter_bar_height = self.filter_bar_height();
+        content_position.y += filter_bar_height;
+        content_size.height -= filter_bar_height;
+
+        let visible_indices = self.visible_indices();
+
+        // Items left out by the filter.
+        for (index, tray_item) in self.tray_items.iter().enumerate() {
+            if !visible_indices.contains(&index) {
+                result = result + tray_item.render_hidden();
+            }
+        }
+
+        if !visible_indices.is_empty() {
+            for (index, (tray_index, (child_position, child_layout))) in visible_indices
                 .iter()
                 .zip(layout.children.iter())
                 .enumerate()
             {
-                result = result + tray_item.render(*child_position, child_layout, index, context);
+                result = result
+                    + self.tray_items[*tray_index].render(
+                        *child_position,
+                        child_layout,
+                        index,
+                        context,
+                    );
             }
         } else {
+            let content = if self.filter.is_some() {
+                "No matching items"
+            } else {
+                "No tray items found"
+            };
             result = result
                 + RenderOp::Text(
                     self.config.container_foreground,
                     Rect {
-                        x: position.x + self.config.container_padding,
-                        y: position.y,
-                        width: layout.size.width - (self.config.container_padding * 2.0),
-                        height: layout.size.height,
+                        x: content_position.x + self.config.container_padding,
+                        y: content_position.y,
+                        width: content_size.width - (self.config.container_padding * 2.0),
+                        height: content_size.height,
                     },
                     Text {
-                        content: "No tray items found".into(),
+                        content: content.into(),
                         font: self.item_font.clone(),
                         size: self.config.text_size,
                         horizontal_align: HorizontalAlign::Center,
                         vertical_align: VerticalAlign::Middle,
+                        highlights: Vec::new(),
+                    },
+                );
+        }
+
+        let max_scroll_offset = self.max_scroll_offset();
+        if max_scroll_offset > 0.0 {
+            // Cover items scrolled out of the viewport.
+            result = result
+                + RenderOp::Rect(
+                    self.config.container_background,
+                    Rect {
+                        x: position.x,
+                        y: position.y,
+                        width: layout.size.width,
+                        height: self.config.container_padding + filter_bar_height,
+                    },
+                )
+                + RenderOp::Rect(
+                    self.config.container_background,
+                    Rect {
+                        x: position.x,
+                        y: position.y + layout.size.height - self.config.container_padding,
+                        width: layout.size.width,
+                        height: self.config.container_padding,
+                    },
+                );
+
+            let track_height = content_size.height - self.config.container_padding * 2.0;
+            let content_height = track_height + max_scroll_offset;
+            let thumb_width = self.config.container_padding / 2.0;
+            let thumb_height = (track_height * track_height / content_height).max(thumb_width);
+            let thumb_y =
+                (track_height - thumb_height) * (self.current_scroll_offset() / max_scroll_offset);
+            result = result
+                + RenderOp::RoundedRect(
+                    self.config
+                        .container_foreground
+                        .with_alpha(SCROLL_INDICATOR_ALPHA),
+                    Rect {
+                        x: position.x + layout.size.width
+                            - (self.config.container_padding + thumb_width) / 2.0,
+                        y: content_position.y + self.config.container_padding + thumb_y,
+                        width: thumb_width,
+                        height: thumb_height,
+                    },
+                    Size {
+                        width: thumb_width / 2.0,
+                        height: thumb_width / 2.0,
+                    },
+                );
+        }
+
+        if let Some(filter) = &self.filter {
+            result = result
+                + RenderOp::Text(
+                    self.config.container_foreground,
+                    Rect {
+                        x: position.x + self.config.container_padding + self.config.item_padding,
+                        y: position.y + self.config.container_padding,
+                        width: layout.size.width
+                            - (self.config.container_padding + self.config.item_padding) * 2.0,
+                        height: self.config.item_height(),
+                    },
+                    Text {
+                        content: format!("/{}", filter.query).into(),
+                        font: self.item_font.clone(),
+                        size: self.config.text_size,
+                        horizontal_align: HorizontalAlign::Left,
+                        vertical_align: VerticalAlign::Middle,
+                        highlights: Vec::new(),
                     },
                 );
         }
@@ -223,34 +1302,112 @@ impl Widget for TrayContainer {
     }
 
     fn layout(&self, container_size: Size) -> Layout {
-        let mut total_height = self.config.container_padding * 2.0;
-        let mut child_position = Point {
-            x: self.config.container_padding,
-            y: self.config.container_padding,
-        };
-        let mut children = Vec::with_capacity(self.tray_items.len());
-
         let container_inset = Size {
             width: container_size.width - (self.config.container_padding * 2.0),
             height: container_size.height - (self.config.container_padding * 2.0),
         };
 
-        for (index, tray_item) in self.tray_items.iter().enumerate() {
-            let child_layout = tray_item.layout(container_inset);
-            let child_size = child_layout.size;
-            children.push((child_position, child_layout));
-            child_position.y += child_size.height + self.config.item_gap;
-            if index > 0 {
-                total_height += child_size.height + self.config.item_gap;
-            } else {
-                total_height += child_size.height;
+        if let Some(menu) = &self.menu {
+            let child_layout = menu.layout(container_inset);
+            return Layout {
+                size: Size {
+                    width: container_size.width,
+                    height: child_layout.size.height + self.config.container_padding * 2.0,
+                },
+                children: vec![(
+                    Point {
+                        x: self.config.container_padding,
+                        y: self.config.container_padding,
+                    },
+                    child_layout,
+                )],
+            };
+        }
+
+        let filter_bar_height = self.filter_bar_height();
+        let visible_indices = self.visible_indices();
+        let mut total_height = self.config.container_padding * 2.0 + filter_bar_height;
+        let mut child_position = Point {
+            x: self.config.container_padding,
+            y: self.config.container_padding + filter_bar_height,
+        };
+        let mut children = Vec::with_capacity(visible_indices.len());
+
+        match (self.config.layout, self.config.orientation) {
+            (ItemLayout::Grid, _) => {
+                let num_columns = self.grid_columns() as f64;
+                let cell_width = (container_inset.width
+                    - self.config.item_gap * (num_columns - 1.0))
+                    / num_columns;
+                for (row, indices) in visible_indices.chunks(self.grid_columns()).enumerate() {
+                    let mut row_height: f64 = 0.0;
+                    child_position.x = self.config.container_padding;
+                    for tray_index in indices {
+                        let child_layout = self.tray_items[*tray_index].layout(Size {
+                            width: cell_width,
+                            height: container_inset.height,
+                        });
+                        row_height = row_height.max(child_layout.size.height);
+                        children.push((child_position, child_layout));
+                        child_position.x += cell_width + self.config.item_gap;
+                    }
+                    child_position.y += row_height + self.config.item_gap;
+                    if row > 0 {
+                        total_height += row_height + self.config.item_gap;
+                    } else {
+                        total_height += row_height;
+                    }
+                }
+            }
+            (ItemLayout::List, Orientation::Vertical) => {
+                for (index, tray_index) in visible_indices.iter().enumerate() {
+                    let tray_item = &self.tray_items[*tray_index];
+                    let child_layout = tray_item.layout(container_inset);
+                    let child_size = child_layout.size;
+                    children.push((child_position, child_layout));
+                    child_position.y += child_size.height + self.config.item_gap;
+                    if index > 0 {
+                        total_height += child_size.height + self.config.item_gap;
+                    } else {
+                        total_height += child_size.height;
+                    }
+                }
+            }
+            (ItemLayout::List, Orientation::Horizontal) => {
+                let item_width = if self.config.show_label && !visible_indices.is_empty() {
+                    let num_items = visible_indices.len() as f64;
+                    (container_inset.width - self.config.item_gap * (num_items - 1.0)) / num_items
+                } else {
+                    self.config.item_height()
+                };
+                let mut max_height: f64 = 0.0;
+                for tray_index in &visible_indices {
+                    let child_layout = self.tray_items[*tray_index].layout(Size {
+                        width: item_width,
+                        height: container_inset.height,
+                    });
+                    max_height = max_height.max(child_layout.size.height);
+                    children.push((child_position, child_layout));
+                    child_position.x += item_width + self.config.item_gap;
+                }
+                total_height += max_height;
             }
         }
 
+        let scroll_offset = self.current_scroll_offset();
+        for (child_position, _) in &mut children {
+            child_position.y -= scroll_offset;
+        }
+
+        let mut height = total_height.max(self.config.item_height() + filter_bar_height);
+        if self.max_height > 0.0 {
+            height = height.min(self.max_height);
+        }
+
         Layout {
             size: Size {
                 width: container_size.width as f64,
-                height: total_height.max(self.config.item_height()),
+                height,
             },
             children,
         }
@@ -299,14 +1456,545 @@ impl Widget for TrayContainer {
     }
 
     fn on_event(&mut self, event: &protocol::Event, _position: Point, layout: &Layout) -> Effect {
+        if let Some(menu) = &mut self.menu {
+            let mut side_effect = Effect::None;
+            if let Some((position, layout)) = layout.children.first() {
+                side_effect = menu.on_event(event, *position, layout);
+            }
+            if menu.is_closed() {
+                self.menu = None;
+            }
+            return side_effect;
+        }
+
+        // The wheel over an item is forwarded to it, elsewhere it scrolls the
+        // list.
+        match event {
+            protocol::Event::ButtonPress(event)
+                if is_wheel_button(event.detail)
+                    && self.max_scroll_offset() > 0.0
+                    && !hits_child(layout, event.event_x, event.event_y) =>
+            {
+                let delta = self.config.item_height() + self.config.item_gap;
+                return if event.detail == WHEEL_UP_BUTTON {
+                    self.scroll_by(-delta)
+                } else {
+                    self.scroll_by(delta)
+                };
+            }
+            protocol::Event::ButtonRelease(event)
+                if is_wheel_button(event.detail)
+                    && self.max_scroll_offset() > 0.0
+                    && !hits_child(layout, event.event_x, event.event_y) =>
+            {
+                return Effect::None;
+            }
+            _ => {}
+        }
+
         let mut side_effect = Effect::None;
 
-        for (tray_item, (position, layout)) in
-            self.tray_items.iter_mut().zip(layout.children.iter())
+        for (tray_index, (position, layout)) in self
+            .visible_indices()
+            .into_iter()
+            .zip(layout.children.iter())
+        {
+            side_effect =
+                side_effect + self.tray_items[tray_index].on_event(event, *position, layout);
+        }
+
+        if let Some((index, button)) = self
+            .tray_items
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, tray_item)| Some((index, tray_item.take_menu_request()?)))
         {
-            side_effect = side_effect + tray_item.on_event(event, *position, layout);
+            side_effect = side_effect
+                + self.click_item(index, button, Modifiers::NONE, None, x11rb::CURRENT_TIME);
         }
 
         side_effect
     }
 }
+
+#[derive(Debug)]
+struct Hint {
+    typed: String,
+    button: Option<MouseButton>,
+}
+
+#[derive(Debug)]
+struct PendingMenu {
+    owner: TrayItemId,
+    menu: DBusMenu,
+    button: MouseButton,
+    modifiers: Modifiers,
+    position: Option<ClickPosition>,
+    time: xproto::Timestamp,
+}
+
+#[derive(Debug)]
+struct Filter {
+    query: String,
+    matched_indices: Vec<usize>,
+}
+
+fn item_fonts(config: &UiConfig) -> (FontDescription, FontDescription) {
+    let item_font = FontDescription::new(
+        config.item_font.family.clone(),
+        config.item_font.style,
+        config.item_font.weight,
+        config.item_font.stretch,
+    );
+    let selected_item_font = if config.item_font == config.selected_item_font {
+        item_font.clone()
+    } else {
+        FontDescription::new(
+            config.selected_item_font.family.clone(),
+            config.selected_item_font.style,
+            config.selected_item_font.weight,
+            config.selected_item_font.stretch,
+        )
+    };
+    (item_font, selected_item_font)
+}
+
+fn offset_index(index: usize, delta: isize) -> Option<usize> {
+    (index as isize).checked_add(delta)?.try_into().ok()
+}
+
+fn is_wheel_button(detail: u8) -> bool {
+    detail == WHEEL_UP_BUTTON || detail == WHEEL_DOWN_BUTTON
+}
+
+fn hits_child(layout: &Layout, x: i16, y: i16) -> bool {
+    let pointer_position = PhysicalPoint {
+        x: x as _,
+        y: y as _,
+    };
+    layout
+        .children
+        .iter()
+        .any(|(child_position, child_layout)| {
+            Rect::new(*child_position, child_layout.size)
+                .snap()
+                .contains(pointer_position)
+        })
+}
+
+fn find_rule(rules: &[Rule], icon: &TrayItemIcon) -> Option<usize> {
+    rules
+        .iter()
+        .position(|rule| rule.matches(icon.title(), icon.class_name(), icon.process_name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FilterConfig;
+    use crate::tray_manager::TrayIcon;
+
+    #[test]
+    fn test_arrange_pinned_items() {
+        let mut container = new_container(vec![pinned_rule("c", 0)]);
+        add_icons(&mut container, &["a", "b", "c", "d"]);
+        assert_eq!(class_names(&container), ["c", "a", "b", "d"]);
+
+        // A position beyond the end puts the item at the end.
+        let mut container = new_container(vec![pinned_rule("a", 9)]);
+        add_icons(&mut container, &["a", "b", "c"]);
+        assert_eq!(class_names(&container), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_arrange_colliding_items() {
+        let mut container = new_container(vec![pinned_rule("d", 1), pinned_rule("c", 1)]);
+        add_icons(&mut container, &["a", "b", "c", "d"]);
+        // Items pinned at the same position are placed in the order of arrival.
+        assert_eq!(class_names(&container), ["a", "c", "d", "b"]);
+    }
+
+    #[test]
+    fn test_rearrange_items_on_rule_change() {
+        let mut container = new_container(Vec::new());
+        add_icons(&mut container, &["a", "b", "c"]);
+        let _ = container.select_item(Some(2));
+        assert_eq!(class_names(&container), ["a", "b", "c"]);
+
+        let _ = container.set_rules(vec![pinned_rule("c", 0)]);
+        assert_eq!(class_names(&container), ["c", "a", "b"]);
+        assert_eq!(container.selected_index, Some(0));
+
+        let _ = container.set_rules(Vec::new());
+        assert_eq!(class_names(&container), ["a", "b", "c"]);
+        assert_eq!(container.selected_index, Some(2));
+
+        // The rule is applied again when the title of the item is changed.
+        let rule = toml::from_str("title = \"B\"\nposition = 0").unwrap();
+        let _ = container.set_rules(vec![rule]);
+        assert_eq!(class_names(&container), ["a", "b", "c"]);
+        let _ = container.update_tray_item(TrayIcon::detached(2, "B", Some("b")));
+        assert_eq!(class_names(&container), ["b", "a", "c"]);
+        assert_eq!(container.selected_index, Some(2));
+    }
+
+    #[test]
+    fn test_restore_state() {
+        let mut container = new_container(Vec::new());
+        container.restore_state(State {
+            selected_item: Some(key("b")),
+            items: vec![key("c"), key("a"), key("b")],
+        });
+        add_icons(&mut container, &["a", "b", "d", "c"]);
+        assert_eq!(class_names(&container), ["c", "a", "b", "d"]);
+        assert_eq!(container.selected_index, Some(2));
+        assert!(container.take_item_order_changed());
+        assert!(!container.take_item_order_changed());
+
+        // The icon docked again goes back to its slot.
+        let _ = container.remove_tray_item(&TrayItemId::XEmbed(1));
+        assert_eq!(class_names(&container), ["c", "b", "d"]);
+        let _ = container.add_tray_item(TrayIcon::detached(10, "a", Some("a")));
+        assert_eq!(class_names(&container), ["c", "a", "b", "d"]);
+        assert!(!container.take_item_order_changed());
+        assert_eq!(
+            container.state(),
+            State {
+                selected_item: Some(key("b")),
+                items: vec![key("c"), key("a"), key("b"), key("d")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_prune_item_order() {
+        let mut container = new_container(Vec::new());
+        container.restore_state(State {
+            selected_item: None,
+            items: (0..MAX_ITEM_ORDER)
+                .map(|n| key(&format!("item{}", n)))
+                .collect(),
+        });
+        add_icons(&mut container, &["item1"]);
+        assert_eq!(container.item_order.len(), MAX_ITEM_ORDER);
+        assert!(!container.take_item_order_changed());
+
+        // The oldest keys of absent items are dropped first.
+        add_icons(&mut container, &["new1"]);
+        assert_eq!(container.item_order.len(), MAX_ITEM_ORDER);
+        assert_eq!(container.item_order[..2], [key("item1"), key("item2")]);
+        assert_eq!(container.item_order.last(), Some(&key("new1")));
+        assert!(container.take_item_order_changed());
+
+        add_icons(&mut container, &["new2"]);
+        assert_eq!(container.item_order.len(), MAX_ITEM_ORDER);
+        assert_eq!(container.item_order[..2], [key("item1"), key("item3")]);
+        assert_eq!(
+            container.item_order[MAX_ITEM_ORDER - 2..],
+            [key("new1"), key("new2")]
+        );
+    }
+
+    #[test]
+    fn test_filter_selection() {
+        let mut container = new_container(Vec::new());
+        add_icons(&mut container, &["alpha", "beta", "gamma"]);
+        let _ = container.start_filter();
+        assert_eq!(visible_class_names(&container), ["alpha", "beta", "gamma"]);
+        assert_eq!(container.selected_index, None);
+
+        // The first match is selected as the query changes.
+        let _ = container.push_filter_text("ga");
+        assert_eq!(visible_class_names(&container), ["gamma"]);
+        assert_eq!(container.selected_index, Some(2));
+        let _ = container.pop_filter_char();
+        assert_eq!(container.filter_query(), Some("g"));
+        assert_eq!(container.selected_index, Some(2));
+        let _ = container.push_filter_text("x");
+        assert!(visible_class_names(&container).is_empty());
+        assert_eq!(container.selected_index, None);
+        let _ = container.clear_filter_query();
+        assert_eq!(visible_class_names(&container), ["alpha", "beta", "gamma"]);
+        assert_eq!(container.selected_index, Some(0));
+
+        // Items that appear while filtering are filtered too.
+        let _ = container.push_filter_text("et");
+        add_icons(&mut container, &["zeta"]);
+        assert_eq!(visible_class_names(&container), ["beta", "zeta"]);
+        assert_eq!(container.selected_index, Some(1));
+
+        // The selection is kept after the filter is exited.
+        let _ = container.exit_filter();
+        assert_eq!(
+            visible_class_names(&container),
+            ["alpha", "beta", "gamma", "zeta"]
+        );
+        assert_eq!(container.selected_index, Some(1));
+    }
+
+    #[test]
+    fn test_filter_action() {
+        let filter_config = FilterConfig::default();
+        let mut container = new_container(Vec::new());
+        add_icons(&mut container, &["alpha", "beta"]);
+
+        // Escape exits the filter by default.
+        let _ = container.start_filter();
+        let _ = container.push_filter_text("b");
+        let _ = container.run_filter_action(filter_config.escape);
+        assert!(!container.is_filtering());
+        assert_eq!(container.selected_index, Some(1));
+
+        // So does Backspace when the query is empty.
+        let _ = container.start_filter();
+        let _ = container.push_filter_text("a");
+        let _ = container.pop_filter_char();
+        assert_eq!(container.filter_query(), Some(""));
+        let _ = container.run_filter_action(filter_config.backspace_on_empty);
+        assert!(!container.is_filtering());
+
+        let _ = container.start_filter();
+        let _ = container.push_filter_text("b");
+        let _ = container.run_filter_action(FilterAction::Nothing);
+        assert_eq!(container.filter_query(), Some("b"));
+        let _ = container.run_filter_action(FilterAction::ClearQuery);
+        assert_eq!(container.filter_query(), Some(""));
+        assert_eq!(visible_class_names(&container), ["alpha", "beta"]);
+        // Hiding the window exits the filter, so it is not kept when the
+        // window is shown again.
+        let _ = container.run_filter_action(FilterAction::HideWindow);
+        assert!(!container.is_filtering());
+    }
+
+    #[test]
+    fn test_hint() {
+        let mut container = new_container_with_config(UiConfig {
+            hint_alphabet: "ab".to_owned(),
+            ..UiConfig::default()
+        });
+        add_icons(&mut container, &["alpha", "beta", "gamma"]);
+        let _ = container.start_hint(None);
+        assert_eq!(
+            item_hint_labels(&container),
+            [Some(("aa", 0)), Some(("ab", 0)), Some(("ba", 0))]
+        );
+
+        // Labels that do not start with the typed text are hidden.
+        let _ = container.push_hint_text("a", 0);
+        assert_eq!(
+            item_hint_labels(&container),
+            [Some(("aa", 1)), Some(("ab", 1)), None]
+        );
+        let _ = container.push_hint_text("x", 0);
+        assert_eq!(
+            item_hint_labels(&container),
+            [Some(("aa", 1)), Some(("ab", 1)), None]
+        );
+        let _ = container.pop_hint_char();
+        assert_eq!(
+            item_hint_labels(&container),
+            [Some(("aa", 0)), Some(("ab", 0)), Some(("ba", 0))]
+        );
+
+        // A unique prefix does not select the item until the label is typed
+        // in full.
+        let _ = container.push_hint_text("b", 0);
+        assert!(container.is_hinting());
+        assert_eq!(container.selected_index, None);
+        let _ = container.push_hint_text("a", 0);
+        assert!(!container.is_hinting());
+        assert_eq!(container.selected_index, Some(2));
+        assert_eq!(item_hint_labels(&container), [None, None, None]);
+    }
+
+    #[test]
+    fn test_hint_while_filtering() {
+        let mut container = new_container_with_config(UiConfig {
+            hint_alphabet: "ab".to_owned(),
+            ..UiConfig::default()
+        });
+        add_icons(&mut container, &["alpha", "beta", "gamma"]);
+        let _ = container.start_filter();
+        let _ = container.push_filter_text("ma");
+        let _ = container.start_hint(None);
+        // Only the filtered items are labeled, in the filtered order.
+        assert_eq!(item_hint_labels(&container), [None, None, Some(("a", 0))]);
+        let _ = container.push_hint_text("a", 0);
+        assert!(!container.is_hinting());
+        assert_eq!(container.selected_index, Some(2));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let config = UiConfig::default();
+        let item_height = config.item_height();
+        let item_gap = config.item_gap;
+        // The viewport fits two items.
+        let max_height = item_height * 2.0 + item_gap + config.container_padding * 2.0;
+        let mut container = new_container_with_config(config);
+        let _ = container.set_max_height(max_height);
+        add_icons(&mut container, &["a", "b", "c", "d", "e"]);
+        assert_eq!(container.current_scroll_offset(), 0.0);
+        assert_eq!(
+            container.max_scroll_offset(),
+            (item_height + item_gap) * 3.0
+        );
+
+        // The selected item is scrolled into view.
+        let _ = container.select_item(Some(3));
+        assert_eq!(
+            container.current_scroll_offset(),
+            (item_height + item_gap) * 2.0
+        );
+        let _ = container.select_item(Some(1));
+        assert_eq!(container.current_scroll_offset(), item_height + item_gap);
+
+        let _ = container.scroll_by(-1000.0);
+        assert_eq!(container.current_scroll_offset(), 0.0);
+        let _ = container.scroll_by(1000.0);
+        assert_eq!(
+            container.current_scroll_offset(),
+            (item_height + item_gap) * 3.0
+        );
+
+        // The offset is clamped when items are removed.
+        let _ = container.remove_tray_item(&TrayItemId::XEmbed(5));
+        let _ = container.remove_tray_item(&TrayItemId::XEmbed(4));
+        assert_eq!(container.current_scroll_offset(), item_height + item_gap);
+        let _ = container.remove_tray_item(&TrayItemId::XEmbed(3));
+        assert_eq!(container.current_scroll_offset(), 0.0);
+        let _ = container.scroll_by(1000.0);
+        assert_eq!(container.current_scroll_offset(), 0.0);
+
+        // Nothing is scrolled without the maximum height.
+        add_icons(&mut container, &["c", "d", "e"]);
+        let _ = container.scroll_by(1000.0);
+        let _ = container.set_max_height(0.0);
+        assert_eq!(container.current_scroll_offset(), 0.0);
+    }
+
+    #[test]
+    fn test_select_adjacent_item_in_grid() {
+        let mut container = new_container_with_config(UiConfig {
+            layout: ItemLayout::Grid,
+            grid_columns: 3,
+            ..UiConfig::default()
+        });
+        // 0 1 2
+        // 3 4 5
+        // 6
+        add_icons(&mut container, &["a", "b", "c", "d", "e", "f", "g"]);
+
+        // Without the selection, the first or the last item is selected.
+        let _ = container.select_down_item();
+        assert_eq!(container.selected_index, Some(0));
+        let _ = container.select_item(None);
+        let _ = container.select_left_item();
+        assert_eq!(container.selected_index, Some(6));
+        let _ = container.select_item(None);
+        let _ = container.select_right_item();
+        assert_eq!(container.selected_index, Some(0));
+
+        let _ = container.select_right_item();
+        let _ = container.select_down_item();
+        assert_eq!(container.selected_index, Some(4));
+
+        // Moving to the partial last row lands on its last item.
+        let _ = container.select_down_item();
+        assert_eq!(container.selected_index, Some(6));
+        let _ = container.select_up_item();
+        assert_eq!(container.selected_index, Some(3));
+
+        // The selection does not wrap around at the edges.
+        let _ = container.select_left_item();
+        assert_eq!(container.selected_index, Some(3));
+        let _ = container.select_item(Some(2));
+        let _ = container.select_right_item();
+        assert_eq!(container.selected_index, Some(2));
+        let _ = container.select_up_item();
+        assert_eq!(container.selected_index, Some(2));
+        let _ = container.select_item(Some(6));
+        let _ = container.select_down_item();
+        assert_eq!(container.selected_index, Some(6));
+        let _ = container.select_right_item();
+        assert_eq!(container.selected_index, Some(6));
+    }
+
+    #[test]
+    fn test_select_adjacent_item_in_filtered_grid() {
+        let mut container = new_container_with_config(UiConfig {
+            layout: ItemLayout::Grid,
+            grid_columns: 2,
+            ..UiConfig::default()
+        });
+        add_icons(&mut container, &["a1", "b", "a2", "c", "a3"]);
+        let _ = container.start_filter();
+        let _ = container.push_filter_text("a");
+        // The grid is made of the filtered items:
+        // a1 a2
+        // a3
+        assert_eq!(container.selected_index, Some(0));
+        let _ = container.select_right_item();
+        assert_eq!(container.selected_index, Some(2));
+        let _ = container.select_down_item();
+        assert_eq!(container.selected_index, Some(4));
+    }
+
+    fn new_container(rules: Vec<Rule>) -> TrayContainer {
+        TrayContainer::new(Rc::new(UiConfig::default()), rules, 1000.0)
+    }
+
+    fn new_container_with_config(config: UiConfig) -> TrayContainer {
+        TrayContainer::new(Rc::new(config), Vec::new(), 1000.0)
+    }
+
+    fn pinned_rule(class_name: &str, position: usize) -> Rule {
+        toml::from_str(&format!(
+            "class = {:?}\nposition = {}",
+            class_name, position
+        ))
+        .unwrap()
+    }
+
+    fn key(class_name: &str) -> TrayItemKey {
+        TrayItemKey {
+            class: Some(class_name.to_owned()),
+            process: None,
+        }
+    }
+
+    fn add_icons(container: &mut TrayContainer, class_names: &[&str]) {
+        for class_name in class_names {
+            let window = container.tray_items.len() + container.hidden_items.len() + 1;
+            let _ = container.add_tray_item(TrayIcon::detached(
+                window as xproto::Window,
+                class_name,
+                Some(class_name),
+            ));
+        }
+    }
+
+    fn item_hint_labels(container: &TrayContainer) -> Vec<Option<(&str, usize)>> {
+        container
+            .tray_items
+            .iter()
+            .map(TrayItem::hint_label)
+            .collect()
+    }
+
+    fn visible_class_names(container: &TrayContainer) -> Vec<&str> {
+        container
+            .visible_indices()
+            .into_iter()
+            .map(|index| container.tray_items[index].icon().class_name().unwrap())
+            .collect()
+    }
+
+    fn class_names(container: &TrayContainer) -> Vec<&str> {
+        container
+            .tray_items
+            .iter()
+            .map(|tray_item| tray_item.icon().class_name().unwrap())
+            .collect()
+    }
+}
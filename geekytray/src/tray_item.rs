@@ -1,31 +1,64 @@
-use geekytray_shell::event::MouseButton;
+use geekytray_shell::event::{Modifiers, MouseButton, ScrollDirection};
 use geekytray_shell::geometrics::{PhysicalPoint, Point, Rect, Size};
 use geekytray_shell::graphics::{
     CacheKey, FontDescription, HorizontalAlign, RenderContext, RenderOp, Text, VerticalAlign,
 };
 use geekytray_shell::window::{Effect, Layout, Widget};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::mem;
+use std::ops::Range;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+use x11rb::connection::Connection as _;
+use x11rb::errors::ReplyError;
 use x11rb::protocol;
 use x11rb::protocol::xproto;
 use x11rb::protocol::xproto::ConnectionExt as _;
+use x11rb::xcb_ffi::XCBConnection;
 
-use crate::config::UiConfig;
-use crate::tray_manager::TrayIcon;
+use crate::command::ClickPosition;
+use crate::config::{ClickBackend, UiConfig};
+use crate::dbus_menu::DBusMenu;
+use crate::rule::Rule;
+use crate::status_notifier_host::StatusNotifierItem;
+use crate::tray_manager::{TitleSource, TrayIcon};
+
+const MAX_MESSAGE_LINES: usize = 3;
+const MESSAGE_LINE_HEIGHT: f64 = 1.5;
+const MESSAGE_ALPHA: u8 = 0xc0;
+const FALLBACK_TITLE_ALPHA: u8 = 0xa0;
+const HINT_BACKGROUND_ALPHA: u8 = 0xe0;
+// The maximum time in milliseconds between the clicks of a double-click.
+const DOUBLE_CLICK_TIME: xproto::Timestamp = 400;
+// The time between the clicks of a synthesized double-click.
+const DOUBLE_CLICK_INTERVAL: xproto::Timestamp = DOUBLE_CLICK_TIME / 8;
 
 #[derive(Debug)]
 pub struct TrayItem {
-    icon: TrayIcon,
+    icon: TrayItemIcon,
     is_selected: bool,
     is_pressed: bool,
+    is_hovered: bool,
+    click_history: ClickHistory,
+    menu_request: Option<MouseButton>,
+    message: Option<TrayMessage>,
     item_font: FontDescription,
     selected_item_font: FontDescription,
     config: Rc<UiConfig>,
     image_cache_key: CacheKey,
+    // The bounds of the icon in the container window at the last render.
+    icon_bounds: Cell<Option<Rect>>,
+    rule_index: Option<usize>,
+    renamed_title: Option<String>,
+    click_backend: Option<ClickBackend>,
+    title_highlights: Vec<Range<usize>>,
+    hint_label: Option<(String, usize)>,
 }
 
 impl TrayItem {
     pub fn new(
-        icon: TrayIcon,
+        icon: TrayItemIcon,
         item_font: FontDescription,
         selected_item_font: FontDescription,
         config: Rc<UiConfig>,
@@ -34,35 +67,445 @@ impl TrayItem {
             icon,
             is_selected: false,
             is_pressed: false,
+            is_hovered: false,
+            click_history: ClickHistory::default(),
+            menu_request: None,
+            message: None,
             item_font,
             selected_item_font,
             config,
             image_cache_key: CacheKey::next(),
+            icon_bounds: Cell::new(None),
+            rule_index: None,
+            renamed_title: None,
+            click_backend: None,
+            title_highlights: Vec::new(),
+            hint_label: None,
         }
     }
 
-    pub fn window(&self) -> xproto::Window {
-        self.icon.window()
+    pub fn id(&self) -> TrayItemId {
+        self.icon.id()
+    }
+
+    pub fn title(&self) -> &str {
+        self.renamed_title
+            .as_deref()
+            .unwrap_or_else(|| self.icon.title())
+    }
+
+    pub fn icon(&self) -> &TrayItemIcon {
+        &self.icon
+    }
+
+    pub fn rule_index(&self) -> Option<usize> {
+        self.rule_index
+    }
+
+    pub fn set_config(
+        &mut self,
+        item_font: FontDescription,
+        selected_item_font: FontDescription,
+        config: Rc<UiConfig>,
+    ) {
+        self.item_font = item_font;
+        self.selected_item_font = selected_item_font;
+        self.config = config;
+    }
+
+    pub fn set_rule(&mut self, rule: Option<(usize, &Rule)>) {
+        self.rule_index = rule.map(|(index, _)| index);
+        self.renamed_title = rule.and_then(|(_, rule)| rule.rename().map(str::to_owned));
+        self.click_backend = rule.and_then(|(_, rule)| rule.click_backend());
+    }
+
+    fn click_backend(&self) -> ClickBackend {
+        self.click_backend.unwrap_or(self.config.click_backend)
+    }
+
+    pub fn set_title_highlights(&mut self, highlights: Vec<Range<usize>>) {
+        self.title_highlights = highlights;
+    }
+
+    pub fn height(&self) -> f64 {
+        self.config.item_height()
+            + self
+                .message
+                .as_ref()
+                .filter(|_| self.config.show_label)
+                .map_or(0.0, |message| message.height(self.config.text_size))
+    }
+
+    pub fn set_hint_label(&mut self, hint_label: Option<(String, usize)>) {
+        self.hint_label = hint_label;
+    }
+
+    #[cfg(test)]
+    pub fn hint_label(&self) -> Option<(&str, usize)> {
+        self.hint_label
+            .as_ref()
+            .map(|(label, typed_len)| (label.as_str(), *typed_len))
+    }
+
+    pub fn context_menu(&self, button: MouseButton) -> Option<DBusMenu> {
+        match &self.icon {
+            TrayItemIcon::XEmbed(_) => None,
+            TrayItemIcon::StatusNotifier(item) => item.context_menu(button),
+        }
     }
 
-    pub fn update_icon(&mut self, icon: TrayIcon) -> Effect {
+    pub fn take_menu_request(&mut self) -> Option<MouseButton> {
+        self.menu_request.take()
+    }
+
+    pub fn message_received_at(&self) -> Option<Instant> {
+        self.message.as_ref().map(|message| message.received_at)
+    }
+
+    pub fn show_message(&mut self, id: u32, text: String) -> Effect {
+        self.message = Some(TrayMessage {
+            id,
+            text,
+            received_at: Instant::now(),
+        });
+        Effect::RequestLayout
+    }
+
+    pub fn remove_message(&mut self, id: Option<u32>) -> Effect {
+        match &self.message {
+            Some(message) if id.map_or(true, |id| message.id == id) => {
+                self.message = None;
+                Effect::RequestLayout
+            }
+            _ => Effect::None,
+        }
+    }
+
+    pub fn update_icon(&mut self, icon: TrayItemIcon) -> Effect {
         self.icon = icon;
         Effect::RequestRedraw
     }
 
-    pub fn click_item(&mut self, button: MouseButton) -> Effect {
-        let icon = self.icon.clone();
-        let (button, button_mask) = match button {
-            MouseButton::Left => (xproto::ButtonIndex::M1, xproto::ButtonMask::M1),
-            MouseButton::Right => (xproto::ButtonIndex::M3, xproto::ButtonMask::M3),
-            MouseButton::Middle => (xproto::ButtonIndex::M2, xproto::ButtonMask::M2),
-            MouseButton::X1 => (xproto::ButtonIndex::M4, xproto::ButtonMask::M4),
-            MouseButton::X2 => (xproto::ButtonIndex::M5, xproto::ButtonMask::M5),
+    pub fn click_item(
+        &mut self,
+        button: MouseButton,
+        modifiers: Modifiers,
+        position: Option<ClickPosition>,
+        time: xproto::Timestamp,
+    ) -> Effect {
+        match &self.icon {
+            TrayItemIcon::XEmbed(icon) => {
+                let icon = icon.clone();
+                let (button, state) = button_and_state(button, modifiers);
+                let position = position.map(|position| (position.x, position.y));
+                let click_backend = self.click_backend();
+                Effect::action(move |connection, _, _| {
+                    click_tray_icon(
+                        connection,
+                        &icon,
+                        click_backend,
+                        button,
+                        state,
+                        position,
+                        time,
+                    )?;
+                    Ok(Effect::None)
+                })
+            }
+            TrayItemIcon::StatusNotifier(item) => {
+                let item = item.clone();
+                let bounds = self.icon_bounds.get().unwrap_or_default();
+                // Clicks at the position relative to the icon, or at the
+                // center of the icon like XEmbed icons.
+                let (x, y) = match position {
+                    Some(position) => (bounds.x as i16 + position.x, bounds.y as i16 + position.y),
+                    None => (
+                        (bounds.x + bounds.width / 2.0) as i16,
+                        (bounds.y + bounds.height / 2.0) as i16,
+                    ),
+                };
+                Effect::action(move |connection, screen_num, window| {
+                    let screen = &connection.setup().roots[screen_num];
+                    let position = connection
+                        .translate_coordinates(window, screen.root, x, y)?
+                        .reply()?;
+                    click_status_notifier_item(
+                        &item,
+                        button,
+                        position.dst_x as i32,
+                        position.dst_y as i32,
+                    );
+                    Ok(Effect::None)
+                })
+            }
+        }
+    }
+
+    // StatusNotifierItem has no notion of double-clicks, so it is activated
+    // only once.
+    pub fn double_click_item(
+        &mut self,
+        button: MouseButton,
+        modifiers: Modifiers,
+        position: Option<ClickPosition>,
+        time: xproto::Timestamp,
+    ) -> Effect {
+        match &self.icon {
+            TrayItemIcon::XEmbed(_) => {
+                let effect = self.click_item(button, modifiers, position, time);
+                effect
+                    + self
+                        .click_item(
+                            button,
+                            modifiers,
+                            position,
+                            advance_time(time, DOUBLE_CLICK_INTERVAL),
+                        )
+                        .delay(Duration::from_millis(DOUBLE_CLICK_INTERVAL.into()))
+            }
+            TrayItemIcon::StatusNotifier(_) => self.click_item(button, modifiers, position, time),
+        }
+    }
+
+    // Presses the button and releases it after the duration. StatusNotifierItem
+    // is just clicked since it can not be pressed.
+    pub fn hold_item(
+        &mut self,
+        button: MouseButton,
+        modifiers: Modifiers,
+        position: Option<ClickPosition>,
+        duration: Duration,
+        time: xproto::Timestamp,
+    ) -> Effect {
+        match &self.icon {
+            TrayItemIcon::XEmbed(icon) => {
+                let (button, state) = button_and_state(button, modifiers);
+                let position = position.map(|position| (position.x, position.y));
+                let click_backend = self.click_backend();
+                let press_icon = icon.clone();
+                let release_icon = icon.clone();
+                let release_time = advance_time(
+                    time,
+                    duration
+                        .as_millis()
+                        .try_into()
+                        .unwrap_or(xproto::Timestamp::MAX),
+                );
+                let press = Effect::action(move |connection, _, _| {
+                    send_tray_icon_button_events(
+                        connection,
+                        &press_icon,
+                        click_backend,
+                        &[(xproto::BUTTON_PRESS_EVENT, time)],
+                        button,
+                        state,
+                        position,
+                    )?;
+                    Ok(Effect::None)
+                });
+                let release = Effect::action(move |connection, _, _| {
+                    send_tray_icon_button_events(
+                        connection,
+                        &release_icon,
+                        click_backend,
+                        &[(xproto::BUTTON_RELEASE_EVENT, release_time)],
+                        button,
+                        state,
+                        position,
+                    )?;
+                    Ok(Effect::None)
+                });
+                press + release.delay(duration)
+            }
+            TrayItemIcon::StatusNotifier(_) => self.click_item(button, modifiers, position, time),
+        }
+    }
+
+    pub fn scroll_item(&mut self, direction: ScrollDirection, steps: usize) -> Effect {
+        match &self.icon {
+            TrayItemIcon::XEmbed(icon) => {
+                let icon = icon.clone();
+                let button = scroll_button(direction);
+                let click_backend = self.click_backend();
+                Effect::action(move |connection, _, _| {
+                    for _ in 0..steps {
+                        click_tray_icon(
+                            connection,
+                            &icon,
+                            click_backend,
+                            button,
+                            0,
+                            None,
+                            x11rb::CURRENT_TIME,
+                        )?;
+                    }
+                    Ok(Effect::None)
+                })
+            }
+            TrayItemIcon::StatusNotifier(item) => {
+                let item = item.clone();
+                Effect::action(move |_, _, _| {
+                    scroll_status_notifier_item(&item, direction, steps);
+                    Ok(Effect::None)
+                })
+            }
+        }
+    }
+
+    pub fn hover_item(&mut self) -> Effect {
+        let center = (self.config.icon_size / 2.0) as i16;
+        self.hover_icon(center, center)
+    }
+
+    pub fn unhover_item(&mut self) -> Effect {
+        if !mem::replace(&mut self.is_hovered, false) {
+            return Effect::None;
+        }
+        match &self.icon {
+            TrayItemIcon::XEmbed(icon) => {
+                let icon = icon.clone();
+                Effect::action(move |connection, _, _| {
+                    icon.leave(connection, 0, 0)?;
+                    Ok(Effect::None)
+                })
+            }
+            TrayItemIcon::StatusNotifier(_) => Effect::None,
+        }
+    }
+
+    fn hover_icon(&mut self, x: i16, y: i16) -> Effect {
+        let is_entered = !mem::replace(&mut self.is_hovered, true);
+        match &self.icon {
+            TrayItemIcon::XEmbed(icon) => {
+                let icon = icon.clone();
+                Effect::action(move |connection, _, _| {
+                    if is_entered {
+                        icon.enter(connection, x, y)?;
+                    }
+                    icon.motion(connection, x, y)?;
+                    Ok(Effect::None)
+                })
+            }
+            TrayItemIcon::StatusNotifier(_) => Effect::None,
+        }
+    }
+
+    fn hover_pointer(
+        &mut self,
+        position: Point,
+        layout: &Layout,
+        pointer: PhysicalPoint,
+    ) -> Effect {
+        let bounds = Rect::new(position, layout.size);
+        if bounds.snap().contains(pointer) {
+            let (x, y) = self.icon_offset(position, pointer);
+            self.hover_icon(x, y)
+        } else {
+            self.unhover_item()
+        }
+    }
+
+    // Dispatches the click by the pointer. Clicks are sent right away, so the
+    // icon sees the double-click by itself from the times of the two clicks.
+    // StatusNotifierItem has no notion of double-clicks, so the second click
+    // is dropped as `double_click_item()` does.
+    fn release_button(
+        &mut self,
+        event: &xproto::ButtonReleaseEvent,
+        position: ClickPosition,
+    ) -> Effect {
+        let button = match mouse_button_from_detail(event.detail) {
+            Some(button) => button,
+            None => return self.forward_button(event, position),
         };
-        return Effect::Action(Box::new(move |connection, screen_num, _| {
-            icon.click(connection, screen_num, button, button_mask)?;
-            Ok(Effect::None)
-        }));
+        let is_double_click = self.click_history.register_click(event.detail, event.time);
+
+        if self.context_menu(button).is_some() {
+            self.menu_request = Some(button);
+            return Effect::None;
+        }
+
+        if button == MouseButton::Left
+            && is_double_click
+            && matches!(self.icon, TrayItemIcon::StatusNotifier(_))
+        {
+            return Effect::None;
+        }
+
+        let modifiers = Modifiers::from(event.state);
+        self.click_pointer(event, button, modifiers, position)
+    }
+
+    fn click_pointer(
+        &mut self,
+        event: &xproto::ButtonReleaseEvent,
+        button: MouseButton,
+        modifiers: Modifiers,
+        position: ClickPosition,
+    ) -> Effect {
+        match &self.icon {
+            TrayItemIcon::XEmbed(_) => {
+                self.click_item(button, modifiers, Some(position), event.time)
+            }
+            TrayItemIcon::StatusNotifier(item) => {
+                let item = item.clone();
+                let (x, y) = (event.root_x as i32, event.root_y as i32);
+                Effect::action(move |_, _, _| {
+                    click_status_notifier_item(&item, button, x, y);
+                    Ok(Effect::None)
+                })
+            }
+        }
+    }
+
+    // Forwards the button other than mouse buttons, e.g. the wheel, as it is.
+    fn forward_button(
+        &mut self,
+        event: &xproto::ButtonReleaseEvent,
+        position: ClickPosition,
+    ) -> Effect {
+        match &self.icon {
+            TrayItemIcon::XEmbed(icon) => {
+                let icon = icon.clone();
+                let button = event.detail.into();
+                let state = event.state;
+                let click_backend = self.click_backend();
+                let time = event.time;
+                Effect::action(move |connection, _, _| {
+                    click_tray_icon(
+                        connection,
+                        &icon,
+                        click_backend,
+                        button,
+                        state,
+                        Some((position.x, position.y)),
+                        time,
+                    )?;
+                    Ok(Effect::None)
+                })
+            }
+            TrayItemIcon::StatusNotifier(item) => {
+                match scroll_direction_from_detail(event.detail) {
+                    Some(direction) => {
+                        let item = item.clone();
+                        Effect::action(move |_, _, _| {
+                            scroll_status_notifier_item(&item, direction, 1);
+                            Ok(Effect::None)
+                        })
+                    }
+                    None => Effect::None,
+                }
+            }
+        }
+    }
+
+    // Translates the pointer into the icon. Points outside of the icon are
+    // moved onto its edge.
+    fn icon_offset(&self, position: Point, pointer: PhysicalPoint) -> (i16, i16) {
+        let max_offset = self.config.icon_size as i32 - 1;
+        let x = (pointer.x - (position.x + self.config.item_padding) as i32).clamp(0, max_offset);
+        let y = (pointer.y - (position.y + self.config.item_padding) as i32).clamp(0, max_offset);
+        (x as i16, y as i16)
     }
 
     pub fn select_item(&mut self) -> Effect {
@@ -74,6 +517,22 @@ impl TrayItem {
         self.is_selected = false;
         Effect::RequestRedraw
     }
+
+    // Unmaps the embedded icon of an item out of the layout, so that it does
+    // not take the input over other items. The icon is mapped again by the
+    // next render of the item, which replaces this cached operation.
+    pub fn render_hidden(&self) -> RenderOp {
+        match &self.icon {
+            TrayItemIcon::XEmbed(icon) if icon.should_map() => {
+                let embedder = icon.embedder();
+                RenderOp::memoize(self.image_cache_key, (), move |connection, _, _| {
+                    connection.unmap_window(embedder)?.check()?;
+                    Ok(RenderOp::None)
+                })
+            }
+            _ => RenderOp::None,
+        }
+    }
 }
 
 impl Widget for TrayItem {
@@ -109,83 +568,168 @@ impl Widget for TrayItem {
             result = result + RenderOp::Rect(bg_color, Rect::new(position, layout.size));
         }
 
-        let title = if self.config.show_number {
-            format!("{}. {}", index + 1, self.icon.title())
-        } else {
-            self.icon.title().to_owned()
-        };
-
         let font = if self.is_selected {
             self.selected_item_font.clone()
         } else {
             self.item_font.clone()
         };
 
-        result = result
-            + RenderOp::Text(
-                fg_color,
-                Rect {
-                    x: position.x + (self.config.icon_size + self.config.item_padding * 2.0),
-                    y: position.y,
-                    width: layout.size.width
-                        - (self.config.icon_size + self.config.item_padding * 3.0),
-                    height: layout.size.height,
-                },
-                Text {
-                    content: title.into(),
-                    font,
-                    size: self.config.text_size,
-                    horizontal_align: HorizontalAlign::Left,
-                    vertical_align: VerticalAlign::Middle,
-                },
-            );
-
-        if self.icon.should_map() {
-            let icon_window = self.icon.window();
-            let bounds = Rect::new(
-                Point {
-                    x: position.x + self.config.item_padding,
-                    y: position.y + self.config.item_padding,
-                },
-                Size {
-                    width: self.config.icon_size,
-                    height: self.config.icon_size,
-                },
-            );
+        if self.config.show_label {
+            let prefix = if self.config.show_number {
+                format!("{}. ", index + 1)
+            } else {
+                String::new()
+            };
+            let title = format!("{}{}", prefix, self.title());
+            let highlights = self
+                .title_highlights
+                .iter()
+                .map(|range| range.start + prefix.len()..range.end + prefix.len())
+                .collect();
+
+            // Titles that are not the window name are guessed, so they are dimmed.
+            let title_color = if self.renamed_title.is_none() && self.icon.has_fallback_title() {
+                fg_color.with_alpha(FALLBACK_TITLE_ALPHA)
+            } else {
+                fg_color
+            };
 
             result = result
-                + RenderOp::memoize(
-                    self.image_cache_key,
-                    (self.icon.version(), bounds),
-                    move |connection, _, _| {
-                        {
-                            let values = xproto::ConfigureWindowAux::new()
-                                .x(bounds.x as i32)
-                                .y(bounds.y as i32)
-                                .width(bounds.width as u32)
-                                .height(bounds.height as u32);
-                            connection.configure_window(icon_window, &values)?.check()?;
-                        }
-
-                        connection.map_window(icon_window)?.check()?;
-
-                        let render_op = connection
-                            .get_image(
-                                xproto::ImageFormat::Z_PIXMAP,
+                + RenderOp::Text(
+                    title_color,
+                    Rect {
+                        x: position.x + (self.config.icon_size + self.config.item_padding * 2.0),
+                        y: position.y,
+                        width: layout.size.width
+                            - (self.config.icon_size + self.config.item_padding * 3.0),
+                        height: self.config.item_height(),
+                    },
+                    Text {
+                        content: title.into(),
+                        font: font.clone(),
+                        size: self.config.text_size,
+                        horizontal_align: HorizontalAlign::Left,
+                        vertical_align: VerticalAlign::Middle,
+                        highlights,
+                    },
+                );
+
+            if let Some(message) = &self.message {
+                result = result
+                    + RenderOp::Text(
+                        fg_color.with_alpha(MESSAGE_ALPHA),
+                        Rect {
+                            x: position.x
+                                + (self.config.icon_size + self.config.item_padding * 2.0),
+                            y: position.y + self.config.item_height() - self.config.item_padding,
+                            width: layout.size.width
+                                - (self.config.icon_size + self.config.item_padding * 3.0),
+                            height: message.height(self.config.text_size),
+                        },
+                        Text {
+                            content: message.text.clone().into(),
+                            font,
+                            size: self.config.text_size,
+                            horizontal_align: HorizontalAlign::Left,
+                            vertical_align: VerticalAlign::Top,
+                            highlights: Vec::new(),
+                        },
+                    );
+            }
+        }
+
+        let bounds = Rect::new(
+            Point {
+                x: position.x + self.config.item_padding,
+                y: position.y + self.config.item_padding,
+            },
+            Size {
+                width: self.config.icon_size,
+                height: self.config.icon_size,
+            },
+        );
+        self.icon_bounds.set(Some(bounds));
+
+        match &self.icon {
+            TrayItemIcon::XEmbed(icon) if icon.should_map() => {
+                let icon_window = icon.window();
+                let embedder = icon.embedder();
+                let revision = icon.version();
+                result = result
+                    + RenderOp::memoize(
+                        self.image_cache_key,
+                        (revision, bounds),
+                        move |connection, _, _| {
+                            {
+                                let values = xproto::ConfigureWindowAux::new()
+                                    .x(bounds.x as i32)
+                                    .y(bounds.y as i32)
+                                    .width(bounds.width as u32)
+                                    .height(bounds.height as u32);
+                                connection.configure_window(embedder, &values)?.check()?;
+                            }
+
+                            {
+                                let values = xproto::ConfigureWindowAux::new()
+                                    .x(0)
+                                    .y(0)
+                                    .width(bounds.width as u32)
+                                    .height(bounds.height as u32);
+                                connection.configure_window(icon_window, &values)?.check()?;
+                            }
+
+                            connection.map_window(icon_window)?.check()?;
+                            connection.map_window(embedder)?.check()?;
+
+                            Ok(RenderOp::CompositeWindow(
                                 icon_window,
-                                0,
-                                0,
-                                bounds.width as u16,
-                                bounds.height as u16,
-                                0xffffffff,
-                            )?
-                            .reply()
-                            .ok()
-                            .map_or(RenderOp::None, |reply| {
-                                RenderOp::Image(Rc::new(reply.data), bounds, reply.depth)
-                            });
-
-                        Ok(render_op)
+                                bounds,
+                                revision as usize,
+                            ))
+                        },
+                    );
+            }
+            TrayItemIcon::StatusNotifier(item) => {
+                if let Some(image) = item.icon().cloned() {
+                    result = result
+                        + RenderOp::memoize(
+                            self.image_cache_key,
+                            (item.version(), bounds),
+                            move |_, _, _| {
+                                let image = image.scale(bounds.width as u32, bounds.height as u32);
+                                Ok(RenderOp::Image(Rc::new(image.to_bytes()), bounds, 32))
+                            },
+                        );
+                }
+            }
+            _ => {}
+        }
+
+        if let Some((label, typed_len)) = &self.hint_label {
+            result = result
+                + RenderOp::Rect(
+                    self.config
+                        .selected_item_background
+                        .with_alpha(HINT_BACKGROUND_ALPHA),
+                    bounds,
+                )
+                + RenderOp::Text(
+                    self.config.selected_item_foreground,
+                    bounds,
+                    Text {
+                        content: label.clone().into(),
+                        font: self.selected_item_font.clone(),
+                        size: self.config.text_size,
+                        horizontal_align: HorizontalAlign::Center,
+                        vertical_align: VerticalAlign::Middle,
+                        highlights: if *typed_len > 0 {
+                            vec![Range {
+                                start: 0,
+                                end: *typed_len,
+                            }]
+                        } else {
+                            Vec::new()
+                        },
                     },
                 );
         }
@@ -197,7 +741,7 @@ impl Widget for TrayItem {
         Layout {
             size: Size {
                 width: container_size.width as f64,
-                height: self.config.item_height(),
+                height: self.height(),
             },
             children: Vec::new(),
         }
@@ -226,18 +770,30 @@ impl Widget for TrayItem {
                 if self.is_pressed {
                     self.is_pressed = false;
                     if bounds.snap().contains(pointer_position) {
-                        let icon = self.icon.clone();
-                        let button = event.detail.into();
-                        let button_mask = event.state.into();
-                        return Effect::Action(Box::new(move |connection, screen_num, _| {
-                            icon.click(connection, screen_num, button, button_mask)?;
-                            Ok(Effect::None)
-                        }));
+                        let (x, y) = self.icon_offset(position, pointer_position);
+                        return self.release_button(event, ClickPosition { x, y });
                     }
                 }
             }
-            LeaveNotify(_) => {
+            EnterNotify(event) => {
+                let pointer_position = PhysicalPoint {
+                    x: event.event_x as _,
+                    y: event.event_y as _,
+                };
+                return self.hover_pointer(position, layout, pointer_position);
+            }
+            MotionNotify(event) => {
+                let pointer_position = PhysicalPoint {
+                    x: event.event_x as _,
+                    y: event.event_y as _,
+                };
+                return self.hover_pointer(position, layout, pointer_position);
+            }
+            LeaveNotify(event) => {
                 self.is_pressed = false;
+                if event.detail != xproto::NotifyDetail::INFERIOR {
+                    return self.unhover_item();
+                }
             }
             _ => {}
         }
@@ -245,3 +801,254 @@ impl Widget for TrayItem {
         Effect::None
     }
 }
+
+// Recognizes double-clicks from the time of the button events.
+#[derive(Debug, Default)]
+struct ClickHistory {
+    last_click: Option<(u8, xproto::Timestamp)>,
+}
+
+impl ClickHistory {
+    // Returns whether the click makes a double-click with the previous one.
+    fn register_click(&mut self, detail: u8, time: xproto::Timestamp) -> bool {
+        let is_double_click = matches!(
+            self.last_click,
+            Some((last_detail, last_time))
+                if last_detail == detail && time.wrapping_sub(last_time) <= DOUBLE_CLICK_TIME
+        );
+        self.last_click = if is_double_click {
+            None
+        } else {
+            Some((detail, time))
+        };
+        is_double_click
+    }
+}
+
+#[derive(Debug)]
+struct TrayMessage {
+    id: u32,
+    text: String,
+    received_at: Instant,
+}
+
+impl TrayMessage {
+    fn height(&self, text_size: f64) -> f64 {
+        let lines = self.text.lines().count().clamp(1, MAX_MESSAGE_LINES);
+        lines as f64 * text_size * MESSAGE_LINE_HEIGHT
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum TrayItemIcon {
+    XEmbed(TrayIcon),
+    StatusNotifier(StatusNotifierItem),
+}
+
+impl TrayItemIcon {
+    pub fn id(&self) -> TrayItemId {
+        match self {
+            Self::XEmbed(icon) => TrayItemId::XEmbed(icon.window()),
+            Self::StatusNotifier(item) => TrayItemId::StatusNotifier(item.service().to_owned()),
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        match self {
+            Self::XEmbed(icon) => icon.title(),
+            Self::StatusNotifier(item) => item.title(),
+        }
+    }
+
+    pub fn class_name(&self) -> Option<&str> {
+        match self {
+            Self::XEmbed(icon) => icon.class_name(),
+            Self::StatusNotifier(item) => Some(item.id()).filter(|id| !id.is_empty()),
+        }
+    }
+
+    pub fn process_name(&self) -> Option<&str> {
+        match self {
+            Self::XEmbed(icon) => icon.process_name(),
+            Self::StatusNotifier(_) => None,
+        }
+    }
+
+    pub fn key(&self) -> Option<TrayItemKey> {
+        let class = self.class_name().map(str::to_owned);
+        let process = self.process_name().map(str::to_owned);
+        if class.is_none() && process.is_none() {
+            None
+        } else {
+            Some(TrayItemKey { class, process })
+        }
+    }
+
+    pub fn has_fallback_title(&self) -> bool {
+        match self {
+            Self::XEmbed(icon) => icon
+                .title_source()
+                .map_or(false, |source| source != TitleSource::WindowName),
+            Self::StatusNotifier(_) => false,
+        }
+    }
+}
+
+impl From<TrayIcon> for TrayItemIcon {
+    fn from(icon: TrayIcon) -> Self {
+        Self::XEmbed(icon)
+    }
+}
+
+impl From<StatusNotifierItem> for TrayItemIcon {
+    fn from(item: StatusNotifierItem) -> Self {
+        Self::StatusNotifier(item)
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum TrayItemId {
+    XEmbed(xproto::Window),
+    StatusNotifier(String),
+}
+
+// Unlike `TrayItemId`, it identifies the application across restarts.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Deserialize, Serialize)]
+pub struct TrayItemKey {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<String>,
+}
+
+fn click_tray_icon(
+    connection: &XCBConnection,
+    icon: &TrayIcon,
+    click_backend: ClickBackend,
+    button: xproto::ButtonIndex,
+    state: u16,
+    position: Option<(i16, i16)>,
+    time: xproto::Timestamp,
+) -> Result<(), ReplyError> {
+    match click_backend {
+        ClickBackend::Synthetic => icon.click(connection, button, state, position, time),
+        ClickBackend::XTest => icon.fake_click(connection, button, state, position, time),
+    }
+}
+
+fn send_tray_icon_button_events(
+    connection: &XCBConnection,
+    icon: &TrayIcon,
+    click_backend: ClickBackend,
+    events: &[(u8, xproto::Timestamp)],
+    button: xproto::ButtonIndex,
+    state: u16,
+    position: Option<(i16, i16)>,
+) -> Result<(), ReplyError> {
+    match click_backend {
+        ClickBackend::Synthetic => {
+            icon.send_button_events(connection, events, button, state, position)
+        }
+        ClickBackend::XTest => icon.fake_button_events(connection, events, button, state, position),
+    }
+}
+
+// Advances the time of the synthesized event. `CURRENT_TIME` is kept as is
+// since it is not a real time.
+fn advance_time(time: xproto::Timestamp, milliseconds: xproto::Timestamp) -> xproto::Timestamp {
+    if time == x11rb::CURRENT_TIME {
+        time
+    } else {
+        time.wrapping_add(milliseconds)
+    }
+}
+
+fn click_status_notifier_item(item: &StatusNotifierItem, button: MouseButton, x: i32, y: i32) {
+    if let Err(error) = item.click(button, x, y) {
+        log::warn!(
+            "Failed to click StatusNotifierItem `{}`: {}",
+            item.service(),
+            error
+        );
+    }
+}
+
+fn scroll_status_notifier_item(
+    item: &StatusNotifierItem,
+    direction: ScrollDirection,
+    steps: usize,
+) {
+    if let Err(error) = item.scroll(direction, steps) {
+        log::warn!(
+            "Failed to scroll StatusNotifierItem `{}`: {}",
+            item.service(),
+            error
+        );
+    }
+}
+
+fn button_and_state(button: MouseButton, modifiers: Modifiers) -> (xproto::ButtonIndex, u16) {
+    let (button, button_mask) = match button {
+        MouseButton::Left => (xproto::ButtonIndex::M1, xproto::ButtonMask::M1),
+        MouseButton::Right => (xproto::ButtonIndex::M3, xproto::ButtonMask::M3),
+        MouseButton::Middle => (xproto::ButtonIndex::M2, xproto::ButtonMask::M2),
+        MouseButton::X1 => (xproto::ButtonIndex::from(8), xproto::ButtonMask::from(0u16)),
+        MouseButton::X2 => (xproto::ButtonIndex::from(9), xproto::ButtonMask::from(0u16)),
+    };
+    (button, u16::from(button_mask) | u16::from(modifiers))
+}
+
+fn scroll_button(direction: ScrollDirection) -> xproto::ButtonIndex {
+    match direction {
+        ScrollDirection::Up => xproto::ButtonIndex::M4,
+        ScrollDirection::Down => xproto::ButtonIndex::M5,
+        ScrollDirection::Left => xproto::ButtonIndex::from(6),
+        ScrollDirection::Right => xproto::ButtonIndex::from(7),
+    }
+}
+
+fn scroll_direction_from_detail(detail: u8) -> Option<ScrollDirection> {
+    match detail {
+        4 => Some(ScrollDirection::Up),
+        5 => Some(ScrollDirection::Down),
+        6 => Some(ScrollDirection::Left),
+        7 => Some(ScrollDirection::Right),
+        _ => None,
+    }
+}
+
+fn mouse_button_from_detail(detail: u8) -> Option<MouseButton> {
+    match detail {
+        1 => Some(MouseButton::Left),
+        2 => Some(MouseButton::Middle),
+        3 => Some(MouseButton::Right),
+        8 => Some(MouseButton::X1),
+        9 => Some(MouseButton::X2),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_click() {
+        let mut click_history = ClickHistory::default();
+        assert!(!click_history.register_click(1, 1000));
+        assert!(click_history.register_click(1, 1000 + DOUBLE_CLICK_TIME));
+        // The double-click is not continued by the third click.
+        assert!(!click_history.register_click(1, 1000 + DOUBLE_CLICK_TIME + 1));
+        assert!(!click_history.register_click(1, 1000 + DOUBLE_CLICK_TIME * 3));
+        // Clicks of different buttons do not make a double-click.
+        assert!(!click_history.register_click(3, 1000 + DOUBLE_CLICK_TIME * 3 + 1));
+        assert!(!click_history.register_click(1, 1000 + DOUBLE_CLICK_TIME * 3 + 2));
+        // The time wraps around after about 49.7 days.
+        let mut click_history = ClickHistory::default();
+        assert!(!click_history.register_click(1, xproto::Timestamp::MAX - 100));
+        assert!(click_history.register_click(1, 100));
+        let mut click_history = ClickHistory::default();
+        assert!(!click_history.register_click(1, xproto::Timestamp::MAX - 100));
+        assert!(!click_history.register_click(1, DOUBLE_CLICK_TIME));
+    }
+}
@@ -1,7 +1,9 @@
 use geekytray_shell::graphics::Color;
 use std::collections::hash_map;
 use std::collections::HashMap;
+use std::fs;
 use std::mem;
+use std::path::Path;
 use std::rc::Rc;
 use std::str;
 use std::time::Duration;
@@ -14,14 +16,23 @@ use x11rb::protocol::damage;
 use x11rb::protocol::damage::ConnectionExt as _;
 use x11rb::protocol::xproto;
 use x11rb::protocol::xproto::ConnectionExt as _;
+use x11rb::protocol::xtest::ConnectionExt as _;
 use x11rb::wrapper::ConnectionExt as _;
 
-use crate::xembed::{XEmbedInfo, XEmbedMessage};
+use geekytray_shell::event::{Keysym, Modifiers};
+
+use crate::config::SelectionPolicy;
+use crate::xembed::{
+    XEmbedAccelerator, XEmbedFocus, XEmbedInfo, XEmbedMessage, XEMBED_ACCELERATOR_OVERLOADED,
+};
 
 const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
 const SYSTEM_TRAY_BEGIN_MESSAGE: u32 = 1;
 const SYSTEM_TRAY_CANCEL_MESSAGE: u32 = 2;
 
+// The bit of `same_screen_focus` in crossing events.
+const SAME_SCREEN_FLAG: u8 = 1 << 1;
+
 #[derive(Debug)]
 pub struct TrayManager<C: Connection> {
     connection: Rc<C>,
@@ -32,6 +43,8 @@ pub struct TrayManager<C: Connection> {
     system_tray_selection_atom: xproto::Atom,
     embedded_icons: HashMap<xproto::Window, TrayIcon>,
     balloon_messages: HashMap<xproto::Window, BalloonMessage>,
+    focused_icon: Option<xproto::Window>,
+    accelerators: HashMap<xproto::Window, HashMap<u32, XEmbedAccelerator>>,
 }
 
 impl<C: Connection> TrayManager<C> {
@@ -93,21 +106,9 @@ impl<C: Connection> TrayManager<C> {
                     &[visual_id],
                 )?
                 .check()?;
-
-            connection
-                .change_property(
-                    xproto::PropMode::REPLACE,
-                    manager_window,
-                    atoms._NET_SYSTEM_TRAY_COLORS,
-                    xproto::AtomEnum::CARDINAL,
-                    32,
-                    12,
-                    colors.as_bytes(),
-                )?
-                .check()?;
         }
 
-        Ok(Self {
+        let tray_manager = Self {
             connection,
             screen_num,
             manager_window,
@@ -116,11 +117,47 @@ impl<C: Connection> TrayManager<C> {
             system_tray_selection_atom,
             embedded_icons: HashMap::new(),
             balloon_messages: HashMap::new(),
-        })
+            focused_icon: None,
+            accelerators: HashMap::new(),
+        };
+
+        tray_manager.set_colors(&colors)?;
+
+        Ok(tray_manager)
+    }
+
+    pub fn set_colors(&self, colors: &SystemTrayColors) -> Result<(), ReplyError> {
+        self.connection
+            .change_property(
+                xproto::PropMode::REPLACE,
+                self.manager_window,
+                self.atoms._NET_SYSTEM_TRAY_COLORS,
+                xproto::AtomEnum::CARDINAL,
+                32,
+                12,
+                colors.as_bytes(),
+            )?
+            .check()?;
+        self.connection.flush()?;
+        Ok(())
+    }
+
+    pub fn set_orientation(&self, orientation: SystemTrayOrientation) -> Result<(), ReplyError> {
+        self.connection
+            .change_property32(
+                xproto::PropMode::REPLACE,
+                self.manager_window,
+                self.atoms._NET_SYSTEM_TRAY_ORIENTATION,
+                xproto::AtomEnum::CARDINAL,
+                &[orientation.0],
+            )?
+            .check()?;
+        self.connection.flush()?;
+        Ok(())
     }
 
-    pub fn acquire_tray_selection(&mut self) -> Result<bool, ReplyError> {
-        if matches!(self.status, TrayStatus::Managed | TrayStatus::Pending(_)) {
+    pub fn acquire_tray_selection(&mut self, policy: SelectionPolicy) -> Result<bool, ReplyError> {
+        if !matches!(self.status, TrayStatus::Unmanaged) {
             return Ok(false);
         }
 
@@ -135,6 +172,25 @@ impl<C: Connection> TrayManager<C> {
             .reply()?;
         let previous_selection_owner = selection_owner_reply.owner;
 
+        if previous_selection_owner != x11rb::NONE {
+            match policy {
+                SelectionPolicy::Fail => {
+                    log::warn!(
+                        "System tray selection is already owned by window `{}`",
+                        previous_selection_owner
+                    );
+                    return Ok(false);
+                }
+                SelectionPolicy::Wait => {
+                    log::info!("Wait until the current selection owner is destroyed");
+                    self.watch_selection_owner(previous_selection_owner)?;
+                    self.status = TrayStatus::Waiting(previous_selection_owner);
+                    return Ok(true);
+                }
+                SelectionPolicy::Replace => {}
+            }
+        }
+
         self.connection
             .set_selection_owner(
                 self.manager_window,
@@ -148,20 +204,156 @@ impl<C: Connection> TrayManager<C> {
             self.status = TrayStatus::Managed;
         } else {
             log::info!("Wait until the previous selection owner is destroyed");
+            self.watch_selection_owner(previous_selection_owner)?;
 
-            let values = xproto::ChangeWindowAttributesAux::new()
-                .event_mask(Some(xproto::EventMask::STRUCTURE_NOTIFY.into()));
+            self.status = TrayStatus::Pending(previous_selection_owner);
+        }
 
-            self.connection
-                .change_window_attributes(previous_selection_owner, &values)?
-                .check()?;
+        Ok(true)
+    }
 
-            self.status = TrayStatus::Pending(previous_selection_owner);
+    pub fn screen_num(&self) -> usize {
+        self.screen_num
+    }
+
+    pub fn is_unmanaged(&self) -> bool {
+        matches!(self.status, TrayStatus::Unmanaged)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, TrayStatus::Pending(_) | TrayStatus::Waiting(_))
+    }
+
+    pub fn find_icon(&self, window: xproto::Window) -> Option<&TrayIcon> {
+        self.embedded_icons.get(&window)
+    }
+
+    pub fn activate_window(&self, is_active: bool) -> Result<(), ReplyError> {
+        let message = if is_active {
+            XEmbedMessage::WindowActivate
+        } else {
+            XEmbedMessage::WindowDeactivate
+        };
+        for icon in self.embedded_icons.values() {
+            icon.send_xembed_message(self.connection.as_ref(), &self.atoms, message, 0, 0, 0)?;
+        }
+        self.connection.flush()?;
+        Ok(())
+    }
+
+    pub fn focus_icon(
+        &mut self,
+        icon_window: Option<xproto::Window>,
+        focus: XEmbedFocus,
+    ) -> Result<(), ReplyError> {
+        let icon_window = icon_window.filter(|icon_window| {
+            self.embedded_icons
+                .get(icon_window)
+                .map_or(false, |icon| icon.xembed_info.is_some())
+        });
+        if self.focused_icon == icon_window {
+            return Ok(());
         }
 
+        if let Some(icon) = self
+            .focused_icon
+            .and_then(|focused_icon| self.embedded_icons.get(&focused_icon))
+        {
+            log::info!("Focus out tray icon window `{}`", icon.window);
+            icon.send_xembed_message(
+                self.connection.as_ref(),
+                &self.atoms,
+                XEmbedMessage::FocusOut,
+                0,
+                0,
+                0,
+            )?;
+        }
+
+        if let Some(icon) =
+            icon_window.and_then(|icon_window| self.embedded_icons.get(&icon_window))
+        {
+            log::info!("Focus in tray icon window `{}`", icon.window);
+            icon.send_xembed_message(
+                self.connection.as_ref(),
+                &self.atoms,
+                XEmbedMessage::FocusIn,
+                focus.into(),
+                0,
+                0,
+            )?;
+        }
+
+        self.focused_icon = icon_window;
+        self.connection.flush()?;
+
+        Ok(())
+    }
+
+    pub fn forward_key_event(&self, event: &xproto::KeyPressEvent) -> Result<bool, ReplyError> {
+        let icon_window = match self.focused_icon {
+            Some(icon_window) => icon_window,
+            None => return Ok(false),
+        };
+        let event = xproto::KeyPressEvent {
+            event: icon_window,
+            child: x11rb::NONE,
+            event_x: 0,
+            event_y: 0,
+            ..*event
+        };
+        self.connection
+            .send_event(false, icon_window, xproto::EventMask::NO_EVENT, event)?
+            .check()?;
+        self.connection.flush()?;
         Ok(true)
     }
 
+    pub fn find_accelerator(
+        &self,
+        keysym: Keysym,
+        modifiers: Modifiers,
+    ) -> Option<(xproto::Window, u32)> {
+        self.accelerators
+            .iter()
+            .find_map(|(icon_window, accelerators)| {
+                accelerators
+                    .iter()
+                    .find(|(_, accelerator)| accelerator.matches(keysym, modifiers))
+                    .map(|(id, _)| (*icon_window, *id))
+            })
+    }
+
+    pub fn activate_accelerator(
+        &self,
+        icon_window: xproto::Window,
+        id: u32,
+        is_overloaded: bool,
+    ) -> Result<(), ReplyError> {
+        if let Some(icon) = self.embedded_icons.get(&icon_window) {
+            log::info!(
+                "Activate accelerator {} of tray icon window `{}`",
+                id,
+                icon_window
+            );
+            let flags = if is_overloaded {
+                XEMBED_ACCELERATOR_OVERLOADED
+            } else {
+                0
+            };
+            icon.send_xembed_message(
+                self.connection.as_ref(),
+                &self.atoms,
+                XEmbedMessage::ActivateAccelerator,
+                id,
+                flags,
+                0,
+            )?;
+            self.connection.flush()?;
+        }
+        Ok(())
+    }
+
     pub fn process_event(
         &mut self,
         event: &protocol::Event,
@@ -171,11 +363,11 @@ impl<C: Connection> TrayManager<C> {
 
         let response = match event {
             DamageNotify(event) => {
-                log::debug!("Damage notified from drawable `{}`", event.drawable);
-                self.connection
-                    .damage_subtract(event.damage, x11rb::NONE, x11rb::NONE)?
-                    .check()?;
                 if let Some(icon) = self.embedded_icons.get_mut(&event.drawable) {
+                    log::debug!("Damage notified from drawable `{}`", event.drawable);
+                    self.connection
+                        .damage_subtract(event.damage, x11rb::NONE, x11rb::NONE)?
+                        .check()?;
                     icon.version = event.sequence;
                     Some(TrayEvent::TrayIconUpdated(icon.clone()))
                 } else {
@@ -185,28 +377,69 @@ impl<C: Connection> TrayManager<C> {
             ClientMessage(event) if event.type_ == self.atoms._NET_SYSTEM_TRAY_OPCODE => {
                 let data = event.data.as_data32();
                 let opcode = data[1];
-                if opcode == SYSTEM_TRAY_REQUEST_DOCK {
+                if opcode == SYSTEM_TRAY_REQUEST_DOCK && event.window == self.manager_window {
                     let icon_window = data[2];
                     log::info!("Dock request received from window `{}`", icon_window);
                     self.register_tray_icon(container_window, icon_window)?;
+                } else if !self.embedded_icons.contains_key(&event.window) {
+                    // The message is from an icon that is managed on another screen.
                 } else if opcode == SYSTEM_TRAY_BEGIN_MESSAGE {
                     log::info!("Begin tray message from window `{}`", event.window);
                     let balloon_message = BalloonMessage::new(event.data.as_data32());
                     self.balloon_messages.insert(event.window, balloon_message);
                 } else if opcode == SYSTEM_TRAY_CANCEL_MESSAGE {
                     log::info!("Cancel tray message from window `{}`", event.window);
-                    if let hash_map::Entry::Occupied(entry) =
-                        self.balloon_messages.entry(event.window)
-                    {
-                        let [_, _, id, ..] = event.data.as_data32();
-                        if entry.get().id == id {
+                    let [_, _, id, ..] = event.data.as_data32();
+                    match self.balloon_messages.entry(event.window) {
+                        hash_map::Entry::Occupied(entry) if entry.get().id == id => {
                             entry.remove();
                         }
+                        _ => return Ok(Some(TrayEvent::MessageCanceled(event.window, id))),
                     }
                 }
                 None
             }
-            ClientMessage(event) if event.type_ == self.atoms._NET_SYSTEM_TRAY_MESSAGE_DATA => {
+            // Each icon has its own embedder, which tells the sender.
+            ClientMessage(event) if event.type_ == self.atoms._XEMBED => {
+                let icon_window = self
+                    .embedded_icons
+                    .values()
+                    .find(|icon| icon.embedder == event.window)
+                    .map(|icon| icon.window);
+                let [_, message, detail, data1, data2] = event.data.as_data32();
+                match (icon_window, XEmbedMessage::from_u32(message)) {
+                    (None, Some(XEmbedMessage::RegisterAccelerator)) => {
+                        log::warn!(
+                            "Refuse accelerator {} from unknown embedder `{}`",
+                            detail,
+                            event.window
+                        );
+                        None
+                    }
+                    (None, _) => None,
+                    (Some(icon_window), Some(XEmbedMessage::RequestFocus)) => {
+                        Some(TrayEvent::FocusRequested(icon_window))
+                    }
+                    (Some(_), Some(XEmbedMessage::FocusNext)) => Some(TrayEvent::FocusNext),
+                    (Some(_), Some(XEmbedMessage::FocusPrev)) => Some(TrayEvent::FocusPrev),
+                    (Some(icon_window), Some(XEmbedMessage::RegisterAccelerator)) => {
+                        Some(self.register_accelerator(
+                            icon_window,
+                            detail,
+                            XEmbedAccelerator::new(data1, data2),
+                        ))
+                    }
+                    (Some(icon_window), Some(XEmbedMessage::UnregisterAccelerator)) => {
+                        self.unregister_accelerator(icon_window, detail);
+                        None
+                    }
+                    _ => None,
+                }
+            }
+            ClientMessage(event)
+                if event.type_ == self.atoms._NET_SYSTEM_TRAY_MESSAGE_DATA
+                    && self.embedded_icons.contains_key(&event.window) =>
+            {
                 log::info!("Tray message data received from `{}`", event.window);
                 if let hash_map::Entry::Occupied(mut entry) =
                     self.balloon_messages.entry(event.window)
@@ -228,7 +461,7 @@ impl<C: Connection> TrayManager<C> {
             {
                 log::info!("Tray manager selection did clear");
                 for (_, icon) in mem::take(&mut self.embedded_icons) {
-                    icon.release_embedding(self.connection.as_ref(), self.screen_num)?;
+                    icon.release_embedding(self.connection.as_ref())?;
                 }
                 self.status = TrayStatus::Unmanaged;
                 Some(TrayEvent::SelectionCleared)
@@ -245,12 +478,11 @@ impl<C: Connection> TrayManager<C> {
             }
             PropertyNotify(event)
                 if event.atom == u32::from(xproto::AtomEnum::WM_NAME)
+                    || event.atom == u32::from(xproto::AtomEnum::WM_CLASS)
                     || event.atom == self.atoms._NET_WM_NAME =>
             {
                 if let Some(icon) = self.embedded_icons.get_mut(&event.window) {
-                    icon.title =
-                        get_window_title(self.connection.as_ref(), event.window, &self.atoms)?
-                            .unwrap_or_default();
+                    icon.update_title(self.connection.as_ref(), &self.atoms)?;
                     Some(TrayEvent::TrayIconUpdated(icon.clone()))
                 } else {
                     None
@@ -258,13 +490,13 @@ impl<C: Connection> TrayManager<C> {
             }
             // Ignore from SUBSTRUCTURE_NOTIFY.
             ReparentNotify(event) if event.event == event.window => {
-                if event.parent == container_window {
-                    self.embedded_icons
-                        .get(&event.window)
-                        .map(|icon| TrayEvent::TrayIconAdded(icon.clone()))
-                } else {
-                    self.unregister_tray_icon(event.window)
-                        .map(|icon| TrayEvent::TrayIconRemoved(icon))
+                match self.embedded_icons.get(&event.window) {
+                    Some(icon) if icon.embedder == event.parent => {
+                        Some(TrayEvent::TrayIconAdded(icon.clone()))
+                    }
+                    _ => self
+                        .unregister_tray_icon(event.window)
+                        .map(|icon| TrayEvent::TrayIconRemoved(icon)),
                 }
             }
             DestroyNotify(event) => match self.status {
@@ -274,6 +506,16 @@ impl<C: Connection> TrayManager<C> {
                         event.window
                     );
                     self.broadcast_manager_message()?;
+                    self.status = TrayStatus::Managed;
+                    None
+                }
+                TrayStatus::Waiting(current_owner) if event.window == current_owner => {
+                    log::info!(
+                        "Current selection owner window `{}` did destroy",
+                        event.window
+                    );
+                    self.status = TrayStatus::Unmanaged;
+                    self.acquire_tray_selection(SelectionPolicy::Wait)?;
                     None
                 }
                 _ => self
@@ -291,7 +533,7 @@ impl<C: Connection> TrayManager<C> {
             log::info!("Releasing system tray selection");
 
             for (_, icon) in mem::take(&mut self.embedded_icons) {
-                icon.release_embedding(self.connection.as_ref(), self.screen_num)?;
+                icon.release_embedding(self.connection.as_ref())?;
             }
 
             self.connection
@@ -315,7 +557,12 @@ impl<C: Connection> TrayManager<C> {
     ) -> Result<(), ReplyOrIdError> {
         match self.embedded_icons.entry(icon_window) {
             hash_map::Entry::Vacant(entry) => {
-                let icon = TrayIcon::new(self.connection.as_ref(), icon_window, &self.atoms)?;
+                let mut icon = TrayIcon::new(
+                    self.connection.as_ref(),
+                    self.screen_num,
+                    icon_window,
+                    &self.atoms,
+                )?;
                 icon.begin_embedding(self.connection.as_ref(), container_window, &self.atoms)?;
                 entry.insert(icon);
             }
@@ -328,8 +575,59 @@ impl<C: Connection> TrayManager<C> {
     }
 
     fn unregister_tray_icon(&mut self, icon_window: xproto::Window) -> Option<TrayIcon> {
+        if self.focused_icon == Some(icon_window) {
+            self.focused_icon = None;
+        }
+        self.accelerators.remove(&icon_window);
         self.balloon_messages.remove(&icon_window);
-        self.embedded_icons.remove(&icon_window)
+        let icon = self.embedded_icons.remove(&icon_window)?;
+        if let Err(error) = self.connection.destroy_window(icon.embedder) {
+            log::warn!("Failed to destroy the embedder window: {}", error);
+        }
+        Some(icon)
+    }
+
+    fn register_accelerator(
+        &mut self,
+        icon_window: xproto::Window,
+        id: u32,
+        accelerator: XEmbedAccelerator,
+    ) -> TrayEvent {
+        log::info!(
+            "Register accelerator {} ({:?} + {}) of tray icon window `{}`",
+            id,
+            accelerator.modifiers,
+            accelerator.keysym,
+            icon_window
+        );
+        self.accelerators
+            .entry(icon_window)
+            .or_default()
+            .insert(id, accelerator);
+        TrayEvent::AcceleratorRegistered(icon_window, accelerator)
+    }
+
+    fn unregister_accelerator(&mut self, icon_window: xproto::Window, id: u32) {
+        if let Some(accelerators) = self.accelerators.get_mut(&icon_window) {
+            if accelerators.remove(&id).is_some() {
+                log::info!(
+                    "Unregister accelerator {} of tray icon window `{}`",
+                    id,
+                    icon_window
+                );
+            }
+        }
+    }
+
+    fn watch_selection_owner(&self, owner: xproto::Window) -> Result<(), ReplyError> {
+        let values = xproto::ChangeWindowAttributesAux::new()
+            .event_mask(Some(xproto::EventMask::STRUCTURE_NOTIFY.into()));
+
+        self.connection
+            .change_window_attributes(owner, &values)?
+            .check()?;
+
+        Ok(())
     }
 
     fn broadcast_manager_message(&self) -> Result<(), ReplyError> {
@@ -373,9 +671,14 @@ impl<C: Connection> Drop for TrayManager<C> {
 
 #[derive(Debug, Clone)]
 pub struct TrayIcon {
+    screen_num: usize,
     window: xproto::Window,
+    embedder: xproto::Window,
     damage: damage::Damage,
     title: String,
+    title_source: Option<TitleSource>,
+    class_name: Option<String>,
+    process_name: Option<String>,
     xembed_info: Option<XEmbedInfo>,
     version: u16,
 }
@@ -383,6 +686,7 @@ pub struct TrayIcon {
 impl TrayIcon {
     fn new<C: Connection>(
         connection: &C,
+        screen_num: usize,
         window: xproto::Window,
         atoms: &Atoms,
     ) -> Result<Self, ReplyOrIdError> {
@@ -390,37 +694,105 @@ impl TrayIcon {
         connection
             .damage_create(damage, window, damage::ReportLevel::NON_EMPTY)?
             .check()?;
-        let title = get_window_title(connection, window, atoms)?.unwrap_or_default();
         let xembed_info = get_xembed_info(connection, window, atoms)?;
-        Ok(Self {
+        let mut icon = Self {
+            screen_num,
             window,
+            embedder: x11rb::NONE,
             damage,
-            title,
+            title: String::new(),
+            title_source: None,
+            class_name: None,
+            process_name: get_process_name(connection, window, atoms)?,
             xembed_info,
             version: 0,
-        })
+        };
+        icon.update_title(connection, atoms)?;
+        Ok(icon)
+    }
+
+    // Makes an icon that is not backed by a window on the X server.
+    #[cfg(test)]
+    pub fn detached(window: xproto::Window, title: &str, class_name: Option<&str>) -> Self {
+        Self {
+            screen_num: 0,
+            window,
+            embedder: x11rb::NONE,
+            damage: x11rb::NONE,
+            title: title.to_owned(),
+            title_source: None,
+            class_name: class_name.map(str::to_owned),
+            process_name: None,
+            xembed_info: None,
+            version: 0,
+        }
     }
 
     pub fn window(&self) -> xproto::Window {
         self.window
     }
 
+    // The window that the icon is reparented to, which is a child of the
+    // container.
+    pub fn embedder(&self) -> xproto::Window {
+        self.embedder
+    }
+
     pub fn title(&self) -> &str {
         &self.title
     }
 
+    pub fn title_source(&self) -> Option<TitleSource> {
+        self.title_source
+    }
+
+    pub fn class_name(&self) -> Option<&str> {
+        self.class_name.as_deref()
+    }
+
+    pub fn process_name(&self) -> Option<&str> {
+        self.process_name.as_deref()
+    }
+
+    // Clicks at the position relative to the icon, or at the center of the
+    // icon if it is not given.
     pub fn click<C: Connection>(
         &self,
         connection: &C,
-        screen_num: usize,
         button: xproto::ButtonIndex,
-        button_mask: xproto::ButtonMask,
+        state: u16,
+        position: Option<(i16, i16)>,
+        time: xproto::Timestamp,
+    ) -> Result<(), ReplyError> {
+        self.send_button_events(
+            connection,
+            &[
+                (xproto::BUTTON_PRESS_EVENT, time),
+                (xproto::BUTTON_RELEASE_EVENT, time),
+            ],
+            button,
+            state,
+            position,
+        )
+    }
+
+    // Sends the button events of the type and time in order, e.g. only the
+    // press to start holding the button down and the release later. The time
+    // lets the application recognize double-clicks.
+    pub fn send_button_events<C: Connection>(
+        &self,
+        connection: &C,
+        events: &[(u8, xproto::Timestamp)],
+        button: xproto::ButtonIndex,
+        state: u16,
+        position: Option<(i16, i16)>,
     ) -> Result<(), ReplyError> {
-        let screen = &connection.setup().roots[screen_num];
+        let screen = &connection.setup().roots[self.screen_num];
         let saved_pointer = connection.query_pointer(screen.root)?.reply()?;
 
+        let (x, y) = self.click_position(connection, position)?;
         let absolute_position = connection
-            .translate_coordinates(self.window, screen.root, 0, 0)?
+            .translate_coordinates(self.window, screen.root, x, y)?
             .reply()?;
 
         connection
@@ -436,31 +808,26 @@ impl TrayIcon {
             )?
             .check()?;
 
-        send_button_event(
-            connection,
-            screen_num,
-            self.window,
-            button,
-            button_mask,
-            xproto::BUTTON_PRESS_EVENT,
-            0,
-            0,
-            absolute_position.dst_x,
-            absolute_position.dst_y,
-        )?;
-
-        send_button_event(
-            connection,
-            screen_num,
-            self.window,
-            button,
-            button_mask,
-            xproto::BUTTON_RELEASE_EVENT,
-            0,
-            0,
-            absolute_position.dst_x,
-            absolute_position.dst_y,
-        )?;
+        for (event_type, time) in events {
+            let event = xproto::ButtonPressEvent {
+                response_type: *event_type,
+                detail: button.into(),
+                sequence: 0,
+                time: *time,
+                root: screen.root,
+                event: self.window,
+                child: x11rb::NONE,
+                event_x: x,
+                event_y: y,
+                root_x: absolute_position.dst_x,
+                root_y: absolute_position.dst_y,
+                state,
+                same_screen: true,
+            };
+            connection
+                .send_event(true, self.window, xproto::EventMask::NO_EVENT, event)?
+                .check()?;
+        }
 
         connection
             .warp_pointer(
@@ -480,6 +847,186 @@ impl TrayIcon {
         Ok(())
     }
 
+    // Clicks the icon by the fake input of XTEST, which can not be told from
+    // the real input. The modifiers in the state are held down by faking their
+    // keys around the button. It falls back to `click()` if the icon is not
+    // viewable.
+    pub fn fake_click<C: Connection>(
+        &self,
+        connection: &C,
+        button: xproto::ButtonIndex,
+        state: u16,
+        position: Option<(i16, i16)>,
+        time: xproto::Timestamp,
+    ) -> Result<(), ReplyError> {
+        self.fake_button_events(
+            connection,
+            &[
+                (xproto::BUTTON_PRESS_EVENT, time),
+                (xproto::BUTTON_RELEASE_EVENT, time),
+            ],
+            button,
+            state,
+            position,
+        )
+    }
+
+    // The XTEST counterpart of `send_button_events()`. While the button is held
+    // down, the icon keeps the pointer grabbed even though the pointer is
+    // restored. The time is only used by the fallback, since the server stamps
+    // the fake input itself.
+    pub fn fake_button_events<C: Connection>(
+        &self,
+        connection: &C,
+        events: &[(u8, xproto::Timestamp)],
+        button: xproto::ButtonIndex,
+        state: u16,
+        position: Option<(i16, i16)>,
+    ) -> Result<(), ReplyError> {
+        let attributes = connection.get_window_attributes(self.window)?.reply()?;
+        if attributes.map_state != xproto::MapState::VIEWABLE {
+            return self.send_button_events(connection, events, button, state, position);
+        }
+
+        let screen = &connection.setup().roots[self.screen_num];
+        let saved_pointer = connection.query_pointer(screen.root)?.reply()?;
+
+        let (x, y) = self.click_position(connection, position)?;
+        let absolute_position = connection
+            .translate_coordinates(self.window, screen.root, x, y)?
+            .reply()?;
+
+        connection
+            .warp_pointer(
+                x11rb::NONE,             // src_window
+                screen.root,             // dst_window
+                0,                       // src_x
+                0,                       // src_y
+                0,                       // src_width
+                0,                       // src_heihgt
+                absolute_position.dst_x, // dst_x
+                absolute_position.dst_y, // dst_y
+            )?
+            .check()?;
+
+        let modifier_keycodes = get_modifier_keycodes(connection, state)?;
+
+        for (event_type, _) in events {
+            if *event_type == xproto::BUTTON_PRESS_EVENT {
+                for keycode in &modifier_keycodes {
+                    fake_input(connection, xproto::KEY_PRESS_EVENT, *keycode, screen.root)?;
+                }
+            }
+            fake_input(connection, *event_type, button.into(), screen.root)?;
+            if *event_type == xproto::BUTTON_RELEASE_EVENT {
+                for keycode in modifier_keycodes.iter().rev() {
+                    fake_input(connection, xproto::KEY_RELEASE_EVENT, *keycode, screen.root)?;
+                }
+            }
+        }
+
+        connection
+            .warp_pointer(
+                x11rb::NONE,          // src_window
+                screen.root,          // dst_window
+                0,                    // src_x
+                0,                    // src_y
+                0,                    // src_width
+                0,                    // src_heihgt
+                saved_pointer.root_x, // dst_x
+                saved_pointer.root_y, // dst_y
+            )?
+            .check()?;
+
+        connection.flush()?;
+
+        Ok(())
+    }
+
+    fn click_position<C: Connection>(
+        &self,
+        connection: &C,
+        position: Option<(i16, i16)>,
+    ) -> Result<(i16, i16), ReplyError> {
+        match position {
+            Some(position) => Ok(position),
+            None => {
+                let geometry = connection.get_geometry(self.window)?.reply()?;
+                Ok(((geometry.width / 2) as i16, (geometry.height / 2) as i16))
+            }
+        }
+    }
+
+    pub fn enter<C: Connection>(&self, connection: &C, x: i16, y: i16) -> Result<(), ReplyError> {
+        self.send_pointer_event(connection, xproto::ENTER_NOTIFY_EVENT, x, y)
+    }
+
+    pub fn motion<C: Connection>(&self, connection: &C, x: i16, y: i16) -> Result<(), ReplyError> {
+        self.send_pointer_event(connection, xproto::MOTION_NOTIFY_EVENT, x, y)
+    }
+
+    pub fn leave<C: Connection>(&self, connection: &C, x: i16, y: i16) -> Result<(), ReplyError> {
+        self.send_pointer_event(connection, xproto::LEAVE_NOTIFY_EVENT, x, y)
+    }
+
+    fn send_pointer_event<C: Connection>(
+        &self,
+        connection: &C,
+        event_type: u8,
+        x: i16,
+        y: i16,
+    ) -> Result<(), ReplyError> {
+        let screen = &connection.setup().roots[self.screen_num];
+        let absolute_position = connection
+            .translate_coordinates(self.window, screen.root, x, y)?
+            .reply()?;
+
+        if event_type == xproto::MOTION_NOTIFY_EVENT {
+            let event = xproto::MotionNotifyEvent {
+                response_type: event_type,
+                detail: xproto::Motion::NORMAL,
+                sequence: 0,
+                time: x11rb::CURRENT_TIME,
+                root: screen.root,
+                event: self.window,
+                child: x11rb::NONE,
+                root_x: absolute_position.dst_x,
+                root_y: absolute_position.dst_y,
+                event_x: x,
+                event_y: y,
+                state: 0,
+                same_screen: true,
+            };
+            connection
+                .send_event(true, self.window, xproto::EventMask::NO_EVENT, event)?
+                .check()?;
+        } else {
+            let event = xproto::EnterNotifyEvent {
+                response_type: event_type,
+                detail: xproto::NotifyDetail::NONLINEAR,
+                sequence: 0,
+                time: x11rb::CURRENT_TIME,
+                root: screen.root,
+                event: self.window,
+                child: x11rb::NONE,
+                root_x: absolute_position.dst_x,
+                root_y: absolute_position.dst_y,
+                event_x: x,
+                event_y: y,
+                state: 0,
+                mode: xproto::NotifyMode::NORMAL,
+                same_screen_focus: SAME_SCREEN_FLAG,
+            };
+            connection
+                .send_event(true, self.window, xproto::EventMask::NO_EVENT, event)?
+                .check()?;
+        }
+
+        connection.flush()?;
+
+        Ok(())
+    }
+
     pub fn should_map(&self) -> bool {
         match self.xembed_info {
             Some(xembed_info) => xembed_info.is_mapped(),
@@ -491,14 +1038,58 @@ impl TrayIcon {
         self.version
     }
 
+    fn update_title<C: Connection>(
+        &mut self,
+        connection: &C,
+        atoms: &Atoms,
+    ) -> Result<(), ReplyError> {
+        self.class_name = get_window_class(connection, self.window)?;
+        let (title, title_source) = resolve_window_title(
+            connection,
+            self.window,
+            atoms,
+            self.class_name.as_deref(),
+            self.process_name.as_deref(),
+        )?;
+        self.title = title;
+        self.title_source = title_source;
+        Ok(())
+    }
+
+    // The icon is reparented to its own embedder window, so that XEmbed
+    // messages from the client tell which icon sent them.
     fn begin_embedding<C: Connection>(
-        &self,
+        &mut self,
         connection: &C,
         container_window: xproto::Window,
         atoms: &Atoms,
     ) -> Result<(), ReplyOrIdError> {
         log::info!("Begin embedding for tray icon window `{}`", self.window);
 
+        let embedder = connection.generate_id()?;
+        connection
+            .create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                embedder,
+                container_window,
+                0,
+                0,
+                1,
+                1,
+                0,
+                xproto::WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &xproto::CreateWindowAux::new(),
+            )?
+            .check()?;
+
+        // Like the icon, the embedder is drawn by the container.
+        connection
+            .composite_redirect_window(embedder, composite::Redirect::MANUAL)?
+            .check()?;
+
+        self.embedder = embedder;
+
         {
             let values = xproto::ChangeWindowAttributesAux::new().event_mask(Some(
                 (xproto::EventMask::PROPERTY_CHANGE | xproto::EventMask::STRUCTURE_NOTIFY).into(),
@@ -517,31 +1108,18 @@ impl TrayIcon {
             .check()?;
 
         connection
-            .reparent_window(self.window, container_window, 0, 0)?
+            .reparent_window(self.window, self.embedder, 0, 0)?
             .check()?;
 
         if let Some(xembed_info) = self.xembed_info {
-            let event = xproto::ClientMessageEvent::new(
-                32,
-                self.window,
-                atoms._XEMBED,
-                [
-                    x11rb::CURRENT_TIME,
-                    XEmbedMessage::EmbeddedNotify.into(),
-                    0, // detail
-                    container_window,
-                    xembed_info.version,
-                ],
-            );
-
-            connection
-                .send_event(
-                    false,
-                    self.window,
-                    xproto::EventMask::STRUCTURE_NOTIFY,
-                    event,
-                )?
-                .check()?;
+            self.send_xembed_message(
+                connection,
+                atoms,
+                XEmbedMessage::EmbeddedNotify,
+                0,
+                self.embedder,
+                xembed_info.version,
+            )?;
         }
 
         connection.flush()?;
@@ -549,14 +1127,42 @@ impl TrayIcon {
         Ok(())
     }
 
-    fn release_embedding<C: Connection>(
+    fn send_xembed_message<C: Connection>(
         &self,
         connection: &C,
-        screen_num: usize,
+        atoms: &Atoms,
+        message: XEmbedMessage,
+        detail: u32,
+        data1: u32,
+        data2: u32,
     ) -> Result<(), ReplyError> {
+        if self.xembed_info.is_none() {
+            return Ok(());
+        }
+
+        let event = xproto::ClientMessageEvent::new(
+            32,
+            self.window,
+            atoms._XEMBED,
+            [x11rb::CURRENT_TIME, message.into(), detail, data1, data2],
+        );
+
+        connection
+            .send_event(
+                false,
+                self.window,
+                xproto::EventMask::STRUCTURE_NOTIFY,
+                event,
+            )?
+            .check()?;
+
+        Ok(())
+    }
+
+    fn release_embedding<C: Connection>(&self, connection: &C) -> Result<(), ReplyError> {
         log::info!("Release embedding for tray icon {}", self.window);
 
-        let screen = &connection.setup().roots[screen_num];
+        let screen = &connection.setup().roots[self.screen_num];
 
         {
             let values = xproto::ChangeWindowAttributesAux::new()
@@ -574,62 +1180,96 @@ impl TrayIcon {
             .reparent_window(self.window, screen.root, 0, 0)?
             .check()?;
 
+        connection.destroy_window(self.embedder)?.check()?;
+
         connection.flush()?;
 
         Ok(())
     }
 }
 
-fn send_button_event<Connection: self::Connection>(
-    connection: &Connection,
-    screen_num: usize,
-    window: xproto::Window,
-    button: xproto::ButtonIndex,
-    button_mask: xproto::ButtonMask,
+fn fake_input<C: Connection>(
+    connection: &C,
     event_type: u8,
-    x: i16,
-    y: i16,
-    root_x: i16,
-    root_y: i16,
+    detail: u8,
+    root: xproto::Window,
 ) -> Result<(), ReplyError> {
-    let screen = &connection.setup().roots[screen_num];
-
-    let event = xproto::ButtonPressEvent {
-        response_type: event_type,
-        detail: button.into(),
-        sequence: 0,
-        time: x11rb::CURRENT_TIME,
-        root: screen.root,
-        event: window,
-        child: x11rb::NONE,
-        event_x: x,
-        event_y: y,
-        root_x,
-        root_y,
-        state: button_mask.into(),
-        same_screen: true,
-    };
-
     connection
-        .send_event(true, window, xproto::EventMask::NO_EVENT, event)?
+        .xtest_fake_input(event_type, detail, x11rb::CURRENT_TIME, root, 0, 0, 0)?
         .check()?;
-
     Ok(())
 }
 
+// Returns the keycode of each modifier in the state. Lock modifiers are
+// excluded since pressing them toggles the lock.
+fn get_modifier_keycodes<C: Connection>(
+    connection: &C,
+    state: u16,
+) -> Result<Vec<xproto::Keycode>, ReplyError> {
+    let modifiers = [
+        xproto::ModMask::SHIFT,
+        xproto::ModMask::CONTROL,
+        xproto::ModMask::M1,
+        xproto::ModMask::M4,
+    ];
+    if modifiers
+        .iter()
+        .all(|modifier| state & u16::from(*modifier) == 0)
+    {
+        return Ok(Vec::new());
+    }
+
+    let mapping = connection.get_modifier_mapping()?.reply()?;
+    let keycodes_per_modifier = mapping.keycodes_per_modifier() as usize;
+    let mut keycodes = Vec::new();
+    for modifier in modifiers {
+        let mask = u16::from(modifier);
+        if state & mask == 0 {
+            continue;
+        }
+        // The mapping lists the keycodes of Shift, Lock, Control and Mod1-5.
+        let index = mask.trailing_zeros() as usize;
+        let keycode = mapping.keycodes
+            [index * keycodes_per_modifier..(index + 1) * keycodes_per_modifier]
+            .iter()
+            .copied()
+            .find(|keycode| *keycode != 0);
+        match keycode {
+            Some(keycode) => keycodes.push(keycode),
+            None => log::warn!("No key is mapped to the modifier {:#x}", mask),
+        }
+    }
+    Ok(keycodes)
+}
+
 #[derive(Debug)]
 pub enum TrayEvent {
     TrayIconAdded(TrayIcon),
     TrayIconUpdated(TrayIcon),
     TrayIconRemoved(TrayIcon),
     MessageReceived(xproto::Window, BalloonMessage),
+    MessageCanceled(xproto::Window, u32),
+    FocusRequested(xproto::Window),
+    FocusNext,
+    FocusPrev,
+    AcceleratorRegistered(xproto::Window, XEmbedAccelerator),
     SelectionCleared,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TitleSource {
+    WindowName,
+    WindowClass,
+    Process,
+    ClientLeader,
+    TransientFor,
+}
+
 #[derive(Debug)]
 enum TrayStatus {
     Unmanaged,
     Pending(xproto::Window),
+    Waiting(xproto::Window),
     Managed,
 }
 
@@ -655,6 +1295,10 @@ impl BalloonMessage {
         }
     }
 
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
     pub fn timeout(&self) -> Duration {
         self.timeout
     }
@@ -683,7 +1327,6 @@ impl BalloonMessage {
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct SystemTrayOrientation(u32);
 
-#[allow(unused)]
 impl SystemTrayOrientation {
     pub const HORZONTAL: Self = Self(0);
     pub const VERTICAL: Self = Self(1);
@@ -738,6 +1381,147 @@ impl SystemTrayColors {
     }
 }
 
+fn resolve_window_title<C: Connection>(
+    connection: &C,
+    window: xproto::Window,
+    atoms: &Atoms,
+    class_name: Option<&str>,
+    process_name: Option<&str>,
+) -> Result<(String, Option<TitleSource>), ReplyError> {
+    if let Some(title) = get_window_title(connection, window, atoms)? {
+        return Ok((title, Some(TitleSource::WindowName)));
+    }
+
+    if let Some(class_name) = class_name {
+        return Ok((class_name.to_owned(), Some(TitleSource::WindowClass)));
+    }
+
+    if let Some(process_name) = process_name {
+        return Ok((process_name.to_owned(), Some(TitleSource::Process)));
+    }
+
+    // Some applications set the name only to the main window. So we try the
+    // name of related windows.
+    for (property, source) in [
+        (atoms.WM_CLIENT_LEADER, TitleSource::ClientLeader),
+        (
+            xproto::AtomEnum::WM_TRANSIENT_FOR.into(),
+            TitleSource::TransientFor,
+        ),
+    ] {
+        let related_window = connection
+            .get_property(false, window, property, xproto::AtomEnum::WINDOW, 0, 1)?
+            .reply()?
+            .value32()
+            .and_then(|mut values| values.next())
+            .filter(|related_window| *related_window != x11rb::NONE && *related_window != window);
+        if let Some(related_window) = related_window {
+            let title = match get_window_title(connection, related_window, atoms)? {
+                Some(title) => Some(title),
+                None => get_window_class(connection, related_window)?,
+            };
+            if let Some(title) = title {
+                return Ok((title, Some(source)));
+            }
+        }
+    }
+
+    Ok((String::new(), None))
+}
+
+fn get_window_class<C: Connection>(
+    connection: &C,
+    window: xproto::Window,
+) -> Result<Option<String>, ReplyError> {
+    let reply = connection
+        .get_property(
+            false,
+            window,
+            xproto::AtomEnum::WM_CLASS,
+            xproto::AtomEnum::STRING,
+            0,
+            256 / 4,
+        )?
+        .reply()?;
+    // WM_CLASS consists of the instance name and the class name separated by NUL.
+    let class_name = reply.value8().and_then(|bytes| {
+        let bytes = bytes.collect::<Vec<_>>();
+        let mut names = bytes
+            .split(|byte| *byte == 0)
+            .filter_map(|name| str::from_utf8(name).ok())
+            .filter(|name| !name.is_empty());
+        let instance_name = names.next();
+        names.next().or(instance_name).map(str::to_owned)
+    });
+    Ok(class_name)
+}
+
+fn get_process_name<C: Connection>(
+    connection: &C,
+    window: xproto::Window,
+    atoms: &Atoms,
+) -> Result<Option<String>, ReplyError> {
+    let client_machine = connection
+        .get_property(
+            false,
+            window,
+            xproto::AtomEnum::WM_CLIENT_MACHINE,
+            xproto::AtomEnum::STRING,
+            0,
+            256 / 4,
+        )?
+        .reply()?
+        .value8()
+        .and_then(|bytes| String::from_utf8(bytes.collect()).ok());
+    if let Some(client_machine) = client_machine {
+        let hostname = nix::unistd::gethostname(&mut [0u8; 256])
+            .ok()
+            .and_then(|hostname| hostname.to_str().ok().map(str::to_owned));
+        if hostname.map_or(false, |hostname| hostname != client_machine) {
+            // The process is running on the remote host.
+            return Ok(None);
+        }
+    }
+
+    let pid = connection
+        .get_property(
+            false,
+            window,
+            atoms._NET_WM_PID,
+            xproto::AtomEnum::CARDINAL,
+            0,
+            1,
+        )?
+        .reply()?
+        .value32()
+        .and_then(|mut values| values.next());
+    let pid = match pid {
+        Some(pid) => pid,
+        None => return Ok(None),
+    };
+
+    let proc_dir = Path::new("/proc").join(pid.to_string());
+
+    if let Some(comm) = fs::read_to_string(proc_dir.join("comm"))
+        .ok()
+        .map(|comm| comm.trim_end().to_owned())
+        .filter(|comm| !comm.is_empty())
+    {
+        return Ok(Some(comm));
+    }
+
+    let program_name = fs::read(proc_dir.join("cmdline")).ok().and_then(|cmdline| {
+        let program = cmdline.split(|byte| *byte == 0).next()?;
+        let program = str::from_utf8(program).ok()?;
+        Path::new(program)
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .map(str::to_owned)
+    });
+
+    Ok(program_name)
+}
+
 fn get_window_title<C: Connection>(
     connection: &C,
     window: xproto::Window,
@@ -821,6 +1605,8 @@ x11rb::atom_manager! {
         _NET_SYSTEM_TRAY_ORIENTATION,
         _NET_SYSTEM_TRAY_VISUAL,
         _NET_WM_NAME,
+        _NET_WM_PID,
+        WM_CLIENT_LEADER,
         _XEMBED,
         _XEMBED_INFO,
     }
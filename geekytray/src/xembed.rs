@@ -1,6 +1,14 @@
+use geekytray_shell::event::{Keysym, Modifiers};
+
 const XEMBED_MAPPED: u32 = 1 << 0;
 
-#[allow(dead_code)]
+const XEMBED_MODIFIER_SHIFT: u32 = 1 << 0;
+const XEMBED_MODIFIER_CONTROL: u32 = 1 << 1;
+const XEMBED_MODIFIER_ALT: u32 = 1 << 2;
+const XEMBED_MODIFIER_SUPER: u32 = 1 << 3;
+
+pub const XEMBED_ACCELERATOR_OVERLOADED: u32 = 1 << 0;
+
 #[derive(Clone, Copy, Debug)]
 #[repr(u32)]
 pub enum XEmbedMessage {
@@ -21,12 +29,52 @@ pub enum XEmbedMessage {
     ActivateAccelerator = 14,
 }
 
+impl XEmbedMessage {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        use XEmbedMessage::*;
+
+        let message = match value {
+            0 => EmbeddedNotify,
+            1 => WindowActivate,
+            2 => WindowDeactivate,
+            3 => RequestFocus,
+            4 => FocusIn,
+            5 => FocusOut,
+            6 => FocusNext,
+            7 => FocusPrev,
+            8 => GrabKey,
+            9 => UngrabKey,
+            10 => ModalityOn,
+            11 => ModalityOff,
+            12 => RegisterAccelerator,
+            13 => UnregisterAccelerator,
+            14 => ActivateAccelerator,
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
 impl From<XEmbedMessage> for u32 {
     fn from(value: XEmbedMessage) -> Self {
         value as u32
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum XEmbedFocus {
+    Current = 0,
+    First = 1,
+    Last = 2,
+}
+
+impl From<XEmbedFocus> for u32 {
+    fn from(value: XEmbedFocus) -> Self {
+        value as u32
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct XEmbedInfo {
@@ -39,3 +87,35 @@ impl XEmbedInfo {
         self.flags & XEMBED_MAPPED != 0
     }
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct XEmbedAccelerator {
+    pub keysym: Keysym,
+    pub modifiers: Modifiers,
+}
+
+impl XEmbedAccelerator {
+    pub fn new(keysym: u32, modifier_mask: u32) -> Self {
+        let mut modifiers = Modifiers::NONE;
+        if modifier_mask & XEMBED_MODIFIER_SHIFT != 0 {
+            modifiers |= Modifiers::SHIFT;
+        }
+        if modifier_mask & XEMBED_MODIFIER_CONTROL != 0 {
+            modifiers |= Modifiers::CONTROL;
+        }
+        if modifier_mask & XEMBED_MODIFIER_ALT != 0 {
+            modifiers |= Modifiers::ALT;
+        }
+        if modifier_mask & XEMBED_MODIFIER_SUPER != 0 {
+            modifiers |= Modifiers::SUPER;
+        }
+        Self {
+            keysym: keysym.into(),
+            modifiers,
+        }
+    }
+
+    pub fn matches(&self, keysym: Keysym, modifiers: Modifiers) -> bool {
+        self.keysym == keysym && self.modifiers == modifiers.without_locks()
+    }
+}
@@ -0,0 +1,395 @@
+use nix::poll;
+use nix::sys::socket;
+use nix::unistd;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use super::error::Error;
+use super::message::{Message, MessageType};
+use super::value::Value;
+use super::{BUS_INTERFACE, BUS_NAME, BUS_PATH};
+
+const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub struct Connection {
+    stream: UnixStream,
+    unique_name: String,
+    serial_counter: Cell<u32>,
+    read_buffer: RefCell<Vec<u8>>,
+    queued_messages: RefCell<VecDeque<Message>>,
+}
+
+impl Connection {
+    pub fn session() -> Result<Self, Error> {
+        let address = env::var("DBUS_SESSION_BUS_ADDRESS")
+            .map_err(|_| Error::InvalidAddress("DBUS_SESSION_BUS_ADDRESS is not set".to_owned()))?;
+        Self::open(&address)
+    }
+
+    pub fn open(address: &str) -> Result<Self, Error> {
+        let mut last_error = Error::InvalidAddress(address.to_owned());
+        for entry in address.split(';').filter(|entry| !entry.is_empty()) {
+            match connect_address(entry) {
+                Ok(stream) => return Self::from_stream(stream),
+                Err(error) => last_error = error,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn from_stream(mut stream: UnixStream) -> Result<Self, Error> {
+        authenticate(&mut stream)?;
+
+        let mut connection = Self {
+            stream,
+            unique_name: String::new(),
+            serial_counter: Cell::new(0),
+            read_buffer: RefCell::new(Vec::new()),
+            queued_messages: RefCell::new(VecDeque::new()),
+        };
+
+        let reply = connection.call(Message::method_call(
+            BUS_NAME,
+            BUS_PATH,
+            BUS_INTERFACE,
+            "Hello",
+        ))?;
+        connection.unique_name = reply
+            .body()
+            .first()
+            .and_then(Value::as_str)
+            .ok_or(Error::InvalidMessage("Hello reply does not contain a name"))?
+            .to_owned();
+
+        Ok(connection)
+    }
+
+    pub fn unique_name(&self) -> &str {
+        &self.unique_name
+    }
+
+    pub fn send(&self, mut message: Message) -> Result<u32, Error> {
+        let serial = self.next_serial();
+        message.set_serial(serial);
+        (&self.stream).write_all(&message.to_bytes())?;
+        Ok(serial)
+    }
+
+    pub fn call(&self, message: Message) -> Result<Message, Error> {
+        self.call_with_timeout(message, DEFAULT_CALL_TIMEOUT)
+    }
+
+    pub fn call_with_timeout(&self, message: Message, timeout: Duration) -> Result<Message, Error> {
+        let serial = self.send(message)?;
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            while let Some(message) = self.read_message()? {
+                if message.reply_serial() == Some(serial)
+                    && matches!(
+                        message.message_type(),
+                        MessageType::MethodReturn | MessageType::Error
+                    )
+                {
+                    return message.into_result();
+                }
+                self.queued_messages.borrow_mut().push_back(message);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Timeout);
+            }
+            if self.wait_readable(deadline - now)? {
+                self.fill_buffer()?;
+            }
+        }
+    }
+
+    pub fn poll_message(&self) -> Result<Option<Message>, Error> {
+        if let Some(message) = self.queued_messages.borrow_mut().pop_front() {
+            return Ok(Some(message));
+        }
+        if let Some(message) = self.read_message()? {
+            return Ok(Some(message));
+        }
+        if self.wait_readable(Duration::ZERO)? {
+            self.fill_buffer()?;
+            return self.read_message();
+        }
+        Ok(None)
+    }
+
+    pub fn wait_message(&self, timeout: Duration) -> Result<Option<Message>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(message) = self.poll_message()? {
+                return Ok(Some(message));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.wait_readable(deadline - now)?;
+        }
+    }
+
+    pub fn add_match(&self, rule: &str) -> Result<(), Error> {
+        self.call(
+            Message::method_call(BUS_NAME, BUS_PATH, BUS_INTERFACE, "AddMatch")
+                .with_body(vec![rule.into()]),
+        )?;
+        Ok(())
+    }
+
+    pub fn request_name(&self, name: &str, flags: u32) -> Result<u32, Error> {
+        let reply = self.call(
+            Message::method_call(BUS_NAME, BUS_PATH, BUS_INTERFACE, "RequestName")
+                .with_body(vec![name.into(), flags.into()]),
+        )?;
+        reply
+            .body()
+            .first()
+            .and_then(Value::as_u32)
+            .ok_or(Error::InvalidMessage(
+                "RequestName reply does not contain a code",
+            ))
+    }
+
+    pub fn name_has_owner(&self, name: &str) -> Result<bool, Error> {
+        let reply = self.call(
+            Message::method_call(BUS_NAME, BUS_PATH, BUS_INTERFACE, "NameHasOwner")
+                .with_body(vec![name.into()]),
+        )?;
+        reply
+            .body()
+            .first()
+            .and_then(Value::as_bool)
+            .ok_or(Error::InvalidMessage(
+                "NameHasOwner reply does not contain a boolean",
+            ))
+    }
+
+    fn next_serial(&self) -> u32 {
+        let serial = self.serial_counter.get().wrapping_add(1).max(1);
+        self.serial_counter.set(serial);
+        serial
+    }
+
+    fn read_message(&self) -> Result<Option<Message>, Error> {
+        let mut read_buffer = self.read_buffer.borrow_mut();
+        match Message::required_len(&read_buffer)? {
+            Some(len) if read_buffer.len() >= len => {
+                let message = Message::from_bytes(&read_buffer[..len])?;
+                read_buffer.drain(..len);
+                Ok(Some(message))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn fill_buffer(&self) -> Result<(), Error> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let len = (&self.stream).read(&mut chunk)?;
+        if len == 0 {
+            return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        self.read_buffer
+            .borrow_mut()
+            .extend_from_slice(&chunk[..len]);
+        Ok(())
+    }
+
+    fn wait_readable(&self, timeout: Duration) -> Result<bool, Error> {
+        let mut fds = [poll::PollFd::new(
+            self.stream.as_raw_fd(),
+            poll::PollFlags::POLLIN,
+        )];
+        match poll::poll(&mut fds, timeout.as_millis().min(i32::MAX as u128) as i32) {
+            Ok(available_fds) => Ok(available_fds > 0),
+            Err(nix::Error::EINTR) => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+fn connect_address(address: &str) -> Result<UnixStream, Error> {
+    let (transport, params) = address
+        .split_once(':')
+        .ok_or_else(|| Error::InvalidAddress(address.to_owned()))?;
+    if transport != "unix" {
+        return Err(Error::InvalidAddress(address.to_owned()));
+    }
+
+    for param in params.split(',') {
+        let (key, value) = match param.split_once('=') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let value = unescape_address_value(value)
+            .ok_or_else(|| Error::InvalidAddress(address.to_owned()))?;
+        match key {
+            "path" => return Ok(UnixStream::connect(value)?),
+            "abstract" => {
+                let fd = socket::socket(
+                    socket::AddressFamily::Unix,
+                    socket::SockType::Stream,
+                    socket::SockFlag::SOCK_CLOEXEC,
+                    None,
+                )?;
+                let unix_addr = socket::UnixAddr::new_abstract(value.as_bytes())?;
+                if let Err(error) = socket::connect(fd, &socket::SockAddr::Unix(unix_addr)) {
+                    unistd::close(fd).ok();
+                    return Err(error.into());
+                }
+                return Ok(unsafe { UnixStream::from_raw_fd(fd) });
+            }
+            _ => {}
+        }
+    }
+
+    Err(Error::InvalidAddress(address.to_owned()))
+}
+
+fn unescape_address_value(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let high = (iter.next()? as char).to_digit(16)?;
+            let low = (iter.next()? as char).to_digit(16)?;
+            bytes.push((high * 16 + low) as u8);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn authenticate(stream: &mut UnixStream) -> Result<(), Error> {
+    let uid = unistd::getuid().to_string();
+    let hex_uid: String = uid.bytes().map(|byte| format!("{:02x}", byte)).collect();
+
+    stream.write_all(b"\0")?;
+    stream.write_all(format!("AUTH EXTERNAL {}\r\n", hex_uid).as_bytes())?;
+
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while !line.ends_with(b"\r\n") {
+        if stream.read(&mut byte)? == 0 {
+            return Err(Error::AuthenticationFailed);
+        }
+        line.push(byte[0]);
+    }
+    if !line.starts_with(b"OK ") {
+        return Err(Error::AuthenticationFailed);
+    }
+
+    stream.write_all(b"BEGIN\r\n")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Child, Command, Stdio};
+
+    use super::*;
+
+    struct TestBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl TestBus {
+        fn spawn() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon is required to run D-Bus tests");
+            let mut stdout = daemon.stdout.take().unwrap();
+            let mut address = String::new();
+            let mut byte = [0u8; 1];
+            while stdout.read(&mut byte).unwrap() > 0 && byte[0] != b'\n' {
+                address.push(byte[0] as char);
+            }
+            assert!(!address.is_empty(), "dbus-daemon did not print its address");
+            Self { daemon, address }
+        }
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            self.daemon.kill().ok();
+            self.daemon.wait().ok();
+        }
+    }
+
+    #[test]
+    fn test_call_between_connections() {
+        let bus = TestBus::spawn();
+
+        let service = Connection::open(&bus.address).unwrap();
+        let client = Connection::open(&bus.address).unwrap();
+        assert!(service.unique_name().starts_with(':'));
+        assert_ne!(service.unique_name(), client.unique_name());
+        assert_eq!(service.request_name("org.geekytray.Test", 0).unwrap(), 1);
+        assert!(client.name_has_owner("org.geekytray.Test").unwrap());
+
+        let serial = client
+            .send(
+                Message::method_call("org.geekytray.Test", "/", "org.geekytray.Test", "Echo")
+                    .with_body(vec![Value::dict(
+                        "s",
+                        "v",
+                        vec![("key".into(), Value::variant(42))],
+                    )]),
+            )
+            .unwrap();
+
+        let call = loop {
+            let message = service
+                .wait_message(Duration::from_secs(5))
+                .unwrap()
+                .unwrap();
+            if message.is_method_call("org.geekytray.Test", "Echo") {
+                break message;
+            }
+        };
+        assert_eq!(call.sender(), Some(client.unique_name()));
+        assert_eq!(
+            call.body()[0].dict_get("key").and_then(Value::as_i32),
+            Some(42)
+        );
+        service
+            .send(Message::method_return(&call).with_body(call.body().to_vec()))
+            .unwrap();
+
+        let reply = loop {
+            let message = client
+                .wait_message(Duration::from_secs(5))
+                .unwrap()
+                .unwrap();
+            if message.reply_serial() == Some(serial) {
+                break message;
+            }
+        };
+        assert_eq!(reply.body(), call.body());
+    }
+}
@@ -0,0 +1,41 @@
+use nix;
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidAddress(String),
+    AuthenticationFailed,
+    InvalidMessage(&'static str),
+    MethodError(String, String),
+    Timeout,
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(value: nix::Error) -> Self {
+        Self::Io(value.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => error.fmt(f),
+            Self::InvalidAddress(address) => write!(f, "Invalid D-Bus address: {}", address),
+            Self::AuthenticationFailed => f.write_str("D-Bus authentication failed"),
+            Self::InvalidMessage(reason) => write!(f, "Invalid D-Bus message: {}", reason),
+            Self::MethodError(name, message) => write!(f, "{}: {}", name, message),
+            Self::Timeout => f.write_str("D-Bus method call timed out"),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,391 @@
+use super::error::Error;
+use super::value::{Decoder, Encoder, Type, Value};
+
+const HEADER_FIELD_PATH: u8 = 1;
+const HEADER_FIELD_INTERFACE: u8 = 2;
+const HEADER_FIELD_MEMBER: u8 = 3;
+const HEADER_FIELD_ERROR_NAME: u8 = 4;
+const HEADER_FIELD_REPLY_SERIAL: u8 = 5;
+const HEADER_FIELD_DESTINATION: u8 = 6;
+const HEADER_FIELD_SENDER: u8 = 7;
+const HEADER_FIELD_SIGNATURE: u8 = 8;
+
+const FLAG_NO_REPLY_EXPECTED: u8 = 0x1;
+
+const PROTOCOL_VERSION: u8 = 1;
+
+const FIXED_HEADER_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageType {
+    MethodCall,
+    MethodReturn,
+    Error,
+    Signal,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::MethodCall),
+            2 => Some(Self::MethodReturn),
+            3 => Some(Self::Error),
+            4 => Some(Self::Signal),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::MethodCall => 1,
+            Self::MethodReturn => 2,
+            Self::Error => 3,
+            Self::Signal => 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    message_type: MessageType,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    body: Vec<Value>,
+}
+
+impl Message {
+    pub fn method_call(destination: &str, path: &str, interface: &str, member: &str) -> Self {
+        Self {
+            path: Some(path.to_owned()),
+            interface: Some(interface.to_owned()),
+            member: Some(member.to_owned()),
+            destination: Some(destination.to_owned()),
+            ..Self::empty(MessageType::MethodCall)
+        }
+    }
+
+    pub fn method_return(call: &Message) -> Self {
+        Self {
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            ..Self::empty(MessageType::MethodReturn)
+        }
+    }
+
+    pub fn error(call: &Message, error_name: &str, error_message: &str) -> Self {
+        Self {
+            error_name: Some(error_name.to_owned()),
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            body: vec![error_message.into()],
+            ..Self::empty(MessageType::Error)
+        }
+    }
+
+    pub fn signal(path: &str, interface: &str, member: &str) -> Self {
+        Self {
+            path: Some(path.to_owned()),
+            interface: Some(interface.to_owned()),
+            member: Some(member.to_owned()),
+            ..Self::empty(MessageType::Signal)
+        }
+    }
+
+    pub fn with_body(mut self, body: Vec<Value>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn with_destination(mut self, destination: &str) -> Self {
+        self.destination = Some(destination.to_owned());
+        self
+    }
+
+    pub fn without_reply(mut self) -> Self {
+        self.flags |= FLAG_NO_REPLY_EXPECTED;
+        self
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn interface(&self) -> Option<&str> {
+        self.interface.as_deref()
+    }
+
+    pub fn member(&self) -> Option<&str> {
+        self.member.as_deref()
+    }
+
+    pub fn error_name(&self) -> Option<&str> {
+        self.error_name.as_deref()
+    }
+
+    pub fn reply_serial(&self) -> Option<u32> {
+        self.reply_serial
+    }
+
+    pub fn destination(&self) -> Option<&str> {
+        self.destination.as_deref()
+    }
+
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_deref()
+    }
+
+    pub fn body(&self) -> &[Value] {
+        &self.body
+    }
+
+    pub fn expects_reply(&self) -> bool {
+        self.message_type == MessageType::MethodCall && self.flags & FLAG_NO_REPLY_EXPECTED == 0
+    }
+
+    pub fn is_signal(&self, interface: &str, member: &str) -> bool {
+        self.message_type == MessageType::Signal
+            && self.interface.as_deref() == Some(interface)
+            && self.member.as_deref() == Some(member)
+    }
+
+    pub fn is_method_call(&self, interface: &str, member: &str) -> bool {
+        self.message_type == MessageType::MethodCall
+            && self.interface.as_deref() == Some(interface)
+            && self.member.as_deref() == Some(member)
+    }
+
+    pub fn into_result(self) -> Result<Self, Error> {
+        if self.message_type == MessageType::Error {
+            let error_message = self
+                .body
+                .first()
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned();
+            Err(Error::MethodError(
+                self.error_name.unwrap_or_default(),
+                error_message,
+            ))
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub(super) fn set_serial(&mut self, serial: u32) {
+        self.serial = serial;
+    }
+
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        let mut body_encoder = Encoder::new();
+        for value in &self.body {
+            body_encoder.write_value(value);
+        }
+        let body = body_encoder.into_bytes();
+
+        let mut header_fields = Vec::new();
+        let mut push_field = |code: u8, value: Value| {
+            header_fields.push(Value::Struct(vec![
+                Value::Byte(code),
+                Value::variant(value),
+            ]));
+        };
+        if let Some(path) = &self.path {
+            push_field(HEADER_FIELD_PATH, Value::ObjectPath(path.clone()));
+        }
+        if let Some(interface) = &self.interface {
+            push_field(HEADER_FIELD_INTERFACE, interface.as_str().into());
+        }
+        if let Some(member) = &self.member {
+            push_field(HEADER_FIELD_MEMBER, member.as_str().into());
+        }
+        if let Some(error_name) = &self.error_name {
+            push_field(HEADER_FIELD_ERROR_NAME, error_name.as_str().into());
+        }
+        if let Some(reply_serial) = self.reply_serial {
+            push_field(HEADER_FIELD_REPLY_SERIAL, reply_serial.into());
+        }
+        if let Some(destination) = &self.destination {
+            push_field(HEADER_FIELD_DESTINATION, destination.as_str().into());
+        }
+        if let Some(sender) = &self.sender {
+            push_field(HEADER_FIELD_SENDER, sender.as_str().into());
+        }
+        if !self.body.is_empty() {
+            let signature = self.body.iter().map(Value::signature).collect();
+            push_field(HEADER_FIELD_SIGNATURE, Value::Signature(signature));
+        }
+
+        let mut encoder = Encoder::new();
+        encoder.write_u8(b'l');
+        encoder.write_u8(self.message_type.to_u8());
+        encoder.write_u8(self.flags);
+        encoder.write_u8(PROTOCOL_VERSION);
+        encoder.write_u32(body.len() as u32);
+        encoder.write_u32(self.serial);
+        encoder.write_value(&Value::Array("(yv)".to_owned(), header_fields));
+        encoder.align(8);
+        encoder.write_bytes(&body);
+        encoder.into_bytes()
+    }
+
+    pub(super) fn required_len(bytes: &[u8]) -> Result<Option<usize>, Error> {
+        if bytes.len() < FIXED_HEADER_LEN {
+            return Ok(None);
+        }
+        let is_big_endian = match bytes[0] {
+            b'l' => false,
+            b'B' => true,
+            _ => return Err(Error::InvalidMessage("unknown endianness")),
+        };
+        let read_u32 = |offset: usize| {
+            let mut array = [0; 4];
+            array.copy_from_slice(&bytes[offset..offset + 4]);
+            if is_big_endian {
+                u32::from_be_bytes(array)
+            } else {
+                u32::from_le_bytes(array)
+            }
+        };
+        let body_len = read_u32(4) as usize;
+        let header_fields_len = read_u32(12) as usize;
+        let header_len = (FIXED_HEADER_LEN + header_fields_len + 7) / 8 * 8;
+        Ok(Some(header_len + body_len))
+    }
+
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let is_big_endian = bytes.first() == Some(&b'B');
+        let mut decoder = Decoder::new(bytes, is_big_endian);
+        decoder.read_u8()?;
+        let message_type = MessageType::from_u8(decoder.read_u8()?)
+            .ok_or(Error::InvalidMessage("unknown message type"))?;
+        let flags = decoder.read_u8()?;
+        if decoder.read_u8()? != PROTOCOL_VERSION {
+            return Err(Error::InvalidMessage("unsupported protocol version"));
+        }
+        let body_len = decoder.read_u32()? as usize;
+        let serial = decoder.read_u32()?;
+        let header_fields = decoder.read_value(&Type::Array(Box::new(Type::Struct(vec![
+            Type::Byte,
+            Type::Variant,
+        ]))))?;
+        decoder.align(8)?;
+
+        let mut message = Self {
+            serial,
+            flags,
+            ..Self::empty(message_type)
+        };
+        let mut signature = String::new();
+
+        for field in header_fields.as_array().unwrap_or_default() {
+            let (code, value) = match field.as_struct() {
+                Some([Value::Byte(code), value]) => (*code, value),
+                _ => continue,
+            };
+            match code {
+                HEADER_FIELD_PATH => message.path = value.as_str().map(ToOwned::to_owned),
+                HEADER_FIELD_INTERFACE => message.interface = value.as_str().map(ToOwned::to_owned),
+                HEADER_FIELD_MEMBER => message.member = value.as_str().map(ToOwned::to_owned),
+                HEADER_FIELD_ERROR_NAME => {
+                    message.error_name = value.as_str().map(ToOwned::to_owned)
+                }
+                HEADER_FIELD_REPLY_SERIAL => message.reply_serial = value.as_u32(),
+                HEADER_FIELD_DESTINATION => {
+                    message.destination = value.as_str().map(ToOwned::to_owned)
+                }
+                HEADER_FIELD_SENDER => message.sender = value.as_str().map(ToOwned::to_owned),
+                HEADER_FIELD_SIGNATURE => {
+                    signature = value.as_str().unwrap_or_default().to_owned();
+                }
+                _ => {}
+            }
+        }
+
+        let body_start = decoder.position();
+        let body = bytes
+            .get(body_start..body_start + body_len)
+            .ok_or(Error::InvalidMessage("body length exceeds message"))?;
+        let types = Type::parse_signature(&signature)?;
+        message.body = Decoder::new(body, is_big_endian).read_values(&types)?;
+
+        Ok(message)
+    }
+
+    fn empty(message_type: MessageType) -> Self {
+        Self {
+            message_type,
+            flags: 0,
+            serial: 0,
+            path: None,
+            interface: None,
+            member: None,
+            error_name: None,
+            reply_serial: None,
+            destination: None,
+            sender: None,
+            body: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marshal_roundtrip() {
+        let mut message = Message::method_call(
+            "org.kde.StatusNotifierWatcher",
+            "/StatusNotifierWatcher",
+            "org.kde.StatusNotifierWatcher",
+            "RegisterStatusNotifierHost",
+        )
+        .with_body(vec![
+            Value::Byte(7),
+            "org.kde.StatusNotifierHost-1".into(),
+            Value::Int64(-42),
+            Value::dict(
+                "s",
+                "v",
+                vec![
+                    ("Title".into(), Value::variant("GeekyTray")),
+                    ("ItemIsMenu".into(), Value::variant(true)),
+                    (
+                        "IconPixmap".into(),
+                        Value::variant(Value::Array(
+                            "(iiay)".to_owned(),
+                            vec![Value::Struct(vec![
+                                Value::Int32(1),
+                                Value::Int32(1),
+                                Value::ByteArray(vec![0xff, 0x00, 0x80, 0x40]),
+                            ])],
+                        )),
+                    ),
+                ],
+            ),
+            Value::Array("s".to_owned(), Vec::new()),
+            Value::Double(0.5),
+        ]);
+        message.set_serial(3);
+
+        let bytes = message.to_bytes();
+        assert_eq!(Message::required_len(&bytes).unwrap(), Some(bytes.len()));
+        assert_eq!(Message::required_len(&bytes[..15]).unwrap(), None);
+        assert_eq!(Message::from_bytes(&bytes).unwrap(), message);
+    }
+}
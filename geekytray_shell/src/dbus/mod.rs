@@ -0,0 +1,14 @@
+mod connection;
+mod error;
+mod message;
+mod value;
+
+pub use connection::Connection;
+pub use error::Error;
+pub use message::{Message, MessageType};
+pub use value::Value;
+
+pub const BUS_NAME: &str = "org.freedesktop.DBus";
+pub const BUS_PATH: &str = "/org/freedesktop/DBus";
+pub const BUS_INTERFACE: &str = "org.freedesktop.DBus";
+pub const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
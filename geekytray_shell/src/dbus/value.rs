@@ -0,0 +1,580 @@
+use std::str;
+
+use super::error::Error;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Boolean(bool),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Double(f64),
+    String(String),
+    ObjectPath(String),
+    Signature(String),
+    UnixFd(u32),
+    ByteArray(Vec<u8>),
+    Array(String, Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub fn dict(key_signature: &str, value_signature: &str, entries: Vec<(Value, Value)>) -> Self {
+        Self::Array(
+            format!("{{{}{}}}", key_signature, value_signature),
+            entries
+                .into_iter()
+                .map(|(key, value)| Self::DictEntry(Box::new(key), Box::new(value)))
+                .collect(),
+        )
+    }
+
+    pub fn variant(value: impl Into<Value>) -> Self {
+        Self::Variant(Box::new(value.into()))
+    }
+
+    pub fn signature(&self) -> String {
+        match self {
+            Self::Byte(_) => "y".to_owned(),
+            Self::Boolean(_) => "b".to_owned(),
+            Self::Int16(_) => "n".to_owned(),
+            Self::UInt16(_) => "q".to_owned(),
+            Self::Int32(_) => "i".to_owned(),
+            Self::UInt32(_) => "u".to_owned(),
+            Self::Int64(_) => "x".to_owned(),
+            Self::UInt64(_) => "t".to_owned(),
+            Self::Double(_) => "d".to_owned(),
+            Self::String(_) => "s".to_owned(),
+            Self::ObjectPath(_) => "o".to_owned(),
+            Self::Signature(_) => "g".to_owned(),
+            Self::UnixFd(_) => "h".to_owned(),
+            Self::ByteArray(_) => "ay".to_owned(),
+            Self::Array(element_signature, _) => format!("a{}", element_signature),
+            Self::Struct(fields) => {
+                let mut signature = "(".to_owned();
+                for field in fields {
+                    signature.push_str(&field.signature());
+                }
+                signature.push(')');
+                signature
+            }
+            Self::DictEntry(key, value) => {
+                format!("{{{}{}}}", key.signature(), value.signature())
+            }
+            Self::Variant(_) => "v".to_owned(),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(value) => Some(*value),
+            Self::Variant(value) => value.as_bool(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            Self::Int32(value) => Some(*value),
+            Self::Variant(value) => value.as_i32(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Self::UInt32(value) => Some(*value),
+            Self::Variant(value) => value.as_u32(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) | Self::ObjectPath(value) | Self::Signature(value) => Some(value),
+            Self::Variant(value) => value.as_str(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::ByteArray(value) => Some(value),
+            Self::Variant(value) => value.as_bytes(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(_, values) => Some(values),
+            Self::Variant(value) => value.as_array(),
+            _ => None,
+        }
+    }
+
+    pub fn as_struct(&self) -> Option<&[Value]> {
+        match self {
+            Self::Struct(fields) => Some(fields),
+            Self::Variant(value) => value.as_struct(),
+            _ => None,
+        }
+    }
+
+    pub fn dict_entries(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.as_array()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|entry| match entry {
+                Self::DictEntry(key, value) => Some((key.as_ref(), value.as_ref())),
+                _ => None,
+            })
+    }
+
+    pub fn dict_get(&self, key: &str) -> Option<&Value> {
+        self.dict_entries()
+            .find(|(entry_key, _)| entry_key.as_str() == Some(key))
+            .map(|(_, value)| value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Boolean(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Self::Int32(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Self::UInt32(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) enum Type {
+    Byte,
+    Boolean,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    UInt64,
+    Double,
+    String,
+    ObjectPath,
+    Signature,
+    UnixFd,
+    Array(Box<Type>),
+    Struct(Vec<Type>),
+    DictEntry(Box<Type>, Box<Type>),
+    Variant,
+}
+
+impl Type {
+    pub(super) fn parse_signature(signature: &str) -> Result<Vec<Type>, Error> {
+        let mut types = Vec::new();
+        let mut rest = signature.as_bytes();
+        while !rest.is_empty() {
+            let (ty, next) = Self::parse_single(rest)?;
+            types.push(ty);
+            rest = next;
+        }
+        Ok(types)
+    }
+
+    fn parse_single(signature: &[u8]) -> Result<(Type, &[u8]), Error> {
+        let (head, rest) = signature
+            .split_first()
+            .ok_or(Error::InvalidMessage("unexpected end of signature"))?;
+        let ty = match head {
+            b'y' => Self::Byte,
+            b'b' => Self::Boolean,
+            b'n' => Self::Int16,
+            b'q' => Self::UInt16,
+            b'i' => Self::Int32,
+            b'u' => Self::UInt32,
+            b'x' => Self::Int64,
+            b't' => Self::UInt64,
+            b'd' => Self::Double,
+            b's' => Self::String,
+            b'o' => Self::ObjectPath,
+            b'g' => Self::Signature,
+            b'h' => Self::UnixFd,
+            b'v' => Self::Variant,
+            b'a' => {
+                let (element, rest) = Self::parse_single(rest)?;
+                return Ok((Self::Array(Box::new(element)), rest));
+            }
+            b'(' => {
+                let mut fields = Vec::new();
+                let mut rest = rest;
+                loop {
+                    match rest.first() {
+                        Some(b')') => break,
+                        Some(_) => {
+                            let (field, next) = Self::parse_single(rest)?;
+                            fields.push(field);
+                            rest = next;
+                        }
+                        None => return Err(Error::InvalidMessage("unterminated struct")),
+                    }
+                }
+                if fields.is_empty() {
+                    return Err(Error::InvalidMessage("empty struct"));
+                }
+                return Ok((Self::Struct(fields), &rest[1..]));
+            }
+            b'{' => {
+                let (key, rest) = Self::parse_single(rest)?;
+                let (value, rest) = Self::parse_single(rest)?;
+                match rest.split_first() {
+                    Some((b'}', rest)) => {
+                        return Ok((Self::DictEntry(Box::new(key), Box::new(value)), rest));
+                    }
+                    _ => return Err(Error::InvalidMessage("unterminated dict entry")),
+                }
+            }
+            _ => return Err(Error::InvalidMessage("unknown type code in signature")),
+        };
+        Ok((ty, rest))
+    }
+
+    fn signature(&self) -> String {
+        match self {
+            Self::Byte => "y".to_owned(),
+            Self::Boolean => "b".to_owned(),
+            Self::Int16 => "n".to_owned(),
+            Self::UInt16 => "q".to_owned(),
+            Self::Int32 => "i".to_owned(),
+            Self::UInt32 => "u".to_owned(),
+            Self::Int64 => "x".to_owned(),
+            Self::UInt64 => "t".to_owned(),
+            Self::Double => "d".to_owned(),
+            Self::String => "s".to_owned(),
+            Self::ObjectPath => "o".to_owned(),
+            Self::Signature => "g".to_owned(),
+            Self::UnixFd => "h".to_owned(),
+            Self::Array(element) => format!("a{}", element.signature()),
+            Self::Struct(fields) => {
+                let mut signature = "(".to_owned();
+                for field in fields {
+                    signature.push_str(&field.signature());
+                }
+                signature.push(')');
+                signature
+            }
+            Self::DictEntry(key, value) => format!("{{{}{}}}", key.signature(), value.signature()),
+            Self::Variant => "v".to_owned(),
+        }
+    }
+
+    fn alignment(&self) -> usize {
+        match self {
+            Self::Byte | Self::Signature | Self::Variant => 1,
+            Self::Int16 | Self::UInt16 => 2,
+            Self::Boolean
+            | Self::Int32
+            | Self::UInt32
+            | Self::String
+            | Self::ObjectPath
+            | Self::UnixFd
+            | Self::Array(_) => 4,
+            Self::Int64 | Self::UInt64 | Self::Double | Self::Struct(_) | Self::DictEntry(_, _) => {
+                8
+            }
+        }
+    }
+}
+
+pub(super) struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    pub(super) fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    pub(super) fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    pub(super) fn align(&mut self, alignment: usize) {
+        while self.buffer.len() % alignment != 0 {
+            self.buffer.push(0);
+        }
+    }
+
+    pub(super) fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub(super) fn write_u32(&mut self, value: u32) {
+        self.align(4);
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub(super) fn write_value(&mut self, value: &Value) {
+        match value {
+            Value::Byte(value) => self.write_u8(*value),
+            Value::Boolean(value) => self.write_u32(*value as u32),
+            Value::Int16(value) => {
+                self.align(2);
+                self.write_bytes(&value.to_le_bytes());
+            }
+            Value::UInt16(value) => {
+                self.align(2);
+                self.write_bytes(&value.to_le_bytes());
+            }
+            Value::Int32(value) => {
+                self.align(4);
+                self.write_bytes(&value.to_le_bytes());
+            }
+            Value::UInt32(value) | Value::UnixFd(value) => self.write_u32(*value),
+            Value::Int64(value) => {
+                self.align(8);
+                self.write_bytes(&value.to_le_bytes());
+            }
+            Value::UInt64(value) => {
+                self.align(8);
+                self.write_bytes(&value.to_le_bytes());
+            }
+            Value::Double(value) => {
+                self.align(8);
+                self.write_bytes(&value.to_le_bytes());
+            }
+            Value::String(value) | Value::ObjectPath(value) => {
+                self.write_u32(value.len() as u32);
+                self.write_bytes(value.as_bytes());
+                self.write_u8(0);
+            }
+            Value::Signature(value) => self.write_signature(value),
+            Value::ByteArray(bytes) => {
+                self.write_u32(bytes.len() as u32);
+                self.write_bytes(bytes);
+            }
+            Value::Array(element_signature, elements) => {
+                let element_alignment = Type::parse_signature(element_signature)
+                    .ok()
+                    .and_then(|types| types.first().map(Type::alignment))
+                    .unwrap_or(1);
+                self.write_u32(0);
+                let length_offset = self.buffer.len() - 4;
+                self.align(element_alignment);
+                let start = self.buffer.len();
+                for element in elements {
+                    self.write_value(element);
+                }
+                let length = (self.buffer.len() - start) as u32;
+                self.buffer[length_offset..length_offset + 4]
+                    .copy_from_slice(&length.to_le_bytes());
+            }
+            Value::Struct(fields) => {
+                self.align(8);
+                for field in fields {
+                    self.write_value(field);
+                }
+            }
+            Value::DictEntry(key, value) => {
+                self.align(8);
+                self.write_value(key);
+                self.write_value(value);
+            }
+            Value::Variant(value) => {
+                self.write_signature(&value.signature());
+                self.write_value(value);
+            }
+        }
+    }
+
+    fn write_signature(&mut self, signature: &str) {
+        self.write_u8(signature.len() as u8);
+        self.write_bytes(signature.as_bytes());
+        self.write_u8(0);
+    }
+}
+
+pub(super) struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    is_big_endian: bool,
+}
+
+impl<'a> Decoder<'a> {
+    pub(super) fn new(data: &'a [u8], is_big_endian: bool) -> Self {
+        Self {
+            data,
+            position: 0,
+            is_big_endian,
+        }
+    }
+
+    pub(super) fn position(&self) -> usize {
+        self.position
+    }
+
+    pub(super) fn align(&mut self, alignment: usize) -> Result<(), Error> {
+        let padding = (alignment - self.position % alignment) % alignment;
+        self.take(padding)?;
+        Ok(())
+    }
+
+    pub(super) fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(super) fn read_u32(&mut self) -> Result<u32, Error> {
+        self.align(4)?;
+        let bytes = self.take_array::<4>()?;
+        Ok(if self.is_big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    pub(super) fn read_values(&mut self, types: &[Type]) -> Result<Vec<Value>, Error> {
+        types.iter().map(|ty| self.read_value(ty)).collect()
+    }
+
+    pub(super) fn read_value(&mut self, ty: &Type) -> Result<Value, Error> {
+        let value = match ty {
+            Type::Byte => Value::Byte(self.read_u8()?),
+            Type::Boolean => Value::Boolean(self.read_u32()? != 0),
+            Type::Int16 => {
+                self.align(2)?;
+                let bytes = self.take_array::<2>()?;
+                Value::Int16(if self.is_big_endian {
+                    i16::from_be_bytes(bytes)
+                } else {
+                    i16::from_le_bytes(bytes)
+                })
+            }
+            Type::UInt16 => {
+                self.align(2)?;
+                let bytes = self.take_array::<2>()?;
+                Value::UInt16(if self.is_big_endian {
+                    u16::from_be_bytes(bytes)
+                } else {
+                    u16::from_le_bytes(bytes)
+                })
+            }
+            Type::Int32 => Value::Int32(self.read_u32()? as i32),
+            Type::UInt32 => Value::UInt32(self.read_u32()?),
+            Type::UnixFd => Value::UnixFd(self.read_u32()?),
+            Type::Int64 => Value::Int64(self.read_u64()? as i64),
+            Type::UInt64 => Value::UInt64(self.read_u64()?),
+            Type::Double => Value::Double(f64::from_bits(self.read_u64()?)),
+            Type::String => Value::String(self.read_string()?),
+            Type::ObjectPath => Value::ObjectPath(self.read_string()?),
+            Type::Signature => Value::Signature(self.read_signature()?),
+            Type::Array(element) => {
+                let length = self.read_u32()? as usize;
+                self.align(element.alignment())?;
+                if **element == Type::Byte {
+                    Value::ByteArray(self.take(length)?.to_vec())
+                } else {
+                    let end = self.position + length;
+                    if end > self.data.len() {
+                        return Err(Error::InvalidMessage("array length exceeds message"));
+                    }
+                    let mut elements = Vec::new();
+                    while self.position < end {
+                        elements.push(self.read_value(element)?);
+                    }
+                    Value::Array(element.signature(), elements)
+                }
+            }
+            Type::Struct(fields) => {
+                self.align(8)?;
+                Value::Struct(self.read_values(fields)?)
+            }
+            Type::DictEntry(key, value) => {
+                self.align(8)?;
+                let key = self.read_value(key)?;
+                let value = self.read_value(value)?;
+                Value::DictEntry(Box::new(key), Box::new(value))
+            }
+            Type::Variant => {
+                let signature = self.read_signature()?;
+                let types = Type::parse_signature(&signature)?;
+                match types.as_slice() {
+                    [ty] => Value::Variant(Box::new(self.read_value(ty)?)),
+                    _ => return Err(Error::InvalidMessage("variant must have a single type")),
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        self.align(8)?;
+        let bytes = self.take_array::<8>()?;
+        Ok(if self.is_big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_string(&mut self) -> Result<String, Error> {
+        let length = self.read_u32()? as usize;
+        let bytes = self.take(length + 1)?;
+        str::from_utf8(&bytes[..length])
+            .map(ToOwned::to_owned)
+            .map_err(|_| Error::InvalidMessage("string is not valid UTF-8"))
+    }
+
+    fn read_signature(&mut self) -> Result<String, Error> {
+        let length = self.read_u8()? as usize;
+        let bytes = self.take(length + 1)?;
+        str::from_utf8(&bytes[..length])
+            .map(ToOwned::to_owned)
+            .map_err(|_| Error::InvalidMessage("signature is not valid UTF-8"))
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let end = self.position + length;
+        if end > self.data.len() {
+            return Err(Error::InvalidMessage("unexpected end of message"));
+        }
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
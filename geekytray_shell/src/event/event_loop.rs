@@ -7,22 +7,29 @@ use nix::unistd;
 use std::cmp::Reverse;
 use std::collections::BinaryHeap;
 use std::io;
+use std::mem;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 use x11rb::connection::Connection;
 use x11rb::protocol;
 
+use crate::dbus;
+
 const EVENT_KIND_X11: u64 = 1;
 const EVENT_KIND_TIMER: u64 = 2;
 const EVENT_KIND_SIGNAL: u64 = 3;
+const EVENT_KIND_DBUS: u64 = 4;
 
 #[derive(Debug)]
 pub struct EventLoop<C> {
     connection: Rc<C>,
     epoll_fd: RawFd,
     signal_fd: signalfd::SignalFd,
+    signal_mask: signalfd::SigSet,
     timer_fd: Rc<timerfd::TimerFd>,
+    dbus_connection: Option<Rc<dbus::Connection>>,
+    context: EventLoopContext,
 }
 
 impl<C: Connection + AsRawFd> EventLoop<C> {
@@ -40,12 +47,11 @@ impl<C: Connection + AsRawFd> EventLoop<C> {
             )?;
         }
 
-        let signal_fd = {
-            let mut mask = signalfd::SigSet::empty();
-            mask.add(signal::Signal::SIGINT);
-            mask.thread_block()?;
-            signalfd::SignalFd::new(&mask)
-        }?;
+        let mut signal_mask = signalfd::SigSet::empty();
+        signal_mask.add(signal::Signal::SIGINT);
+        signal_mask.thread_block()?;
+
+        let signal_fd = signalfd::SignalFd::new(&signal_mask)?;
 
         {
             let raw_fd = signal_fd.as_raw_fd();
@@ -74,24 +80,65 @@ impl<C: Connection + AsRawFd> EventLoop<C> {
             )?;
         }
 
+        let timer_fd = Rc::new(timer_fd);
+
         Ok(Self {
             connection,
             epoll_fd,
             signal_fd,
-            timer_fd: Rc::new(timer_fd),
+            signal_mask,
+            timer_fd: timer_fd.clone(),
+            dbus_connection: None,
+            context: EventLoopContext::new(timer_fd),
         })
     }
 
+    pub fn context_mut(&mut self) -> &mut EventLoopContext {
+        &mut self.context
+    }
+
+    pub fn watch_signals(&mut self, signals: &[signal::Signal]) -> io::Result<()> {
+        for signal in signals {
+            self.signal_mask.add(*signal);
+        }
+        self.signal_mask.thread_block()?;
+        self.signal_fd.set_mask(&self.signal_mask)?;
+        Ok(())
+    }
+
+    pub fn watch_dbus_connection(&mut self, connection: Rc<dbus::Connection>) -> io::Result<()> {
+        let raw_fd = connection.as_raw_fd();
+        let mut event = epoll::EpollEvent::new(epoll::EpollFlags::EPOLLIN, EVENT_KIND_DBUS);
+        epoll::epoll_ctl(
+            self.epoll_fd,
+            epoll::EpollOp::EpollCtlAdd,
+            raw_fd,
+            Some(&mut event),
+        )?;
+        self.dbus_connection = Some(connection);
+        Ok(())
+    }
+
     pub fn run<F>(&mut self, mut callback: F) -> anyhow::Result<()>
     where
         F: FnMut(Event, &mut EventLoopContext, &mut ControlFlow) -> anyhow::Result<()>,
     {
-        let mut epoll_events = vec![epoll::EpollEvent::empty(); 3];
+        let mut epoll_events = vec![epoll::EpollEvent::empty(); 4];
         let mut control_flow = ControlFlow::Continue;
 
-        let mut context = EventLoopContext::new(self);
+        let mut context = mem::replace(
+            &mut self.context,
+            EventLoopContext::new(self.timer_fd.clone()),
+        );
 
         'outer: loop {
+            // Messages may have been queued while waiting for a method reply.
+            self.dispatch_dbus_messages(&mut callback, &mut context, &mut control_flow)?;
+
+            if matches!(control_flow, ControlFlow::Break) {
+                break 'outer;
+            }
+
             let available_fds =
                 epoll::epoll_wait(self.epoll_fd, &mut epoll_events, -1).unwrap_or(0);
 
@@ -121,6 +168,12 @@ impl<C: Connection + AsRawFd> EventLoop<C> {
                             break 'outer;
                         }
                     }
+                } else if epoll_event.data() == EVENT_KIND_DBUS {
+                    self.dispatch_dbus_messages(&mut callback, &mut context, &mut control_flow)?;
+
+                    if matches!(control_flow, ControlFlow::Break) {
+                        break 'outer;
+                    }
                 } else {
                     unreachable!();
                 }
@@ -133,6 +186,50 @@ impl<C: Connection + AsRawFd> EventLoop<C> {
             }
         }
 
+        self.context = context;
+
+        Ok(())
+    }
+
+    fn dispatch_dbus_messages<F>(
+        &mut self,
+        callback: &mut F,
+        context: &mut EventLoopContext,
+        control_flow: &mut ControlFlow,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(Event, &mut EventLoopContext, &mut ControlFlow) -> anyhow::Result<()>,
+    {
+        let connection = match &self.dbus_connection {
+            Some(connection) => connection.clone(),
+            None => return Ok(()),
+        };
+
+        loop {
+            match connection.poll_message() {
+                Ok(Some(message)) => {
+                    callback(Event::DBusMessage(message), context, control_flow)?;
+
+                    if matches!(control_flow, ControlFlow::Break) {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    log::error!("Stop watching the D-Bus connection: {}", error);
+                    epoll::epoll_ctl(
+                        self.epoll_fd,
+                        epoll::EpollOp::EpollCtlDel,
+                        connection.as_raw_fd(),
+                        None,
+                    )
+                    .ok();
+                    self.dbus_connection = None;
+                    break;
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -143,6 +240,7 @@ impl<Connection> Drop for EventLoop<Connection> {
     }
 }
 
+#[derive(Debug)]
 pub struct EventLoopContext {
     timer_fd: Rc<timerfd::TimerFd>,
     timer_counter: TimerId,
@@ -150,9 +248,9 @@ pub struct EventLoopContext {
 }
 
 impl EventLoopContext {
-    fn new<C>(event_loop: &EventLoop<C>) -> Self {
+    fn new(timer_fd: Rc<timerfd::TimerFd>) -> Self {
         Self {
-            timer_fd: event_loop.timer_fd.clone(),
+            timer_fd,
             timer_counter: 0,
             timer_queue: BinaryHeap::new(),
         }
@@ -221,6 +319,7 @@ pub enum Event {
     X11Event(protocol::Event),
     Signal(signalfd::siginfo),
     Timer(Timer),
+    DBusMessage(dbus::Message),
     NextTick,
 }
 
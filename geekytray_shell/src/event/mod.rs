@@ -4,4 +4,4 @@ mod mouse;
 
 pub use event_loop::{ControlFlow, Event, EventLoop, EventLoopContext, Timer, TimerId};
 pub use keyboard::{KeyState, Keysym, Modifiers};
-pub use mouse::MouseButton;
+pub use mouse::{MouseButton, ScrollDirection};
@@ -8,3 +8,11 @@ pub enum MouseButton {
     X1,
     X2,
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum ScrollDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
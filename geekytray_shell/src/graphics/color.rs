@@ -43,6 +43,10 @@ impl Color {
         }
     }
 
+    pub const fn with_alpha(self, alpha: u8) -> Self {
+        Self { alpha, ..self }
+    }
+
     pub fn to_u16_rgba(&self) -> [u16; 4] {
         let r = self.red as u16;
         let g = self.green as u16;
@@ -0,0 +1,171 @@
+use cairo_sys as cairo;
+use std::fs;
+use std::os::raw::*;
+use std::path::Path;
+use std::slice;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    // Premultiplied ARGB32 pixels in native byte order, as cairo expects.
+    pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn from_argb32_be(width: u32, height: u32, bytes: &[u8]) -> Option<Self> {
+        let len = (width as usize) * (height as usize);
+        if len == 0 || bytes.len() < len * 4 {
+            return None;
+        }
+        let pixels = bytes
+            .chunks_exact(4)
+            .take(len)
+            .map(|chunk| {
+                let [a, r, g, b] = [chunk[0], chunk[1], chunk[2], chunk[3]];
+                let premultiply = |c: u8| (c as u32 * a as u32 + 127) / 255;
+                (a as u32) << 24 | premultiply(r) << 16 | premultiply(g) << 8 | premultiply(b)
+            })
+            .collect();
+        Some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn from_png(path: impl AsRef<Path>) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        let mut reader = PngReader {
+            data: &data,
+            position: 0,
+        };
+
+        unsafe {
+            let surface = cairo::cairo_image_surface_create_from_png_stream(
+                Some(read_png),
+                (&mut reader as *mut PngReader).cast(),
+            );
+            let image = if cairo::cairo_surface_status(surface) == cairo::STATUS_SUCCESS {
+                Self::from_image_surface(surface)
+            } else {
+                None
+            };
+            cairo::cairo_surface_destroy(surface);
+            image
+        }
+    }
+
+    unsafe fn from_image_surface(surface: *mut cairo::cairo_surface_t) -> Option<Self> {
+        cairo::cairo_surface_flush(surface);
+
+        let format = cairo::cairo_image_surface_get_format(surface);
+        if format != cairo::FORMAT_A_RGB32 && format != cairo::FORMAT_RGB24 {
+            return None;
+        }
+
+        let width = cairo::cairo_image_surface_get_width(surface) as usize;
+        let height = cairo::cairo_image_surface_get_height(surface) as usize;
+        let stride = cairo::cairo_image_surface_get_stride(surface) as usize;
+        let data = cairo::cairo_image_surface_get_data(surface);
+        if data.is_null() || width == 0 || height == 0 {
+            return None;
+        }
+
+        let bytes = slice::from_raw_parts(data, stride * height);
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in bytes.chunks_exact(stride) {
+            for chunk in row[..width * 4].chunks_exact(4) {
+                let pixel = u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                if format == cairo::FORMAT_RGB24 {
+                    pixels.push(pixel | 0xff000000);
+                } else {
+                    pixels.push(pixel);
+                }
+            }
+        }
+
+        Some(Self {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn scale(&self, width: u32, height: u32) -> Self {
+        if width == self.width && height == self.height {
+            return self.clone();
+        }
+
+        let mut pixels = Vec::with_capacity((width as usize) * (height as usize));
+
+        for y in 0..height {
+            let (y0, y1) = source_span(y, height, self.height);
+            for x in 0..width {
+                let (x0, x1) = source_span(x, width, self.width);
+                let mut sum = [0u32; 4];
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        let pixel = self.pixels[(sy * self.width + sx) as usize];
+                        for (i, total) in sum.iter_mut().enumerate() {
+                            *total += (pixel >> (24 - i * 8)) & 0xff;
+                        }
+                    }
+                }
+                let count = (y1 - y0) * (x1 - x0);
+                let [a, r, g, b] = sum.map(|total| (total + count / 2) / count);
+                pixels.push(a << 24 | r << 16 | g << 8 | b);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_ne_bytes())
+            .collect()
+    }
+}
+
+fn source_span(destination: u32, destination_len: u32, source_len: u32) -> (u32, u32) {
+    let start = (destination as u64 * source_len as u64 / destination_len as u64) as u32;
+    let end = (((destination as u64 + 1) * source_len as u64 + destination_len as u64 - 1)
+        / destination_len as u64) as u32;
+    let start = start.min(source_len - 1);
+    (start, end.clamp(start + 1, source_len))
+}
+
+struct PngReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+unsafe extern "C" fn read_png(
+    closure: *mut c_void,
+    buffer: *mut c_uchar,
+    length: c_uint,
+) -> cairo::cairo_status_t {
+    let reader = &mut *(closure as *mut PngReader);
+    let length = length as usize;
+    if reader.position + length > reader.data.len() {
+        return cairo::STATUS_READ_ERROR;
+    }
+    let source = &reader.data[reader.position..reader.position + length];
+    slice::from_raw_parts_mut(buffer, length).copy_from_slice(source);
+    reader.position += length;
+    cairo::STATUS_SUCCESS
+}
@@ -1,9 +1,11 @@
 mod color;
 mod font;
+mod image;
 mod render_context;
 mod text;
 
 pub use color::{Color, ColorParseError};
 pub use font::{FontDescription, FontFamily, FontStretch, FontStyle, FontWeight};
+pub use image::Image;
 pub use render_context::{CacheKey, RenderContext, RenderError, RenderOp};
 pub use text::{HorizontalAlign, Text, VerticalAlign};
@@ -32,11 +32,16 @@ pub struct RenderContext {
     size: PhysicalSize,
     pixmap: xproto::Pixmap,
     gc: xproto::Gcontext,
-    pictformat: render::Pictformat,
+    picture: render::Picture,
     cairo: *mut cairo::cairo_t,
     cairo_surface: *mut cairo::cairo_surface_t,
     pango: *mut pango::PangoContext,
     cached_render_ops: HashMap<CacheKey, (Rc<dyn CacheDep>, RenderOp)>,
+    window_pictures: HashMap<xproto::Window, WindowPicture>,
+    // The revisions of windows that could not be composited. They are not
+    // tried again until the revision is changed by a damage or the window is
+    // configured.
+    failed_windows: HashMap<xproto::Window, usize>,
 }
 
 impl RenderContext {
@@ -77,6 +82,16 @@ impl RenderContext {
         let pictformat = get_pictformat_from_visual(connection.as_ref(), visual.visual_id)?
             .ok_or(RenderError::PictFormatNotFound)?;
 
+        let picture = connection.generate_id()?;
+        connection
+            .render_create_picture(
+                picture,
+                pixmap,
+                pictformat,
+                &render::CreatePictureAux::new(),
+            )?
+            .check()?;
+
         let cairo_surface = unsafe {
             let visual = visual.serialize();
 
@@ -98,11 +113,13 @@ impl RenderContext {
             size,
             pixmap,
             gc,
-            pictformat,
+            picture,
             cairo_surface,
             cairo,
             pango,
             cached_render_ops: HashMap::new(),
+            window_pictures: HashMap::new(),
+            failed_windows: HashMap::new(),
         })
     }
 
@@ -113,6 +130,8 @@ impl RenderContext {
             cairo::cairo_surface_flush(self.cairo_surface);
         }
 
+        self.release_unused_window_pictures()?;
+
         self.connection
             .copy_area(
                 self.pixmap,
@@ -155,8 +174,15 @@ impl RenderContext {
                 RenderOp::Image(image, bounds, depth) => {
                     self.image(image.as_slice(), bounds, depth);
                 }
-                RenderOp::CompositeWindow(window, bounds) => {
-                    self.composite_window(window, bounds)?;
+                RenderOp::CompositeWindow(window, bounds, revision) => {
+                    // Flush drawings by cairo before compositing on the same pixmap.
+                    unsafe {
+                        cairo::cairo_surface_flush(self.cairo_surface);
+                    }
+                    self.composite_window(window, bounds, revision)?;
+                    unsafe {
+                        cairo::cairo_surface_mark_dirty(self.cairo_surface);
+                    }
                 }
                 RenderOp::Action(action) => {
                     current = action(self.connection.as_ref(), self.screen_num, self.window)?;
@@ -317,6 +343,22 @@ impl RenderContext {
                 text.content.len() as i32,
             );
 
+            if !text.highlights.is_empty() {
+                let attrs = pango::pango_attr_list_new();
+                for range in &text.highlights {
+                    for attr in [
+                        pango::pango_attr_underline_new(pango::PANGO_UNDERLINE_SINGLE),
+                        pango::pango_attr_weight_new(pango::PANGO_WEIGHT_BOLD),
+                    ] {
+                        (*attr).start_index = range.start as c_uint;
+                        (*attr).end_index = range.end as c_uint;
+                        pango::pango_attr_list_insert(attrs, attr);
+                    }
+                }
+                pango::pango_layout_set_attributes(layout, attrs);
+                pango::pango_attr_list_unref(attrs);
+            }
+
             layout
         };
 
@@ -368,62 +410,90 @@ impl RenderContext {
         }
     }
 
-    fn composite_window(&self, window: xproto::Window, bounds: Rect) -> Result<(), RenderError> {
-        let pixmap = self.connection.generate_id()?;
+    // Returns true if the window has failed to be composited.
+    pub fn invalidate_window(&mut self, window: xproto::Window) -> bool {
+        self.failed_windows.remove(&window).is_some()
+    }
 
-        if let Err(error) = self
-            .connection
-            .composite_name_window_pixmap(window, pixmap)?
-            .check()
-        {
-            // Window is probably hidden.
-            log::warn!("composite_window failure: {}", error);
+    fn composite_window(
+        &mut self,
+        window: xproto::Window,
+        bounds: Rect,
+        revision: usize,
+    ) -> Result<(), RenderError> {
+        if self.failed_windows.get(&window) == Some(&revision) {
             return Ok(());
         }
 
-        let src_picture = self.connection.generate_id()?;
-        let dest_picture = self.connection.generate_id()?;
-
-        let visual = self
-            .connection
-            .get_window_attributes(window)?
-            .reply()?
-            .visual;
-        let pictformat = get_pictformat_from_visual(self.connection.as_ref(), visual)?
-            .ok_or(RenderError::PictFormatNotFound)?;
-
-        {
-            let values = render::CreatePictureAux::new();
-            self.connection
-                .render_create_picture(src_picture, pixmap, pictformat, &values)?
-                .check()?;
-            self.connection
-                .render_create_picture(dest_picture, self.pixmap, self.pictformat, &values)?
-                .check()?;
-        }
-
-        self.connection
-            .render_composite(
-                render::PictOp::OVER,
-                src_picture,
-                x11rb::NONE,
-                dest_picture,
-                0,
-                0,
-                0,
-                0,
-                bounds.x as i16,
-                bounds.y as i16,
-                bounds.width as u16,
-                bounds.height as u16,
-            )?
-            .check()?;
+        let picture = match self.window_pictures.get_mut(&window) {
+            Some(window_picture) if window_picture.revision == revision => {
+                window_picture.is_used = true;
+                window_picture.picture
+            }
+            _ => {
+                let pictformat = match self.window_pictures.remove(&window) {
+                    Some(window_picture) => {
+                        window_picture.release(self.connection.as_ref())?;
+                        window_picture.pictformat
+                    }
+                    None => {
+                        let visual = self
+                            .connection
+                            .get_window_attributes(window)?
+                            .reply()?
+                            .visual;
+                        get_pictformat_from_visual(self.connection.as_ref(), visual)?
+                            .ok_or(RenderError::PictFormatNotFound)?
+                    }
+                };
+                match WindowPicture::new(self.connection.as_ref(), window, pictformat, revision)? {
+                    Some(window_picture) => {
+                        let picture = window_picture.picture;
+                        self.window_pictures.insert(window, window_picture);
+                        self.failed_windows.remove(&window);
+                        picture
+                    }
+                    None => {
+                        self.failed_windows.insert(window, revision);
+                        return Ok(());
+                    }
+                }
+            }
+        };
 
-        self.connection.render_free_picture(dest_picture)?.check()?;
-        self.connection.render_free_picture(src_picture)?.check()?;
+        self.connection.render_composite(
+            render::PictOp::OVER,
+            picture,
+            x11rb::NONE,
+            self.picture,
+            0,
+            0,
+            0,
+            0,
+            bounds.x as i16,
+            bounds.y as i16,
+            bounds.width as u16,
+            bounds.height as u16,
+        )?;
 
         Ok(())
     }
+
+    fn release_unused_window_pictures(&mut self) -> Result<(), RenderError> {
+        let mut result = Ok(());
+        self.window_pictures.retain(|_, window_picture| {
+            if window_picture.is_used {
+                window_picture.is_used = false;
+                true
+            } else {
+                if let Err(error) = window_picture.release(self.connection.as_ref()) {
+                    result = Err(error.into());
+                }
+                false
+            }
+        });
+        result
+    }
 }
 
 impl Drop for RenderContext {
@@ -432,6 +502,10 @@ impl Drop for RenderContext {
             gobject::g_object_unref(self.pango.cast());
             cairo::cairo_destroy(self.cairo);
             cairo::cairo_surface_destroy(self.cairo_surface);
+            for (_, window_picture) in self.window_pictures.drain() {
+                window_picture.release(self.connection.as_ref()).ok();
+            }
+            self.connection.render_free_picture(self.picture).ok();
             self.connection.free_gc(self.gc).ok();
             self.connection.free_pixmap(self.pixmap).ok();
         }
@@ -447,7 +521,7 @@ pub enum RenderOp {
     Stroke(Color, Rect, f64),
     Text(Color, Rect, Text),
     Image(Rc<Vec<u8>>, Rect, u8),
-    CompositeWindow(xproto::Window, Rect),
+    CompositeWindow(xproto::Window, Rect, usize),
     Action(Rc<dyn Fn(&XCBConnection, usize, xproto::Window) -> Result<RenderOp, ReplyError>>),
     Memoize(
         CacheKey,
@@ -534,6 +608,58 @@ impl fmt::Display for RenderError {
 
 impl error::Error for RenderError {}
 
+struct WindowPicture {
+    pixmap: xproto::Pixmap,
+    picture: render::Picture,
+    pictformat: render::Pictformat,
+    revision: usize,
+    is_used: bool,
+}
+
+impl WindowPicture {
+    fn new(
+        connection: &XCBConnection,
+        window: xproto::Window,
+        pictformat: render::Pictformat,
+        revision: usize,
+    ) -> Result<Option<Self>, RenderError> {
+        let pixmap = connection.generate_id()?;
+
+        if let Err(error) = connection
+            .composite_name_window_pixmap(window, pixmap)?
+            .check()
+        {
+            // Window is probably hidden.
+            log::warn!("composite_window failure: {}", error);
+            return Ok(None);
+        }
+
+        let picture = connection.generate_id()?;
+        connection
+            .render_create_picture(
+                picture,
+                pixmap,
+                pictformat,
+                &render::CreatePictureAux::new(),
+            )?
+            .check()?;
+
+        Ok(Some(Self {
+            pixmap,
+            picture,
+            pictformat,
+            revision,
+            is_used: true,
+        }))
+    }
+
+    fn release(&self, connection: &XCBConnection) -> Result<(), ConnectionError> {
+        connection.render_free_picture(self.picture)?;
+        connection.free_pixmap(self.pixmap)?;
+        Ok(())
+    }
+}
+
 pub trait CacheDep {
     fn same(&self, other: &dyn Any) -> bool;
 
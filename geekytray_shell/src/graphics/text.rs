@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::ops::Range;
 
 use super::font::FontDescription;
 
@@ -9,6 +10,7 @@ pub struct Text {
     pub size: f64,
     pub horizontal_align: HorizontalAlign,
     pub vertical_align: VerticalAlign,
+    pub highlights: Vec<Range<usize>>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -1,3 +1,4 @@
+pub mod dbus;
 pub mod event;
 pub mod geometrics;
 pub mod graphics;
@@ -55,6 +55,7 @@ impl<Widget: self::Widget> Window<Widget> {
                 | xproto::EventMask::KEY_PRESS
                 | xproto::EventMask::KEY_RELEASE
                 | xproto::EventMask::LEAVE_WINDOW
+                | xproto::EventMask::POINTER_MOTION
                 | xproto::EventMask::PROPERTY_CHANGE
                 | xproto::EventMask::STRUCTURE_NOTIFY;
 
@@ -240,6 +241,7 @@ impl<Widget: self::Widget> Window<Widget> {
                 Ok(())
             }
             Event::Signal(_) => Ok(()),
+            Event::DBusMessage(_) => Ok(()),
             Event::NextTick => {
                 if self.should_layout {
                     self.recalculate_layout()?;
@@ -272,6 +274,12 @@ impl<Widget: self::Widget> Window<Widget> {
                 }
             }
             ConfigureNotify(event) => {
+                if let Some(render_context) = &mut self.render_context {
+                    // The window may be composited now.
+                    if render_context.invalidate_window(event.window) {
+                        self.should_redraw = true;
+                    }
+                }
                 if event.window == event.event && event.window == self.window {
                     self.position = PhysicalPoint {
                         x: event.x as i32,
@@ -42,6 +42,20 @@ impl State {
         Keysym::from(unsafe { ffi::xkb_state_key_get_one_sym(self.state, keycode) })
     }
 
+    pub fn get_utf8(&self, keycode: u32) -> String {
+        let mut buffer = [0u8; 64];
+        let length = unsafe {
+            ffi::xkb_state_key_get_utf8(
+                self.state,
+                keycode,
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+            )
+        };
+        let length = (length.max(0) as usize).min(buffer.len() - 1);
+        String::from_utf8_lossy(&buffer[..length]).into_owned()
+    }
+
     pub fn get_modifiers(&self) -> Modifiers {
         [
             (self.mod_indices.control, Modifiers::CONTROL),